@@ -0,0 +1,182 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use argon2::password_hash::rand_core::OsRng;
+use argon2::password_hash::{PasswordHash, PasswordHasher, PasswordVerifier, SaltString};
+use argon2::Argon2;
+use log::warn;
+use sha2::{Digest, Sha256};
+
+pub const SESSION_COOKIE: &str = "m3u_session";
+
+/// Hashes a password for a `user.<name>` line of `config.txt`.
+pub fn hash_password(password: &str) -> Result<String, String> {
+    let salt = SaltString::generate(&mut OsRng);
+    Argon2::default()
+        .hash_password(password.as_bytes(), &salt)
+        .map(|hash| hash.to_string())
+        .map_err(|e| e.to_string())
+}
+
+fn verify_password(password: &str, hash: &str) -> bool {
+    match PasswordHash::new(hash) {
+        Ok(hash) => Argon2::default().verify_password(password.as_bytes(), &hash).is_ok(),
+        Err(_) => false,
+    }
+}
+
+fn digest(value: &str) -> [u8; 32] {
+    Sha256::digest(value.as_bytes()).into()
+}
+
+struct Session {
+    user: String,
+    expires: Instant,
+}
+
+/// Optional login for the web interface: accounts from `user.<name>` lines holding password
+/// hashes, cookie sessions kept in memory, and an `api_token` for scripts.
+pub struct Auth {
+    users: HashMap<String, String>,
+    /// Checked against when the user name is unknown, so that takes as long to reject as a
+    /// wrong password.
+    dummy_hash: String,
+    /// Stored hashed so comparing it does not leak how much of a guess matched.
+    api_token: Option<[u8; 32]>,
+    session_lifetime: Duration,
+    sessions: Mutex<HashMap<String, Session>>,
+}
+
+impl Auth {
+    pub fn from_config(config: &HashMap<String, String>) -> Self {
+        let mut users = HashMap::new();
+        for (key, value) in config {
+            let Some(name) = key.strip_prefix("user.") else { continue };
+            if PasswordHash::new(value).is_err() {
+                warn!("Ignoring user {}: the value is not a password hash (generate one with --hash-password)", name);
+                continue;
+            }
+            users.insert(name.to_string(), value.clone());
+        }
+        let days = config.get("session_days").and_then(|v| v.parse::<u64>().ok()).unwrap_or(30).max(1);
+
+        Auth {
+            users,
+            dummy_hash: hash_password("").expect("Failed to hash a password"),
+            api_token: config.get("api_token").filter(|token| !token.is_empty()).map(|token| digest(token)),
+            session_lifetime: Duration::from_secs(days * 24 * 3600),
+            sessions: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        !self.users.is_empty() || self.api_token.is_some()
+    }
+
+    pub fn session_lifetime(&self) -> Duration {
+        self.session_lifetime
+    }
+
+    /// Checks the credentials and starts a session, returning its cookie value.
+    pub fn login(&self, user: &str, password: &str) -> Option<String> {
+        let valid = match self.users.get(user) {
+            Some(hash) => verify_password(password, hash),
+            None => {
+                verify_password(password, &self.dummy_hash);
+                false
+            }
+        };
+        if !valid {
+            return None;
+        }
+
+        let token = crate::random::random_hex(32);
+
+        let now = Instant::now();
+        let mut sessions = self.sessions.lock().unwrap();
+        sessions.retain(|_, session| session.expires > now);
+        sessions.insert(token.clone(), Session {
+            user: user.to_string(),
+            expires: now + self.session_lifetime,
+        });
+        Some(token)
+    }
+
+    pub fn logout(&self, token: &str) {
+        self.sessions.lock().unwrap().remove(token);
+    }
+
+    /// The user a session cookie belongs to, if it is still valid.
+    pub fn session_user(&self, token: &str) -> Option<String> {
+        let sessions = self.sessions.lock().unwrap();
+        sessions.get(token)
+            .filter(|session| session.expires > Instant::now())
+            .map(|session| session.user.clone())
+    }
+
+    pub fn is_api_token(&self, token: &str) -> bool {
+        self.api_token.is_some_and(|expected| expected == digest(token))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn auth(settings: &[(&str, &str)]) -> Auth {
+        let mut config: HashMap<String, String> = settings.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect();
+        config.insert("user.alice".to_string(), hash_password("correct horse").unwrap());
+        config.insert("user.mallory".to_string(), "plain text".to_string());
+        Auth::from_config(&config)
+    }
+
+    #[test]
+    fn only_the_right_password_starts_a_session() {
+        let auth = auth(&[]);
+        assert!(auth.is_enabled());
+        assert_eq!(auth.login("alice", "wrong"), None);
+        assert_eq!(auth.login("alice", ""), None);
+        // Unknown users are checked against the dummy hash and refused like wrong passwords
+        assert_eq!(auth.login("bob", "correct horse"), None);
+        assert_eq!(auth.login("bob", ""), None);
+        // Values that are not hashes are not accounts
+        assert_eq!(auth.login("mallory", "plain text"), None);
+
+        let token = auth.login("alice", "correct horse").unwrap();
+        assert_eq!(token.len(), 64);
+        assert_eq!(auth.session_user(&token).as_deref(), Some("alice"));
+        assert_ne!(auth.login("alice", "correct horse").unwrap(), token);
+        assert_eq!(auth.session_user("not a session"), None);
+
+        auth.logout(&token);
+        assert_eq!(auth.session_user(&token), None);
+    }
+
+    #[test]
+    fn sessions_expire_after_their_lifetime() {
+        let mut auth = auth(&[("session_days", "7")]);
+        assert_eq!(auth.session_lifetime(), Duration::from_secs(7 * 24 * 3600));
+        auth.session_lifetime = Duration::from_millis(100);
+
+        let expiring = auth.login("alice", "correct horse").unwrap();
+        assert!(auth.session_user(&expiring).is_some());
+        std::thread::sleep(Duration::from_millis(150));
+        assert_eq!(auth.session_user(&expiring), None);
+        // Expired sessions are dropped when the next one starts
+        auth.login("alice", "correct horse").unwrap();
+        assert_eq!(auth.sessions.lock().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn api_tokens_must_match_exactly() {
+        let auth = Auth::from_config(&HashMap::from([("api_token".to_string(), "s3cret-token".to_string())]));
+        assert!(auth.is_enabled());
+        assert!(auth.is_api_token("s3cret-token"));
+        assert!(!auth.is_api_token("s3cret-toke"));
+        assert!(!auth.is_api_token(""));
+
+        let open = Auth::from_config(&HashMap::from([("api_token".to_string(), String::new())]));
+        assert!(!open.is_enabled());
+        assert!(!open.is_api_token(""));
+    }
+}
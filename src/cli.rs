@@ -0,0 +1,120 @@
+use std::collections::HashMap;
+
+/// Flags that override the `config.txt` setting named next to them.
+const SETTING_FLAGS: [(&str, &str, &str); 4] = [
+    ("--bind", "bind_address", "Address to listen on, e.g. 0.0.0.0 for the whole network (default 127.0.0.1)"),
+    ("--port", "port", "Port to listen on (default 8080)"),
+    ("--tls-cert", "tls_cert_path", "PEM certificate chain; serves HTTPS together with --tls-key"),
+    ("--tls-key", "tls_key_path", "PEM private key for --tls-cert"),
+];
+
+pub struct Args {
+    pub config_path: String,
+    pub help: bool,
+    /// Print the hash of a password read from standard input, for a `user.<name>` setting.
+    pub hash_password: bool,
+    /// Settings given on the command line, applied on top of the config file.
+    pub overrides: HashMap<String, String>,
+}
+
+pub fn usage() -> String {
+    let mut text = String::from("Usage: m3u_browser [OPTIONS]\n\nOptions:\n");
+    text.push_str("  --config <PATH>      Settings file to read (default config.txt)\n");
+    for (flag, _, description) in SETTING_FLAGS {
+        text.push_str(&format!("  {:<20} {}\n", format!("{} <VALUE>", flag), description));
+    }
+    text.push_str("  --hash-password      Read a password from standard input and print its hash for config.txt\n");
+    text.push_str("  -h, --help           Print this help\n");
+    text
+}
+
+/// Parses `--flag value` and `--flag=value` arguments, without the program name.
+pub fn parse_args(args: impl IntoIterator<Item = String>) -> Result<Args, String> {
+    let mut parsed = Args {
+        config_path: "config.txt".to_string(),
+        help: false,
+        hash_password: false,
+        overrides: HashMap::new(),
+    };
+    let mut args = args.into_iter();
+
+    while let Some(arg) = args.next() {
+        if arg == "-h" || arg == "--help" {
+            parsed.help = true;
+            continue;
+        }
+        if arg == "--hash-password" {
+            parsed.hash_password = true;
+            continue;
+        }
+        let (flag, inline_value) = match arg.split_once('=') {
+            Some((flag, value)) => (flag.to_string(), Some(value.to_string())),
+            None => (arg, None),
+        };
+        let key = if flag == "--config" {
+            None
+        } else {
+            match SETTING_FLAGS.iter().find(|(name, _, _)| *name == flag) {
+                Some((_, key, _)) => Some(*key),
+                None => return Err(format!("Unknown option: {}", flag)),
+            }
+        };
+        let value = match inline_value.or_else(|| args.next()) {
+            Some(value) => value,
+            None => return Err(format!("{} needs a value", flag)),
+        };
+        match key {
+            Some(key) => {
+                parsed.overrides.insert(key.to_string(), value);
+            }
+            None => parsed.config_path = value,
+        }
+    }
+    Ok(parsed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Result<Args, String> {
+        parse_args(args.iter().map(|arg| arg.to_string()))
+    }
+
+    #[test]
+    fn flags_take_separate_or_inline_values() {
+        let args = parse(&["--port", "9000", "--bind=0.0.0.0", "--config", "/etc/m3u.txt", "--tls-cert=a=b.pem"]).unwrap();
+        assert_eq!(args.config_path, "/etc/m3u.txt");
+        assert_eq!(args.overrides, HashMap::from([
+            ("port".to_string(), "9000".to_string()),
+            ("bind_address".to_string(), "0.0.0.0".to_string()),
+            ("tls_cert_path".to_string(), "a=b.pem".to_string()),
+        ]));
+        assert!(!args.help && !args.hash_password);
+
+        let defaults = parse(&[]).unwrap();
+        assert_eq!(defaults.config_path, "config.txt");
+        assert!(defaults.overrides.is_empty());
+        assert!(parse(&["-h"]).unwrap().help);
+        assert!(parse(&["--hash-password"]).unwrap().hash_password);
+        // The last value given wins
+        assert_eq!(parse(&["--port", "1", "--port=2"]).unwrap().overrides["port"], "2");
+    }
+
+    #[test]
+    fn unknown_flags_and_missing_values_are_errors() {
+        assert_eq!(parse(&["--verbose"]).err().unwrap(), "Unknown option: --verbose");
+        assert_eq!(parse(&["config.txt"]).err().unwrap(), "Unknown option: config.txt");
+        assert_eq!(parse(&["--port"]).err().unwrap(), "--port needs a value");
+        assert_eq!(parse(&["--config"]).err().unwrap(), "--config needs a value");
+    }
+
+    #[test]
+    fn usage_lists_every_flag() {
+        let usage = usage();
+        for (flag, _, _) in SETTING_FLAGS {
+            assert!(usage.contains(flag), "{}", flag);
+        }
+        assert!(usage.contains("--config") && usage.contains("--hash-password"));
+    }
+}
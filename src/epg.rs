@@ -0,0 +1,321 @@
+use std::collections::{HashMap, HashSet};
+use std::fs::File;
+use std::io::{BufRead, BufReader, Read};
+use std::sync::RwLock;
+use std::time::Duration;
+use chrono::{DateTime, Local, NaiveDateTime, TimeZone, Utc};
+use flate2::read::GzDecoder;
+use quick_xml::events::{BytesStart, Event};
+use quick_xml::Reader;
+use serde::Serialize;
+
+#[derive(Serialize, Clone, Debug)]
+pub struct Programme {
+    /// Unix timestamps of the scheduled start and end.
+    pub start: i64,
+    pub stop: i64,
+    pub title: String,
+    pub description: Option<String>,
+}
+
+impl Programme {
+    pub fn is_airing(&self, now: i64) -> bool {
+        self.start <= now && now < self.stop
+    }
+}
+
+pub fn now() -> i64 {
+    Utc::now().timestamp()
+}
+
+/// Formats a timestamp in the server's local time zone.
+pub fn format_time(timestamp: i64) -> String {
+    match Local.timestamp_opt(timestamp, 0).single() {
+        Some(time) => time.format("%a %d %b %H:%M").to_string(),
+        None => timestamp.to_string(),
+    }
+}
+
+/// Formats just the time of day, for the end of a programme.
+pub fn format_clock(timestamp: i64) -> String {
+    match Local.timestamp_opt(timestamp, 0).single() {
+        Some(time) => time.format("%H:%M").to_string(),
+        None => timestamp.to_string(),
+    }
+}
+
+/// Parses XMLTV times such as `20240131203000 +0100`. Times without an offset are taken as UTC.
+fn parse_time(value: &str) -> Option<i64> {
+    let value = value.trim();
+    if let Ok(time) = DateTime::parse_from_str(value, "%Y%m%d%H%M%S %z") {
+        return Some(time.timestamp());
+    }
+    let digits = value.get(..14)?;
+    NaiveDateTime::parse_from_str(digits, "%Y%m%d%H%M%S").ok().map(|time| time.and_utc().timestamp())
+}
+
+/// Programme listings from an XMLTV guide, keyed by the channel's `tvg-id`.
+pub struct Guide {
+    source: Option<String>,
+    programmes: RwLock<HashMap<String, Vec<Programme>>>,
+}
+
+impl Guide {
+    pub fn from_config(config: &HashMap<String, String>) -> Self {
+        Guide {
+            source: config.get("epg_path").cloned(),
+            programmes: RwLock::new(HashMap::new()),
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.source.is_some()
+    }
+
+    /// Reads the guide from `epg_path` (a file or an http(s) URL, optionally gzipped), keeping
+    /// only programmes of `tvg_ids` that have not ended yet. Returns the number of programmes.
+    pub async fn load(&self, tvg_ids: HashSet<String>) -> Result<usize, String> {
+        let source = self.source.clone().ok_or("epg_path is not set")?;
+        let since = now();
+        let programmes = tokio::task::spawn_blocking(move || {
+            let mut input = BufReader::new(open_source(&source)?);
+            let is_gzip = input.fill_buf().map_err(|e| format!("Failed to read {}: {}", source, e))?.starts_with(&[0x1f, 0x8b]);
+            if is_gzip {
+                parse_xmltv(BufReader::new(GzDecoder::new(input)), &tvg_ids, since)
+            } else {
+                parse_xmltv(input, &tvg_ids, since)
+            }
+        }).await.map_err(|e| e.to_string())??;
+
+        let count = programmes.values().map(Vec::len).sum();
+        *self.programmes.write().unwrap() = programmes;
+        Ok(count)
+    }
+
+    /// Programmes of a channel that are airing or still to come.
+    pub fn upcoming(&self, tvg_id: &str) -> Vec<Programme> {
+        let now = now();
+        self.programmes.read().unwrap()
+            .get(tvg_id)
+            .map(|programmes| programmes.iter().filter(|p| p.stop > now).cloned().collect())
+            .unwrap_or_default()
+    }
+
+    pub fn programme(&self, tvg_id: &str, start: i64) -> Option<Programme> {
+        self.programmes.read().unwrap().get(tvg_id)?.iter().find(|p| p.start == start).cloned()
+    }
+}
+
+/// Opens a guide file or starts downloading a guide URL, for reading on a blocking thread.
+fn open_source(source: &str) -> Result<Box<dyn Read>, String> {
+    if source.starts_with("http://") || source.starts_with("https://") {
+        let response = reqwest::blocking::Client::builder()
+            .timeout(Duration::from_secs(120))
+            .build()
+            .and_then(|client| client.get(source).send())
+            .and_then(|r| r.error_for_status())
+            .map_err(|e| e.to_string())?;
+        Ok(Box::new(response))
+    } else {
+        let file = File::open(source).map_err(|e| format!("Failed to read {}: {}", source, e))?;
+        Ok(Box::new(file))
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Field {
+    Title,
+    Description,
+}
+
+fn attribute<R>(reader: &Reader<R>, element: &BytesStart, name: &str) -> Option<String> {
+    element.try_get_attribute(name).ok()??
+        .decode_and_unescape_value(reader.decoder())
+        .ok()
+        .map(|value| value.into_owned())
+}
+
+/// Streams through an XMLTV document, which can be hundreds of megabytes, collecting the
+/// programmes of the wanted channels that end after `since`.
+fn parse_xmltv<R: BufRead>(input: R, wanted: &HashSet<String>, since: i64) -> Result<HashMap<String, Vec<Programme>>, String> {
+    let mut reader = Reader::from_reader(input);
+    let mut programmes: HashMap<String, Vec<Programme>> = HashMap::new();
+    let mut buffer = Vec::new();
+    let mut current: Option<(String, Programme)> = None;
+    let mut field: Option<Field> = None;
+    let mut text = String::new();
+
+    loop {
+        match reader.read_event_into(&mut buffer) {
+            Ok(Event::Start(element)) => match element.name().as_ref() {
+                b"programme" => {
+                    let channel = attribute(&reader, &element, "channel");
+                    let start = attribute(&reader, &element, "start").and_then(|v| parse_time(&v));
+                    let stop = attribute(&reader, &element, "stop").and_then(|v| parse_time(&v));
+                    current = match (channel, start, stop) {
+                        (Some(channel), Some(start), Some(stop)) if stop > since && wanted.contains(&channel) => Some((channel, Programme {
+                            start,
+                            stop,
+                            title: String::new(),
+                            description: None,
+                        })),
+                        _ => None,
+                    };
+                }
+                b"title" if current.is_some() => {
+                    field = Some(Field::Title);
+                    text.clear();
+                }
+                b"desc" if current.is_some() => {
+                    field = Some(Field::Description);
+                    text.clear();
+                }
+                _ => {}
+            },
+            Ok(Event::Text(content)) if field.is_some() => {
+                text.push_str(&content.decode().map_err(|e| e.to_string())?);
+            }
+            Ok(Event::CData(content)) if field.is_some() => {
+                text.push_str(&content.decode().map_err(|e| e.to_string())?);
+            }
+            Ok(Event::GeneralRef(reference)) if field.is_some() => {
+                if let Ok(Some(c)) = reference.resolve_char_ref() {
+                    text.push(c);
+                } else if let Ok(name) = reference.decode() {
+                    if let Some(value) = quick_xml::escape::resolve_predefined_entity(&name) {
+                        text.push_str(value);
+                    }
+                }
+            }
+            Ok(Event::End(element)) => match element.name().as_ref() {
+                b"programme" => {
+                    if let Some((channel, programme)) = current.take() {
+                        programmes.entry(channel).or_default().push(programme);
+                    }
+                    field = None;
+                }
+                b"title" | b"desc" => {
+                    if let (Some(field), Some((_, programme))) = (field.take(), current.as_mut()) {
+                        let value = text.trim().to_string();
+                        // Guides often repeat titles in several languages; keep the first one
+                        match field {
+                            Field::Title if programme.title.is_empty() => programme.title = value,
+                            Field::Description if programme.description.is_none() && !value.is_empty() => programme.description = Some(value),
+                            _ => {}
+                        }
+                    }
+                }
+                _ => {}
+            },
+            Ok(Event::Eof) => break,
+            Err(e) => return Err(format!("Invalid XMLTV at byte {}: {}", reader.buffer_position(), e)),
+            _ => {}
+        }
+        buffer.clear();
+    }
+
+    for list in programmes.values_mut() {
+        list.sort_by_key(|p| p.start);
+        list.dedup_by_key(|p| p.start);
+    }
+    Ok(programmes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+    use flate2::write::GzEncoder;
+    use flate2::Compression;
+
+    const GUIDE: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<!DOCTYPE tv SYSTEM "xmltv.dtd">
+<tv generator-info-name="test">
+  <channel id="one.uk"><display-name>One</display-name></channel>
+  <programme start="20990101180000 +0000" stop="20990101190000 +0000" channel="one.uk">
+    <title lang="en">News &amp; Weather</title>
+    <title lang="cy">Newyddion</title>
+    <desc lang="en"><![CDATA[Headlines <live>]]></desc>
+  </programme>
+  <programme start="20990101200000 +0100" stop="20990101210000 +0100" channel="one.uk">
+    <title>Quiz &#8211; Final</title>
+    <desc></desc>
+  </programme>
+  <programme start="20990101170000" stop="20990101180000" channel="one.uk">
+    <title>Earlier</title>
+  </programme>
+  <programme start="20990101180000 +0000" stop="20990101190000 +0000" channel="other.de">
+    <title>Not wanted</title>
+  </programme>
+  <programme start="20000101180000 +0000" stop="20000101190000 +0000" channel="one.uk">
+    <title>Long over</title>
+  </programme>
+  <programme start="tomorrow" stop="20990101190000 +0000" channel="one.uk">
+    <title>Bad start</title>
+  </programme>
+</tv>
+"#;
+
+    fn at(value: &str) -> i64 {
+        DateTime::parse_from_rfc3339(value).unwrap().timestamp()
+    }
+
+    #[test]
+    fn times_honour_their_offset() {
+        assert_eq!(parse_time("20240131203000 +0100"), Some(at("2024-01-31T19:30:00Z")));
+        assert_eq!(parse_time("20240131203000 -0530"), Some(at("2024-02-01T02:00:00Z")));
+        assert_eq!(parse_time(" 20240131203000 +0000 "), Some(at("2024-01-31T20:30:00Z")));
+        // Without an offset the time is UTC
+        assert_eq!(parse_time("20240131203000"), Some(at("2024-01-31T20:30:00Z")));
+        assert_eq!(parse_time("20240131203000 CET"), Some(at("2024-01-31T20:30:00Z")));
+        assert_eq!(parse_time("2024013120"), None);
+        assert_eq!(parse_time("tomorrow evening"), None);
+    }
+
+    #[test]
+    fn only_wanted_programmes_that_have_not_ended_are_kept() {
+        let wanted = HashSet::from(["one.uk".to_string()]);
+        let programmes = parse_xmltv(GUIDE.as_bytes(), &wanted, at("2024-01-01T00:00:00Z")).unwrap();
+        assert_eq!(programmes.keys().collect::<Vec<_>>(), ["one.uk"]);
+
+        let one = &programmes["one.uk"];
+        let titles: Vec<&str> = one.iter().map(|p| p.title.as_str()).collect();
+        assert_eq!(titles, ["Earlier", "News & Weather", "Quiz – Final"]);
+        assert_eq!(one[1].description.as_deref(), Some("Headlines <live>"));
+        assert!(one[1].is_airing(at("2099-01-01T18:30:00Z")));
+        assert!(!one[1].is_airing(at("2099-01-01T19:00:00Z")));
+        assert_eq!(one[2].start, at("2099-01-01T19:00:00Z"));
+        assert_eq!(one[2].description, None);
+    }
+
+    #[test]
+    fn invalid_guides_are_reported() {
+        let wanted = HashSet::from(["one.uk".to_string()]);
+        assert!(parse_xmltv("<tv><programme></tv>".as_bytes(), &wanted, 0).is_err());
+        assert!(parse_xmltv("".as_bytes(), &wanted, 0).unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn gzipped_guides_are_loaded() {
+        let dir = std::env::temp_dir().join(format!("m3u_browser_epg_test_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let plain = dir.join("guide.xml");
+        std::fs::write(&plain, GUIDE).unwrap();
+        let gzipped = dir.join("guide.xml.gz");
+        let mut encoder = GzEncoder::new(File::create(&gzipped).unwrap(), Compression::default());
+        encoder.write_all(GUIDE.as_bytes()).unwrap();
+        encoder.finish().unwrap();
+
+        for path in [&plain, &gzipped] {
+            let guide = Guide::from_config(&HashMap::from([("epg_path".to_string(), path.display().to_string())]));
+            assert_eq!(guide.load(HashSet::from(["one.uk".to_string()])).await, Ok(3));
+            assert_eq!(guide.upcoming("one.uk").len(), 3);
+            assert_eq!(guide.programme("one.uk", at("2099-01-01T19:00:00Z")).unwrap().title, "Quiz – Final");
+            assert!(guide.upcoming("other.de").is_empty());
+        }
+
+        let missing = Guide::from_config(&HashMap::from([("epg_path".to_string(), dir.join("missing.xml").display().to_string())]));
+        assert!(missing.load(HashSet::new()).await.is_err());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,232 @@
+use std::collections::HashMap;
+use std::sync::RwLock;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use futures::stream::{self, StreamExt};
+use serde::Serialize;
+use log::info;
+
+/// How much of an HLS manifest is read to see whether it is one.
+const MAX_MANIFEST_BYTES: usize = 64 * 1024;
+
+#[derive(Serialize, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum HealthStatus {
+    Online,
+    Offline,
+}
+
+#[derive(Serialize, Clone, Debug)]
+pub struct HealthRecord {
+    pub status: HealthStatus,
+    pub http_status: Option<u16>,
+    pub latency_ms: u64,
+    pub checked_at: u64,
+    pub error: Option<String>,
+}
+
+impl HealthRecord {
+    /// Short human-readable summary used as the badge tooltip.
+    pub fn summary(&self) -> String {
+        let age = unix_now().saturating_sub(self.checked_at);
+        let detail = match (&self.error, self.http_status) {
+            (Some(error), _) => error.clone(),
+            (None, Some(code)) => format!("HTTP {}", code),
+            (None, None) => "no response".to_string(),
+        };
+        format!("{}, {} ms, checked {}s ago", detail, self.latency_ms, age)
+    }
+}
+
+/// Probes stream URLs and remembers the last result per channel ID.
+pub struct HealthChecker {
+    client: reqwest::Client,
+    concurrency: usize,
+    records: RwLock<HashMap<String, HealthRecord>>,
+}
+
+pub fn unix_now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0)
+}
+
+impl HealthChecker {
+    pub fn new(timeout: Duration, concurrency: usize) -> Self {
+        let client = reqwest::Client::builder()
+            .timeout(timeout)
+            .build()
+            .expect("Failed to build HTTP client");
+        HealthChecker {
+            client,
+            concurrency: concurrency.max(1),
+            records: RwLock::new(HashMap::new()),
+        }
+    }
+
+    pub fn from_config(config: &HashMap<String, String>) -> Self {
+        let timeout = config.get("health_check_timeout_secs").and_then(|v| v.parse().ok()).unwrap_or(10);
+        let concurrency = config.get("health_check_concurrency").and_then(|v| v.parse().ok()).unwrap_or(16);
+        HealthChecker::new(Duration::from_secs(timeout), concurrency)
+    }
+
+    pub fn get(&self, id: &str) -> Option<HealthRecord> {
+        self.records.read().unwrap().get(id).cloned()
+    }
+
+    pub fn is_online(&self, id: &str) -> bool {
+        matches!(self.get(id), Some(record) if record.status == HealthStatus::Online)
+    }
+
+    pub async fn check(&self, id: &str, url: &str) -> HealthRecord {
+        let record = probe(&self.client, url).await;
+        self.records.write().unwrap().insert(id.to_string(), record.clone());
+        record
+    }
+
+    /// Checks every `(id, url)` pair with at most `concurrency` requests in flight.
+    pub async fn check_all(&self, channels: Vec<(String, String)>) {
+        let total = channels.len();
+        let online = stream::iter(channels)
+            .map(|(id, url)| async move { self.check(&id, &url).await.status })
+            .buffer_unordered(self.concurrency)
+            .filter(|status| futures::future::ready(*status == HealthStatus::Online))
+            .count()
+            .await;
+        info!("Health check finished: {} of {} channels online", online, total);
+    }
+}
+
+async fn probe(client: &reqwest::Client, url: &str) -> HealthRecord {
+    let started = Instant::now();
+    let mut record = HealthRecord {
+        status: HealthStatus::Offline,
+        http_status: None,
+        latency_ms: 0,
+        checked_at: unix_now(),
+        error: None,
+    };
+    if !crate::playlist::is_http_url(url) {
+        record.error = Some("Only http(s) streams can be checked".to_string());
+        return record;
+    }
+    let result = client.get(url).send().await;
+
+    match result {
+        Ok(response) => {
+            record.http_status = Some(response.status().as_u16());
+            let is_hls = crate::hls::is_hls_url(url)
+                || response.headers().get(reqwest::header::CONTENT_TYPE)
+                    .and_then(|v| v.to_str().ok())
+                    .map(|v| v.to_lowercase().contains("mpegurl"))
+                    .unwrap_or(false);
+
+            if !response.status().is_success() {
+                record.error = Some(format!("HTTP {}", response.status().as_u16()));
+            } else if is_hls {
+                // A manifest is only useful if it actually is one
+                match manifest_start(response).await {
+                    Ok(body) if body.trim_ascii_start().starts_with(b"#EXTM3U") => record.status = HealthStatus::Online,
+                    Ok(_) => record.error = Some("Invalid HLS manifest".to_string()),
+                    Err(e) => record.error = Some(e.to_string()),
+                }
+            } else {
+                // Live streams never end, so the response headers are enough
+                record.status = HealthStatus::Online;
+            }
+        }
+        Err(e) => {
+            record.error = Some(if e.is_timeout() { "Timed out".to_string() } else { e.to_string() });
+        }
+    }
+
+    record.latency_ms = started.elapsed().as_millis() as u64;
+    record
+}
+
+/// Reads the body until its first non-blank bytes are known, at most `MAX_MANIFEST_BYTES`.
+async fn manifest_start(response: reqwest::Response) -> Result<Vec<u8>, reqwest::Error> {
+    let mut stream = response.bytes_stream();
+    let mut body = Vec::new();
+    while let Some(chunk) = stream.next().await {
+        body.extend_from_slice(&chunk?);
+        if body.len() >= MAX_MANIFEST_BYTES || body.trim_ascii_start().len() >= "#EXTM3U".len() {
+            break;
+        }
+    }
+    body.truncate(MAX_MANIFEST_BYTES);
+    Ok(body)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+
+    /// Answers every connection on a local port with `response` and returns the base URL.
+    async fn serve(response: &'static str) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            while let Ok((mut socket, _)) = listener.accept().await {
+                let mut request = [0u8; 1024];
+                let _ = socket.read(&mut request).await;
+                let _ = socket.write_all(response.as_bytes()).await;
+            }
+        });
+        format!("http://{}", address)
+    }
+
+    fn checker() -> HealthChecker {
+        HealthChecker::new(Duration::from_secs(5), 4)
+    }
+
+    #[tokio::test]
+    async fn responding_stream_is_online() {
+        let url = serve("HTTP/1.1 200 OK\r\nContent-Type: video/mp2t\r\nContent-Length: 4\r\n\r\nG\0\0\0").await;
+        let record = checker().check("ok", &format!("{}/live.ts", url)).await;
+        assert_eq!(record.status, HealthStatus::Online);
+        assert_eq!(record.http_status, Some(200));
+    }
+
+    #[tokio::test]
+    async fn error_status_and_refused_connection_are_offline() {
+        let url = serve("HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\n\r\n").await;
+        let record = checker().check("missing", &format!("{}/live.ts", url)).await;
+        assert_eq!(record.status, HealthStatus::Offline);
+        assert_eq!(record.error.as_deref(), Some("HTTP 404"));
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let dead = format!("http://{}/live.ts", listener.local_addr().unwrap());
+        drop(listener);
+        let checker = checker();
+        let record = checker.check("dead", &dead).await;
+        assert_eq!(record.status, HealthStatus::Offline);
+        assert_eq!(record.http_status, None);
+        assert!(!checker.is_online("dead"));
+    }
+
+    #[tokio::test]
+    async fn hls_manifest_must_start_with_extm3u() {
+        let url = serve("HTTP/1.1 200 OK\r\nContent-Type: application/vnd.apple.mpegurl\r\nContent-Length: 27\r\n\r\n\n#EXTM3U\n#EXT-X-VERSION:3\n\n").await;
+        assert_eq!(checker().check("hls", &format!("{}/index", url)).await.status, HealthStatus::Online);
+
+        let url = serve("HTTP/1.1 200 OK\r\nContent-Length: 15\r\n\r\n<html></html>\n\n").await;
+        let record = checker().check("html", &format!("{}/index.m3u8", url)).await;
+        assert_eq!(record.status, HealthStatus::Offline);
+        assert_eq!(record.error.as_deref(), Some("Invalid HLS manifest"));
+    }
+
+    #[tokio::test]
+    async fn endless_manifest_is_not_read_to_the_end() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/index.m3u8", listener.local_addr().unwrap());
+        tokio::spawn(async move {
+            let (mut socket, _) = listener.accept().await.unwrap();
+            let mut request = [0u8; 1024];
+            let _ = socket.read(&mut request).await;
+            let _ = socket.write_all(b"HTTP/1.1 200 OK\r\n\r\n").await;
+            while socket.write_all(&[b' '; 4096]).await.is_ok() {}
+        });
+        let record = checker().check("endless", &url).await;
+        assert_eq!(record.error.as_deref(), Some("Invalid HLS manifest"));
+    }
+}
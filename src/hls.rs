@@ -0,0 +1,295 @@
+use std::collections::HashMap;
+use futures::StreamExt;
+use serde::Serialize;
+
+/// Playlists come from untrusted servers, so reading one stops at this size.
+pub const MAX_PLAYLIST_BYTES: usize = 4 * 1024 * 1024;
+
+#[derive(Serialize, Clone, Debug)]
+pub struct Variant {
+    pub url: String,
+    pub bandwidth: u64,
+    pub average_bandwidth: Option<u64>,
+    pub resolution: Option<(u32, u32)>,
+    pub codecs: Option<String>,
+    pub frame_rate: Option<f64>,
+    pub audio_group: Option<String>,
+}
+
+impl Variant {
+    pub fn label(&self) -> String {
+        let mut parts = Vec::new();
+        if let Some((width, height)) = self.resolution {
+            parts.push(format!("{}x{}", width, height));
+        }
+        parts.push(format!("{:.0} kbps", self.bandwidth as f64 / 1000.0));
+        if let Some(frame_rate) = self.frame_rate {
+            parts.push(format!("{} fps", frame_rate));
+        }
+        if let Some(codecs) = &self.codecs {
+            parts.push(codecs.clone());
+        }
+        parts.join(", ")
+    }
+}
+
+#[derive(Serialize, Clone, Debug)]
+pub struct AudioRendition {
+    pub group_id: String,
+    pub name: String,
+    pub language: Option<String>,
+    pub url: Option<String>,
+    pub default: bool,
+}
+
+#[derive(Serialize, Clone, Debug, Default)]
+pub struct MasterPlaylist {
+    pub variants: Vec<Variant>,
+    pub audio: Vec<AudioRendition>,
+}
+
+impl MasterPlaylist {
+    pub fn audio_group(&self, group_id: &str) -> Vec<&AudioRendition> {
+        self.audio.iter().filter(|a| a.group_id == group_id).collect()
+    }
+}
+
+pub fn is_hls_url(url: &str) -> bool {
+    url.to_lowercase().split(['?', '#']).next().unwrap_or("").ends_with(".m3u8")
+}
+
+/// Splits an attribute list such as `BANDWIDTH=1280000,CODECS="avc1.4d401f,mp4a.40.2"`,
+/// keeping commas inside quoted values.
+pub fn parse_attributes(list: &str) -> HashMap<String, String> {
+    let mut attributes = HashMap::new();
+    let mut rest = list.trim();
+    while !rest.is_empty() {
+        let Some(eq) = rest.find('=') else { break };
+        let key = rest[..eq].trim().to_string();
+        rest = &rest[eq + 1..];
+        let value;
+        if let Some(stripped) = rest.strip_prefix('"') {
+            let end = stripped.find('"').unwrap_or(stripped.len());
+            value = stripped[..end].to_string();
+            rest = stripped.get(end + 1..).unwrap_or("");
+        } else {
+            let end = rest.find(',').unwrap_or(rest.len());
+            value = rest[..end].trim().to_string();
+            rest = &rest[end..];
+        }
+        rest = rest.trim_start_matches(',').trim_start();
+        attributes.insert(key, value);
+    }
+    attributes
+}
+
+/// Resolves a playlist URI relative to the URL the playlist was fetched from.
+pub fn resolve_url(base_url: &str, uri: &str) -> String {
+    match reqwest::Url::parse(base_url).and_then(|base| base.join(uri)) {
+        Ok(url) => url.to_string(),
+        Err(_) => uri.to_string(),
+    }
+}
+
+/// Parses a master playlist. Returns `None` for media playlists, which have no variants to pick from.
+pub fn parse_master(base_url: &str, text: &str) -> Option<MasterPlaylist> {
+    if !text.trim_start().starts_with("#EXTM3U") {
+        return None;
+    }
+
+    let mut master = MasterPlaylist::default();
+    let mut pending: Option<HashMap<String, String>> = None;
+
+    for line in text.lines() {
+        let line = line.trim();
+        if let Some(attributes) = line.strip_prefix("#EXT-X-STREAM-INF:") {
+            pending = Some(parse_attributes(attributes));
+        } else if let Some(attributes) = line.strip_prefix("#EXT-X-MEDIA:") {
+            let attributes = parse_attributes(attributes);
+            if attributes.get("TYPE").map(String::as_str) == Some("AUDIO") {
+                master.audio.push(AudioRendition {
+                    group_id: attributes.get("GROUP-ID").cloned().unwrap_or_default(),
+                    name: attributes.get("NAME").cloned().unwrap_or_default(),
+                    language: attributes.get("LANGUAGE").cloned(),
+                    url: attributes.get("URI").map(|uri| resolve_url(base_url, uri)),
+                    default: attributes.get("DEFAULT").map(String::as_str) == Some("YES"),
+                });
+            }
+        } else if !line.is_empty() && !line.starts_with('#') {
+            if let Some(attributes) = pending.take() {
+                master.variants.push(Variant {
+                    url: resolve_url(base_url, line),
+                    bandwidth: attributes.get("BANDWIDTH").and_then(|v| v.parse().ok()).unwrap_or(0),
+                    average_bandwidth: attributes.get("AVERAGE-BANDWIDTH").and_then(|v| v.parse().ok()),
+                    resolution: attributes.get("RESOLUTION").and_then(|v| {
+                        let (width, height) = v.split_once(['x', 'X'])?;
+                        Some((width.parse().ok()?, height.parse().ok()?))
+                    }),
+                    codecs: attributes.get("CODECS").cloned(),
+                    frame_rate: attributes.get("FRAME-RATE").and_then(|v| v.parse().ok()),
+                    audio_group: attributes.get("AUDIO").cloned(),
+                });
+            }
+        }
+    }
+
+    if master.variants.is_empty() {
+        return None;
+    }
+    master.variants.sort_by_key(|v| std::cmp::Reverse(v.bandwidth));
+    Some(master)
+}
+
+/// Fetches `url` and parses it as a master playlist, following redirects so relative URIs resolve correctly.
+pub async fn fetch_master(client: &reqwest::Client, url: &str) -> Result<Option<MasterPlaylist>, Box<dyn std::error::Error>> {
+    let response = client.get(url).send().await?.error_for_status()?;
+    let final_url = response.url().to_string();
+    let text = read_playlist(response).await?;
+    Ok(parse_master(&final_url, &text))
+}
+
+/// Reads a playlist body, refusing anything larger than `MAX_PLAYLIST_BYTES`.
+pub async fn read_playlist(response: reqwest::Response) -> Result<String, String> {
+    if response.content_length().is_some_and(|length| length > MAX_PLAYLIST_BYTES as u64) {
+        return Err(format!("Playlist is larger than {} bytes", MAX_PLAYLIST_BYTES));
+    }
+    let mut stream = response.bytes_stream();
+    let mut body = Vec::new();
+    while let Some(chunk) = stream.next().await {
+        let chunk = chunk.map_err(|e| e.to_string())?;
+        if body.len() + chunk.len() > MAX_PLAYLIST_BYTES {
+            return Err(format!("Playlist is larger than {} bytes", MAX_PLAYLIST_BYTES));
+        }
+        body.extend_from_slice(&chunk);
+    }
+    Ok(String::from_utf8_lossy(&body).into_owned())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+
+    const MASTER: &str = "#EXTM3U
+#EXT-X-VERSION:4
+#EXT-X-MEDIA:TYPE=AUDIO,GROUP-ID=\"aac\",NAME=\"English\",LANGUAGE=\"en\",DEFAULT=YES,URI=\"audio/en.m3u8\"
+#EXT-X-MEDIA:TYPE=AUDIO,GROUP-ID=\"aac\",NAME=\"Deutsch\",LANGUAGE=\"de\",URI=\"https://audio.example.com/de.m3u8\"
+#EXT-X-MEDIA:TYPE=SUBTITLES,GROUP-ID=\"subs\",NAME=\"English\",URI=\"subs/en.m3u8\"
+
+#EXT-X-STREAM-INF:BANDWIDTH=800000,RESOLUTION=640x360,CODECS=\"avc1.4d401e,mp4a.40.2\",AUDIO=\"aac\"
+low/index.m3u8
+#EXT-X-STREAM-INF:BANDWIDTH=2500000,AVERAGE-BANDWIDTH=2200000,RESOLUTION=1280x720,FRAME-RATE=50.000,CODECS=\"avc1.640020,mp4a.40.2\",AUDIO=\"aac\"
+/live/high/index.m3u8?token=abc
+#EXT-X-STREAM-INF:BANDWIDTH=5000000
+https://cdn.example.net/hd.m3u8
+";
+
+    #[test]
+    fn attributes_keep_commas_inside_quotes() {
+        let attributes = parse_attributes(r#"BANDWIDTH=1280000, CODECS="avc1.4d401f,mp4a.40.2",NAME="A, B",DEFAULT=YES"#);
+        assert_eq!(attributes["BANDWIDTH"], "1280000");
+        assert_eq!(attributes["CODECS"], "avc1.4d401f,mp4a.40.2");
+        assert_eq!(attributes["NAME"], "A, B");
+        assert_eq!(attributes["DEFAULT"], "YES");
+        assert_eq!(attributes.len(), 4);
+
+        let unterminated = parse_attributes(r#"URI="key.bin"#);
+        assert_eq!(unterminated["URI"], "key.bin");
+        assert!(parse_attributes("").is_empty());
+    }
+
+    #[test]
+    fn uris_resolve_against_the_playlist_url() {
+        let base = "https://example.com/live/channel/master.m3u8?token=1";
+        assert_eq!(resolve_url(base, "low/index.m3u8"), "https://example.com/live/channel/low/index.m3u8");
+        assert_eq!(resolve_url(base, "../other.m3u8"), "https://example.com/live/other.m3u8");
+        assert_eq!(resolve_url(base, "/root.m3u8"), "https://example.com/root.m3u8");
+        assert_eq!(resolve_url(base, "//cdn.example.net/a.ts"), "https://cdn.example.net/a.ts");
+        assert_eq!(resolve_url(base, "http://cdn.example.net/a.ts"), "http://cdn.example.net/a.ts");
+        // Without a usable base the URI is kept as it is
+        assert_eq!(resolve_url("not a url", "a.ts"), "a.ts");
+    }
+
+    #[test]
+    fn master_playlists_list_variants_by_bandwidth() {
+        let master = parse_master("https://example.com/live/master.m3u8", MASTER).unwrap();
+        let urls: Vec<&str> = master.variants.iter().map(|v| v.url.as_str()).collect();
+        assert_eq!(urls, [
+            "https://cdn.example.net/hd.m3u8",
+            "https://example.com/live/high/index.m3u8?token=abc",
+            "https://example.com/live/low/index.m3u8",
+        ]);
+
+        let high = &master.variants[1];
+        assert_eq!(high.bandwidth, 2500000);
+        assert_eq!(high.average_bandwidth, Some(2200000));
+        assert_eq!(high.resolution, Some((1280, 720)));
+        assert_eq!(high.frame_rate, Some(50.0));
+        assert_eq!(high.codecs.as_deref(), Some("avc1.640020,mp4a.40.2"));
+        assert_eq!(high.audio_group.as_deref(), Some("aac"));
+        assert_eq!(high.label(), "1280x720, 2500 kbps, 50 fps, avc1.640020,mp4a.40.2");
+        assert_eq!(master.variants[0].resolution, None);
+        assert_eq!(master.variants[0].audio_group, None);
+    }
+
+    #[test]
+    fn audio_renditions_are_grouped() {
+        let master = parse_master("https://example.com/live/master.m3u8", MASTER).unwrap();
+        // Subtitles are not audio
+        assert_eq!(master.audio.len(), 2);
+        let aac = master.audio_group("aac");
+        assert_eq!(aac[0].name, "English");
+        assert_eq!(aac[0].language.as_deref(), Some("en"));
+        assert_eq!(aac[0].url.as_deref(), Some("https://example.com/live/audio/en.m3u8"));
+        assert!(aac[0].default);
+        assert_eq!(aac[1].url.as_deref(), Some("https://audio.example.com/de.m3u8"));
+        assert!(!aac[1].default);
+        assert!(master.audio_group("ac3").is_empty());
+    }
+
+    #[test]
+    fn media_playlists_are_not_master_playlists() {
+        let media = "#EXTM3U\n#EXT-X-TARGETDURATION:6\n#EXTINF:6.0,\nsegment1.ts\n#EXTINF:6.0,\nsegment2.ts\n";
+        assert!(parse_master("https://example.com/live/index.m3u8", media).is_none());
+        assert!(parse_master("https://example.com/", "<html></html>").is_none());
+        assert!(parse_master("https://example.com/", "").is_none());
+    }
+
+    #[test]
+    fn hls_urls_are_recognised_by_their_path() {
+        assert!(is_hls_url("https://example.com/live/INDEX.M3U8?token=1"));
+        assert!(!is_hls_url("https://example.com/stream.ts?list=.m3u8"));
+        assert!(!is_hls_url("https://example.com/playlist.m3u"));
+    }
+
+    /// Answers a single request with `response`.
+    async fn serve_once(response: Vec<u8>) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/master.m3u8", listener.local_addr().unwrap());
+        tokio::spawn(async move {
+            let (mut socket, _) = listener.accept().await.unwrap();
+            let mut request = [0u8; 1024];
+            let _ = socket.read(&mut request).await;
+            let _ = socket.write_all(&response).await;
+        });
+        url
+    }
+
+    #[tokio::test]
+    async fn fetched_playlists_resolve_against_the_url_they_came_from() {
+        let response = format!("HTTP/1.1 200 OK\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}", MASTER.len(), MASTER);
+        let url = serve_once(response.into_bytes()).await;
+        let master = fetch_master(&reqwest::Client::new(), &url).await.unwrap().unwrap();
+        assert_eq!(master.variants[2].url, url.replace("master.m3u8", "low/index.m3u8"));
+    }
+
+    #[tokio::test]
+    async fn oversized_playlists_are_refused() {
+        let mut response = b"HTTP/1.1 200 OK\r\nConnection: close\r\n\r\n#EXTM3U\n".to_vec();
+        response.resize(response.len() + MAX_PLAYLIST_BYTES, b'#');
+        let url = serve_once(response).await;
+        let error = fetch_master(&reqwest::Client::new(), &url).await.unwrap_err();
+        assert!(error.to_string().contains("larger than"), "{}", error);
+    }
+}
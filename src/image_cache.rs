@@ -0,0 +1,1245 @@
+use std::collections::HashMap;
+use std::io::{Cursor, Read};
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime};
+use futures::{stream, StreamExt};
+use hyper::client::connect::dns::Name;
+use image::{ImageReader, Limits};
+use log::{info, warn};
+use reqwest::dns::{Addrs, Resolve, Resolving};
+use reqwest::redirect;
+use sha2::{Digest, Sha256};
+use tokio::io::AsyncWriteExt;
+use tokio::sync::Semaphore;
+
+/// Whether an address is reachable from the internet, as opposed to this machine or the local
+/// network. Logos are not fetched from anything else unless `image_allow_private_addresses` is set.
+fn is_public(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => {
+            let [a, b, ..] = ip.octets();
+            !(ip.is_private()
+                || ip.is_loopback()
+                || ip.is_link_local()
+                || ip.is_unspecified()
+                || ip.is_broadcast()
+                || ip.is_multicast()
+                || ip.is_documentation()
+                // Carrier-grade NAT, 100.64.0.0/10
+                || (a == 100 && (64..128).contains(&b))
+                // Benchmarking, 198.18.0.0/15
+                || (a == 198 && (b & 0xfe) == 18)
+                // Reserved, 240.0.0.0/4
+                || a >= 240
+                || a == 0)
+        }
+        IpAddr::V6(ip) => {
+            let segments = ip.segments();
+            let embedded = |high: u16, low: u16| IpAddr::V4(Ipv4Addr::from(((high as u32) << 16) | low as u32));
+            // Mapped ::ffff:a.b.c.d and compatible ::a.b.c.d, which also covers :: and ::1
+            if let Some(ip) = ip.to_ipv4() {
+                return is_public(IpAddr::V4(ip));
+            }
+            // NAT64 64:ff9b::/96 and 6to4 2002::/16 reach the IPv4 address they carry
+            if segments[..6] == [0x64, 0xff9b, 0, 0, 0, 0] {
+                return is_public(embedded(segments[6], segments[7]));
+            }
+            if segments[0] == 0x2002 {
+                return is_public(embedded(segments[1], segments[2]));
+            }
+            let first = segments[0];
+            !(ip.is_multicast()
+                // Local-use NAT64 64:ff9b:1::/48, translated by a gateway of the local network
+                || segments[..3] == [0x64, 0xff9b, 1]
+                // Unique local fc00::/7 and link-local fe80::/10
+                || (first & 0xfe00) == 0xfc00
+                || (first & 0xffc0) == 0xfe80)
+        }
+    }
+}
+
+/// Resolves host names for the image client, leaving out private addresses. Doing it here
+/// rather than checking URLs beforehand also covers redirects and DNS answers that change
+/// between the check and the request.
+struct PublicResolver;
+
+impl Resolve for PublicResolver {
+    fn resolve(&self, name: Name) -> Resolving {
+        Box::pin(async move {
+            let host = name.as_str().to_string();
+            let addresses: Vec<SocketAddr> = tokio::net::lookup_host((host.as_str(), 0)).await?
+                .filter(|address| is_public(address.ip()))
+                .collect();
+            if addresses.is_empty() {
+                return Err(format!("{} only resolves to private addresses", host).into());
+            }
+            Ok(Box::new(addresses.into_iter()) as Addrs)
+        })
+    }
+}
+
+/// Checks what the resolver cannot see: the scheme, and hosts given as IP addresses.
+fn check_url(url: &reqwest::Url, allow_private: bool) -> Result<(), String> {
+    if url.scheme() != "http" && url.scheme() != "https" {
+        return Err(format!("Unsupported scheme: {}", url.scheme()));
+    }
+    let host = url.host_str().ok_or("URL has no host")?;
+    let Ok(ip) = host.trim_start_matches('[').trim_end_matches(']').parse::<IpAddr>() else {
+        return Ok(());
+    };
+    if allow_private || is_public(ip) {
+        Ok(())
+    } else {
+        Err(format!("{} is a private address", ip))
+    }
+}
+
+/// The image formats logos are accepted in, recognized by their first bytes rather than by
+/// what the server claims they are.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum ImageFormat {
+    Png,
+    Jpeg,
+    Gif,
+    Webp,
+    Bmp,
+    Ico,
+    Avif,
+    Svg,
+}
+
+impl ImageFormat {
+    const ALL: [ImageFormat; 8] = [
+        ImageFormat::Png, ImageFormat::Jpeg, ImageFormat::Gif, ImageFormat::Webp,
+        ImageFormat::Bmp, ImageFormat::Ico, ImageFormat::Avif, ImageFormat::Svg,
+    ];
+
+    pub fn detect(bytes: &[u8]) -> Option<ImageFormat> {
+        if bytes.starts_with(b"\x89PNG\r\n\x1a\n") {
+            Some(ImageFormat::Png)
+        } else if bytes.starts_with(&[0xff, 0xd8, 0xff]) {
+            Some(ImageFormat::Jpeg)
+        } else if bytes.starts_with(b"GIF87a") || bytes.starts_with(b"GIF89a") {
+            Some(ImageFormat::Gif)
+        } else if bytes.len() >= 12 && &bytes[..4] == b"RIFF" && &bytes[8..12] == b"WEBP" {
+            Some(ImageFormat::Webp)
+        } else if bytes.starts_with(b"BM") && bytes.len() >= 14 {
+            Some(ImageFormat::Bmp)
+        } else if bytes.starts_with(&[0, 0, 1, 0]) {
+            Some(ImageFormat::Ico)
+        } else if bytes.len() >= 12 && &bytes[4..8] == b"ftyp" && (&bytes[8..12] == b"avif" || &bytes[8..12] == b"avis") {
+            Some(ImageFormat::Avif)
+        } else if is_svg(bytes) {
+            Some(ImageFormat::Svg)
+        } else {
+            None
+        }
+    }
+
+    pub fn extension(self) -> &'static str {
+        match self {
+            ImageFormat::Png => "png",
+            ImageFormat::Jpeg => "jpg",
+            ImageFormat::Gif => "gif",
+            ImageFormat::Webp => "webp",
+            ImageFormat::Bmp => "bmp",
+            ImageFormat::Ico => "ico",
+            ImageFormat::Avif => "avif",
+            ImageFormat::Svg => "svg",
+        }
+    }
+
+    pub fn from_extension(extension: &str) -> Option<ImageFormat> {
+        ImageFormat::ALL.into_iter().find(|format| format.extension() == extension)
+    }
+
+    pub fn mime_type(self) -> &'static str {
+        match self {
+            ImageFormat::Png => "image/png",
+            ImageFormat::Jpeg => "image/jpeg",
+            ImageFormat::Gif => "image/gif",
+            ImageFormat::Webp => "image/webp",
+            ImageFormat::Bmp => "image/bmp",
+            ImageFormat::Ico => "image/x-icon",
+            ImageFormat::Avif => "image/avif",
+            ImageFormat::Svg => "image/svg+xml",
+        }
+    }
+
+    /// The decoder for formats that can be turned into thumbnails. SVG is already scalable,
+    /// and AVIF has no decoder here, so those are kept as they are.
+    fn decoder_format(self) -> Option<image::ImageFormat> {
+        match self {
+            ImageFormat::Png => Some(image::ImageFormat::Png),
+            ImageFormat::Jpeg => Some(image::ImageFormat::Jpeg),
+            ImageFormat::Gif => Some(image::ImageFormat::Gif),
+            ImageFormat::Webp => Some(image::ImageFormat::WebP),
+            ImageFormat::Bmp => Some(image::ImageFormat::Bmp),
+            ImageFormat::Ico => Some(image::ImageFormat::Ico),
+            ImageFormat::Avif | ImageFormat::Svg => None,
+        }
+    }
+}
+
+/// An SVG document, possibly starting with an XML declaration, comments or a doctype.
+fn is_svg(bytes: &[u8]) -> bool {
+    let head = String::from_utf8_lossy(&bytes[..bytes.len().min(4096)]).to_ascii_lowercase();
+    let head = head.trim_start_matches('\u{feff}').trim_start();
+    head.starts_with('<') && head.contains("<svg") && !head.contains("<html")
+}
+
+/// Scales a logo down to fit in `size` pixels and encodes it as PNG, which keeps transparency.
+/// Returns `None` when the logo cannot be decoded or the thumbnail would not be smaller.
+fn thumbnail(bytes: &[u8], format: ImageFormat, size: u32) -> Option<Vec<u8>> {
+    let mut reader = ImageReader::with_format(Cursor::new(bytes), format.decoder_format()?);
+    // A small file can still declare huge dimensions, so decoding is bounded too
+    let mut limits = Limits::default();
+    limits.max_image_width = Some(8192);
+    limits.max_image_height = Some(8192);
+    limits.max_alloc = Some(128 * 1024 * 1024);
+    reader.limits(limits);
+    let image = reader.decode().ok()?;
+
+    let image = if image.width() > size || image.height() > size {
+        image.thumbnail(size, size)
+    } else {
+        image
+    };
+    let mut encoded = Vec::new();
+    image.write_to(&mut Cursor::new(&mut encoded), image::ImageFormat::Png).ok()?;
+    (encoded.len() < bytes.len()).then_some(encoded)
+}
+
+struct CacheEntry {
+    file_name: String,
+    size: u64,
+    saved: SystemTime,
+    last_access: SystemTime,
+}
+
+/// Cached files are named after the SHA-256 of their URL.
+fn is_cache_key(key: &str) -> bool {
+    key.len() == 64 && key.bytes().all(|b| b.is_ascii_digit() || (b'a'..=b'f').contains(&b))
+}
+
+/// Reads what is already in the cache directory, so it survives restarts. Files that were
+/// never used since are as old as their last modification.
+fn scan_dir(dir: &Path) -> CacheIndex {
+    let mut index = CacheIndex::default();
+    let Ok(dir_entries) = std::fs::read_dir(dir) else {
+        return index;
+    };
+    for dir_entry in dir_entries.flatten() {
+        let path = dir_entry.path();
+        let Ok(metadata) = dir_entry.metadata() else { continue };
+        let (Some(key), Some(extension)) = (path.file_stem().and_then(|s| s.to_str()), path.extension().and_then(|e| e.to_str())) else {
+            continue;
+        };
+        if extension.ends_with("tmp") {
+            // Left behind by a download that was interrupted by a shutdown
+            let _ = std::fs::remove_file(&path);
+            continue;
+        }
+        if !metadata.is_file() || !is_cache_key(key) {
+            continue;
+        }
+        let Some(mut format) = ImageFormat::from_extension(extension) else { continue };
+        if format == ImageFormat::Jpeg {
+            // Older versions saved every logo as .jpg, whatever its format
+            let actual = std::fs::File::open(&path).and_then(|file| {
+                let mut head = Vec::new();
+                file.take(4096).read_to_end(&mut head)?;
+                Ok(ImageFormat::detect(&head))
+            });
+            if let Ok(Some(actual)) = actual {
+                if actual != format && std::fs::rename(&path, path.with_extension(actual.extension())).is_ok() {
+                    format = actual;
+                }
+            }
+        }
+        let saved = metadata.modified().unwrap_or_else(|_| SystemTime::now());
+        index.insert(key.to_string(), CacheEntry {
+            file_name: format!("{}.{}", key, format.extension()),
+            size: metadata.len(),
+            saved,
+            last_access: metadata.accessed().map_or(saved, |accessed| accessed.max(saved)),
+        });
+    }
+    index
+}
+
+/// A logo whose download failed, and when to try it again.
+struct Failure {
+    attempts: u32,
+    retry_at: Instant,
+}
+
+/// The backoff doubles with every failed attempt up to this.
+const MAX_RETRY_AFTER: Duration = Duration::from_secs(24 * 3600);
+
+#[derive(Clone, Default)]
+pub struct PrefetchProgress {
+    pub running: bool,
+    pub total: usize,
+    pub done: usize,
+    pub failed: usize,
+    /// Downloaded by the prefetch, not counting logos that were already cached.
+    pub bytes: u64,
+}
+
+/// The cached files by the hash of their URL, and their total size.
+#[derive(Default)]
+struct CacheIndex {
+    entries: HashMap<String, CacheEntry>,
+    bytes: u64,
+}
+
+impl CacheIndex {
+    fn insert(&mut self, key: String, entry: CacheEntry) {
+        self.bytes += entry.size;
+        if let Some(previous) = self.entries.insert(key, entry) {
+            self.bytes -= previous.size;
+        }
+    }
+
+    fn remove(&mut self, key: &str) -> Option<CacheEntry> {
+        let entry = self.entries.remove(key)?;
+        self.bytes -= entry.size;
+        Some(entry)
+    }
+}
+
+pub struct ImageCacheStats {
+    pub entries: usize,
+    pub bytes: u64,
+    pub max_bytes: Option<u64>,
+    pub max_age: Option<Duration>,
+    pub hits: u64,
+    pub misses: u64,
+    /// Logos whose last download failed.
+    pub failures: usize,
+}
+
+/// A logo for channels without one: the initials of the first two words of the name on a
+/// colour picked from its hash, so channels look different from each other in the grid view.
+fn fallback_svg(name: &str) -> String {
+    let initials: String = name
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .take(2)
+        .filter_map(|word| word.chars().next())
+        .flat_map(char::to_uppercase)
+        .collect();
+    let hash = Sha256::digest(name.as_bytes());
+    let hue = u16::from_be_bytes([hash[0], hash[1]]) % 360;
+    // Only letters and digits are used, so the initials need no escaping
+    format!(
+        concat!(
+            r##"<svg xmlns="http://www.w3.org/2000/svg" width="128" height="128" viewBox="0 0 128 128">"##,
+            r##"<rect width="128" height="128" rx="16" fill="hsl({}, 45%, 40%)"/>"##,
+            r##"<text x="64" y="64" dy=".35em" text-anchor="middle" font-family="sans-serif" font-size="52" font-weight="bold" fill="#fff">{}</text>"##,
+            "</svg>",
+        ),
+        hue,
+        if initials.is_empty() { "?".to_string() } else { initials },
+    )
+}
+
+/// Downloads channel logos into `image_cache/` so browsers load them from this server.
+pub struct ImageCache {
+    dir: PathBuf,
+    client: reqwest::Client,
+    max_bytes: u64,
+    allow_private: bool,
+    /// Logos are scaled down to fit in this many pixels when set.
+    thumbnail_size: Option<u32>,
+    index: Mutex<CacheIndex>,
+    /// Least recently used logos are removed when the cache grows past this.
+    max_total_bytes: Option<u64>,
+    /// Logos are downloaded again once they are this old.
+    max_age: Option<Duration>,
+    hits: AtomicU64,
+    misses: AtomicU64,
+    failures: Mutex<HashMap<String, Failure>>,
+    /// How long to wait before trying a failed logo again for the first time.
+    retry_after: Duration,
+    /// One lock per logo being downloaded, so concurrent requests for it share the download.
+    in_flight: Mutex<HashMap<String, Arc<tokio::sync::Mutex<()>>>>,
+    /// Limits the downloads running at once across all logos.
+    downloads: Semaphore,
+    prefetch: Mutex<Option<PrefetchProgress>>,
+    prefetch_concurrency: usize,
+    prefetch_bytes_per_sec: Option<u64>,
+}
+
+/// A request's share of the download of one key. The last one to finish, or to be dropped
+/// while waiting, removes the key from `in_flight`.
+struct InFlight<'a> {
+    cache: &'a ImageCache,
+    key: &'a str,
+    lock: Arc<tokio::sync::Mutex<()>>,
+}
+
+impl<'a> InFlight<'a> {
+    fn join(cache: &'a ImageCache, key: &'a str) -> Self {
+        let lock = cache.in_flight.lock().unwrap().entry(key.to_string()).or_default().clone();
+        InFlight { cache, key, lock }
+    }
+}
+
+impl Drop for InFlight<'_> {
+    fn drop(&mut self) {
+        let mut in_flight = self.cache.in_flight.lock().unwrap();
+        // Two references are left when nobody else is waiting: the map's and this one
+        if in_flight.get(self.key).is_some_and(|lock| Arc::ptr_eq(lock, &self.lock) && Arc::strong_count(lock) == 2) {
+            in_flight.remove(self.key);
+        }
+    }
+}
+
+impl ImageCache {
+    pub fn from_config(config: &HashMap<String, String>) -> Self {
+        ImageCache::in_dir(PathBuf::from("image_cache"), config)
+    }
+
+    fn in_dir(dir: PathBuf, config: &HashMap<String, String>) -> Self {
+        let number = |key: &str, default: u64| config.get(key).and_then(|v| v.parse::<u64>().ok()).unwrap_or(default);
+        let allow_private = config.get("image_allow_private_addresses").is_some_and(|v| v == "true");
+        let max_redirects = number("image_max_redirects", 3) as usize;
+
+        let mut client = reqwest::Client::builder()
+            .timeout(Duration::from_secs(number("image_fetch_timeout_secs", 10).max(1)))
+            .connect_timeout(Duration::from_secs(5))
+            .redirect(redirect::Policy::custom(move |attempt| {
+                if attempt.previous().len() > max_redirects {
+                    return attempt.error("Too many redirects");
+                }
+                match check_url(attempt.url(), allow_private) {
+                    Ok(()) => attempt.follow(),
+                    Err(e) => attempt.error(e),
+                }
+            }));
+        if !allow_private {
+            client = client.dns_resolver(Arc::new(PublicResolver));
+        }
+
+        let index = scan_dir(&dir);
+
+        ImageCache {
+            dir,
+            client: client.build().expect("Failed to build HTTP client"),
+            max_bytes: number("image_max_bytes", 5 * 1024 * 1024),
+            allow_private,
+            thumbnail_size: config.get("image_thumbnail_size")
+                .and_then(|v| v.parse::<u32>().ok())
+                .filter(|size| *size > 0),
+            index: Mutex::new(index),
+            max_total_bytes: Some(number("image_cache_max_mb", 500) * 1024 * 1024).filter(|bytes| *bytes > 0),
+            max_age: Some(number("image_cache_max_age_days", 30) * 24 * 3600).filter(|secs| *secs > 0).map(Duration::from_secs),
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+            failures: Mutex::new(HashMap::new()),
+            retry_after: Duration::from_secs(number("image_retry_minutes", 5).max(1) * 60),
+            in_flight: Mutex::new(HashMap::new()),
+            downloads: Semaphore::new(number("image_fetch_concurrency", 8).max(1) as usize),
+            prefetch: Mutex::new(None),
+            prefetch_concurrency: number("image_prefetch_concurrency", 2).max(1) as usize,
+            prefetch_bytes_per_sec: Some(number("image_prefetch_max_kbps", 0) * 1024).filter(|rate| *rate > 0),
+        }
+    }
+
+    /// Whether the cache has a size or age limit that the eviction task has to enforce.
+    pub fn has_limits(&self) -> bool {
+        self.max_total_bytes.is_some() || self.max_age.is_some()
+    }
+
+    /// The path and format of a cached file, marking it as used. Anything but the name of a
+    /// file in the cache, like `../config.txt`, is refused.
+    pub fn file(&self, file_name: &str) -> Option<(PathBuf, ImageFormat)> {
+        let (key, extension) = file_name.split_once('.')?;
+        if !is_cache_key(key) {
+            return None;
+        }
+        let format = ImageFormat::from_extension(extension)?;
+        let mut index = self.index.lock().unwrap();
+        let entry = index.entries.get_mut(key).filter(|entry| entry.file_name == file_name)?;
+        entry.last_access = SystemTime::now();
+        Some((self.dir.join(file_name), format))
+    }
+
+    pub fn stats(&self) -> ImageCacheStats {
+        let index = self.index.lock().unwrap();
+        ImageCacheStats {
+            entries: index.entries.len(),
+            bytes: index.bytes,
+            max_bytes: self.max_total_bytes,
+            max_age: self.max_age,
+            hits: self.hits.load(Ordering::Relaxed),
+            misses: self.misses.load(Ordering::Relaxed),
+            failures: self.failures.lock().unwrap().len(),
+        }
+    }
+
+    /// Removes logos older than the maximum age, then the least recently used ones until the
+    /// cache fits in its size limit. Returns how many files were removed.
+    pub async fn evict(&self) -> usize {
+        let now = SystemTime::now();
+        let removed = {
+            let mut index = self.index.lock().unwrap();
+            let mut removed = Vec::new();
+            if let Some(max_age) = self.max_age {
+                let expired: Vec<String> = index.entries.iter()
+                    .filter(|(_, entry)| now.duration_since(entry.saved).is_ok_and(|age| age > max_age))
+                    .map(|(key, _)| key.clone())
+                    .collect();
+                for key in expired {
+                    removed.extend(index.remove(&key).map(|entry| entry.file_name));
+                }
+            }
+            if let Some(max_bytes) = self.max_total_bytes {
+                if index.bytes > max_bytes {
+                    let mut by_access: Vec<(SystemTime, String)> = index.entries.iter()
+                        .map(|(key, entry)| (entry.last_access, key.clone()))
+                        .collect();
+                    by_access.sort();
+                    for (_, key) in by_access {
+                        if index.bytes <= max_bytes {
+                            break;
+                        }
+                        removed.extend(index.remove(&key).map(|entry| entry.file_name));
+                    }
+                }
+            }
+            removed
+        };
+
+        for file_name in &removed {
+            if let Err(e) = tokio::fs::remove_file(self.dir.join(file_name)).await {
+                warn!("Failed to remove cached image {}: {}", file_name, e);
+            }
+        }
+        removed.len()
+    }
+
+    /// Removes every cached logo, returning how many there were. Failed logos are forgotten too,
+    /// so they are tried again right away.
+    pub async fn clear(&self) -> usize {
+        self.failures.lock().unwrap().clear();
+        let removed: Vec<CacheEntry> = std::mem::take(&mut *self.index.lock().unwrap()).entries.into_values().collect();
+        for entry in &removed {
+            if let Err(e) = tokio::fs::remove_file(self.dir.join(&entry.file_name)).await {
+                warn!("Failed to remove cached image {}: {}", entry.file_name, e);
+            }
+        }
+        removed.len()
+    }
+
+    /// Returns the file name of the cached copy of `url`, downloading it first if needed.
+    /// Requests for a logo that is already being downloaded wait for that download, and
+    /// logos that failed are not tried again until their backoff has passed.
+    pub async fn fetch(&self, url: &str) -> Result<String, String> {
+        let key = format!("{:x}", Sha256::digest(url.as_bytes()));
+        if let Some(file_name) = self.lookup(&key)? {
+            self.hits.fetch_add(1, Ordering::Relaxed);
+            return Ok(file_name);
+        }
+        self.misses.fetch_add(1, Ordering::Relaxed);
+        self.download_once(key, url).await.map(|(file_name, _)| file_name)
+    }
+
+    /// Downloads a logo unless another request is already doing so, returning its file name
+    /// and how many bytes were downloaded for it.
+    async fn download_once(&self, key: String, url: &str) -> Result<(String, u64), String> {
+        let in_flight = InFlight::join(self, &key);
+        let _guard = in_flight.lock.lock().await;
+        // Whoever held the lock before may have just finished the same download
+        if let Some(file_name) = self.lookup(&key)? {
+            return Ok((file_name, 0));
+        }
+
+        let result = {
+            let _permit = self.downloads.acquire().await.expect("The download semaphore is never closed");
+            self.save(&key, url).await
+        };
+        // Recorded before the lock is released, so the requests waiting for it see the outcome
+        match &result {
+            Ok(_) => {
+                self.failures.lock().unwrap().remove(&key);
+            }
+            Err(e) => {
+                let mut failures = self.failures.lock().unwrap();
+                let attempts = failures.get(&key).map_or(1, |failure| failure.attempts + 1);
+                let backoff = self.retry_after.saturating_mul(1 << (attempts - 1).min(16)).min(MAX_RETRY_AFTER);
+                warn!("Failed to cache image {}: {} (next attempt in {} minutes)", url, e, backoff.as_secs() / 60);
+                failures.insert(key.clone(), Failure { attempts, retry_at: Instant::now() + backoff });
+            }
+        }
+        result
+    }
+
+    /// Returns the file name of the generated logo for a channel name, saving it on first use.
+    pub async fn fallback(&self, name: &str) -> Result<String, String> {
+        let key = format!("{:x}", Sha256::digest(format!("fallback:{}", name).as_bytes()));
+        if let Some(entry) = self.index.lock().unwrap().entries.get_mut(&key) {
+            entry.last_access = SystemTime::now();
+            return Ok(entry.file_name.clone());
+        }
+
+        let svg = fallback_svg(name);
+        let file_name = format!("{}.{}", key, ImageFormat::Svg.extension());
+        let path = self.dir.join(&file_name);
+        tokio::fs::create_dir_all(&self.dir).await.map_err(|e| e.to_string())?;
+        write_atomically(&path, svg.as_bytes()).await.map_err(|e| format!("Failed to save {}: {}", path.display(), e))?;
+
+        let now = SystemTime::now();
+        self.index.lock().unwrap().insert(key, CacheEntry {
+            file_name: file_name.clone(),
+            size: svg.len() as u64,
+            saved: now,
+            last_access: now,
+        });
+        Ok(file_name)
+    }
+
+    /// Downloads the logos that are not cached yet in the background, `prefetch_concurrency` at
+    /// a time and no faster than `prefetch_bytes_per_sec`, stopping early if the cache fills up.
+    pub async fn prefetch(&self, urls: Vec<String>) {
+        let started = Instant::now();
+        *self.prefetch.lock().unwrap() = Some(PrefetchProgress { running: true, total: urls.len(), ..Default::default() });
+
+        stream::iter(urls)
+            .take_while(|_| futures::future::ready(!self.is_full()))
+            .map(|url| async move {
+                let key = format!("{:x}", Sha256::digest(url.as_bytes()));
+                match self.lookup(&key) {
+                    Ok(Some(_)) => Ok(0),
+                    Ok(None) => {
+                        self.wait_for_prefetch_rate(started).await;
+                        self.download_once(key, &url).await.map(|(_, downloaded)| downloaded)
+                    }
+                    Err(e) => Err(e),
+                }
+            })
+            .buffer_unordered(self.prefetch_concurrency)
+            .for_each(|result| async move {
+                let mut progress = self.prefetch.lock().unwrap();
+                let progress = progress.as_mut().expect("Prefetch progress is set when it starts");
+                progress.done += 1;
+                match result {
+                    Ok(downloaded) => progress.bytes += downloaded,
+                    Err(_) => progress.failed += 1,
+                }
+                if progress.done.is_multiple_of(500) {
+                    info!("Prefetched {} of {} logos", progress.done, progress.total);
+                }
+            })
+            .await;
+
+        let mut progress = self.prefetch.lock().unwrap();
+        if let Some(progress) = progress.as_mut() {
+            progress.running = false;
+            if progress.done < progress.total {
+                info!("Stopped prefetching logos after {} of {}: the image cache is full", progress.done, progress.total);
+            } else {
+                info!("Prefetched {} logos in {}s, {} failed", progress.total, started.elapsed().as_secs(), progress.failed);
+            }
+        }
+    }
+
+    pub fn prefetch_progress(&self) -> Option<PrefetchProgress> {
+        self.prefetch.lock().unwrap().clone()
+    }
+
+    fn is_full(&self) -> bool {
+        self.max_total_bytes.is_some_and(|max_bytes| {
+            self.index.lock().unwrap().bytes >= max_bytes
+        })
+    }
+
+    /// Keeps the next prefetch download from starting until the average rate since `started` is
+    /// back under the limit. Downloads that are already running carry on meanwhile.
+    async fn wait_for_prefetch_rate(&self, started: Instant) {
+        let Some(rate) = self.prefetch_bytes_per_sec else { return };
+        let bytes = self.prefetch.lock().unwrap().as_ref().map_or(0, |progress| progress.bytes);
+        let due = Duration::from_secs_f64(bytes as f64 / rate as f64);
+        if let Some(wait) = due.checked_sub(started.elapsed()) {
+            tokio::time::sleep(wait).await;
+        }
+    }
+
+    /// The cached file for a URL hash, or an error if the last download failed too recently to retry.
+    fn lookup(&self, key: &str) -> Result<Option<String>, String> {
+        if let Some(entry) = self.index.lock().unwrap().entries.get_mut(key) {
+            entry.last_access = SystemTime::now();
+            return Ok(Some(entry.file_name.clone()));
+        }
+        match self.failures.lock().unwrap().get(key) {
+            Some(failure) if failure.retry_at > Instant::now() => Err("Download failed recently".to_string()),
+            _ => Ok(None),
+        }
+    }
+
+    /// Downloads and stores a logo, returning its file name and the downloaded size, which is
+    /// larger than the stored one for thumbnails.
+    async fn save(&self, key: &str, url: &str) -> Result<(String, u64), String> {
+        let parsed = reqwest::Url::parse(url).map_err(|e| format!("Invalid URL {}: {}", url, e))?;
+        check_url(&parsed, self.allow_private)?;
+        let mut bytes = self.download(parsed).await?;
+        let downloaded = bytes.len() as u64;
+        let mut format = ImageFormat::detect(&bytes).ok_or("Downloaded content is not an image")?;
+
+        if let Some(size) = self.thumbnail_size {
+            (bytes, format) = tokio::task::spawn_blocking(move || match thumbnail(&bytes, format, size) {
+                Some(resized) => (resized, ImageFormat::Png),
+                None => (bytes, format),
+            }).await.map_err(|e| e.to_string())?;
+        }
+
+        let file_name = format!("{}.{}", key, format.extension());
+        let path = self.dir.join(&file_name);
+        tokio::fs::create_dir_all(&self.dir).await.map_err(|e| e.to_string())?;
+        write_atomically(&path, &bytes).await.map_err(|e| format!("Failed to save {}: {}", path.display(), e))?;
+
+        let now = SystemTime::now();
+        self.index.lock().unwrap().insert(key.to_string(), CacheEntry {
+            file_name: file_name.clone(),
+            size: bytes.len() as u64,
+            saved: now,
+            last_access: now,
+        });
+        Ok((file_name, downloaded))
+    }
+
+    async fn download(&self, url: reqwest::Url) -> Result<Vec<u8>, String> {
+        let response = self.client.get(url)
+            .send()
+            .await
+            .and_then(|r| r.error_for_status())
+            .map_err(|e| e.to_string())?;
+
+        if response.content_length().is_some_and(|length| length > self.max_bytes) {
+            return Err(format!("Image is larger than {} bytes", self.max_bytes));
+        }
+
+        // The length header may be missing or wrong, so the limit is enforced on what arrives
+        let mut bytes = Vec::new();
+        let mut stream = response.bytes_stream();
+        while let Some(chunk) = stream.next().await {
+            let chunk = chunk.map_err(|e| e.to_string())?;
+            if (bytes.len() + chunk.len()) as u64 > self.max_bytes {
+                return Err(format!("Image is larger than {} bytes", self.max_bytes));
+            }
+            bytes.extend_from_slice(&chunk);
+        }
+        if bytes.is_empty() {
+            return Err("Downloaded image is empty".to_string());
+        }
+        Ok(bytes)
+    }
+}
+
+static TEMP_FILE_COUNTER: AtomicU64 = AtomicU64::new(0);
+
+/// Writes to a temporary file first, so a failed download never leaves a partial image behind.
+async fn write_atomically(path: &Path, bytes: &[u8]) -> std::io::Result<()> {
+    let temp_path = path.with_extension(format!("{}.tmp", TEMP_FILE_COUNTER.fetch_add(1, Ordering::Relaxed)));
+    let mut file = tokio::fs::File::create(&temp_path).await?;
+    file.write_all(bytes).await?;
+    file.flush().await?;
+    drop(file);
+    tokio::fs::rename(&temp_path, path).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::str::FromStr;
+    use std::sync::atomic::AtomicUsize;
+    use tokio::io::AsyncReadExt;
+    use tokio::net::TcpListener;
+
+    /// Serves raw HTTP responses by path on a local port. Returns the base URL and a count of
+    /// the requests received.
+    async fn serve(routes: Vec<(&'static str, Vec<u8>)>) -> (String, Arc<AtomicUsize>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let base = format!("http://{}", listener.local_addr().unwrap());
+        let requests = Arc::new(AtomicUsize::new(0));
+        let counter = requests.clone();
+        tokio::spawn(async move {
+            while let Ok((mut socket, _)) = listener.accept().await {
+                counter.fetch_add(1, Ordering::Relaxed);
+                let mut request = [0u8; 2048];
+                let read = socket.read(&mut request).await.unwrap_or(0);
+                let request = String::from_utf8_lossy(&request[..read]).into_owned();
+                let path = request.split(' ').nth(1).unwrap_or("");
+                let response = routes.iter()
+                    .find(|(route, _)| *route == path)
+                    .map(|(_, response)| response.clone())
+                    .unwrap_or_else(|| b"HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\nConnection: close\r\n\r\n".to_vec());
+                let _ = socket.write_all(&response).await;
+            }
+        });
+        (base, requests)
+    }
+
+    fn ok(body: &[u8]) -> Vec<u8> {
+        [format!("HTTP/1.1 200 OK\r\nContent-Length: {}\r\nConnection: close\r\n\r\n", body.len()).as_bytes(), body].concat()
+    }
+
+    fn redirect(location: &str) -> Vec<u8> {
+        format!("HTTP/1.1 302 Found\r\nLocation: {}\r\nContent-Length: 0\r\nConnection: close\r\n\r\n", location).into_bytes()
+    }
+
+    const GIF: &[u8] = b"GIF89a\x01\x00\x01\x00\x00\x00\x00;";
+
+    /// A cache in its own temporary directory that may fetch from local test servers.
+    fn cache(name: &str, settings: &[(&str, &str)]) -> ImageCache {
+        let dir = std::env::temp_dir().join(format!("m3u_browser_images_{}_{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        let mut config: HashMap<String, String> = settings.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect();
+        config.insert("image_allow_private_addresses".to_string(), "true".to_string());
+        ImageCache::in_dir(dir, &config)
+    }
+
+    #[tokio::test]
+    async fn abandoned_download_is_not_left_in_flight() {
+        // Accepts connections but never answers
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/logo.png", listener.local_addr().unwrap());
+        tokio::spawn(async move {
+            let mut sockets = Vec::new();
+            while let Ok((socket, _)) = listener.accept().await {
+                sockets.push(socket);
+            }
+        });
+
+        let cache = cache("abandoned", &[]);
+        let first = tokio::time::timeout(Duration::from_millis(200), cache.fetch(&url));
+        let second = tokio::time::timeout(Duration::from_millis(300), cache.fetch(&url));
+        let (first, second) = tokio::join!(first, second);
+        assert!(first.is_err() && second.is_err());
+        assert!(cache.in_flight.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn only_public_addresses_are_public() {
+        for ip in [
+            "8.8.8.8", "1.1.1.1", "198.20.0.1", "223.255.255.255", "2001:4860:4860::8888", "::ffff:8.8.8.8",
+            "::8.8.8.8", "64:ff9b::808:808", "2002:808:808::1",
+        ] {
+            assert!(is_public(ip.parse().unwrap()), "{}", ip);
+        }
+        for ip in [
+            "127.0.0.1", "10.1.2.3", "172.16.0.1", "192.168.1.1", "169.254.169.254", "100.64.0.1",
+            "0.1.2.3", "0.0.0.0", "255.255.255.255", "224.0.0.1", "192.0.2.1",
+            "198.18.0.1", "198.19.255.255", "240.0.0.1", "250.1.2.3",
+            "::1", "::", "fc00::1", "fd12::1", "fe80::1", "ff02::1", "::ffff:127.0.0.1", "::ffff:10.0.0.1",
+            // IPv4 addresses inside IPv4-compatible, NAT64 and 6to4 addresses
+            "::127.0.0.1", "::192.168.1.1", "64:ff9b::7f00:1", "64:ff9b::a9fe:a9fe", "64:ff9b:1::1",
+            "2002:7f00:1::1", "2002:c0a8:101::1", "2002:a00:1::",
+        ] {
+            assert!(!is_public(ip.parse().unwrap()), "{}", ip);
+        }
+    }
+
+    #[test]
+    fn urls_need_http_and_a_public_literal_address() {
+        let check = |url: &str, allow_private| check_url(&reqwest::Url::parse(url).unwrap(), allow_private);
+        assert!(check("https://example.com/logo.png", false).is_ok());
+        assert!(check("http://8.8.8.8/logo.png", false).is_ok());
+        assert!(check("ftp://example.com/logo.png", false).is_err());
+        assert!(check("file:///etc/passwd", true).is_err());
+        assert!(check("http://127.0.0.1/logo.png", false).is_err());
+        assert!(check("http://[::1]:8080/logo.png", false).is_err());
+        assert!(check("http://[::ffff:192.168.0.1]/logo.png", false).is_err());
+        assert!(check("http://127.0.0.1/logo.png", true).is_ok());
+    }
+
+    #[tokio::test]
+    async fn resolver_refuses_names_of_private_addresses() {
+        let error = PublicResolver.resolve(Name::from_str("localhost").unwrap()).await.err().unwrap();
+        assert!(error.to_string().contains("private addresses"), "{}", error);
+    }
+
+    #[tokio::test]
+    async fn private_addresses_are_refused_before_connecting() {
+        let (base, requests) = serve(vec![("/logo.gif", ok(GIF))]).await;
+        let dir = std::env::temp_dir().join(format!("m3u_browser_images_private_{}", std::process::id()));
+        let cache = ImageCache::in_dir(dir, &HashMap::new());
+        let error = cache.fetch(&format!("{}/logo.gif", base)).await.unwrap_err();
+        assert!(error.contains("private address"), "{}", error);
+        assert_eq!(requests.load(Ordering::Relaxed), 0);
+    }
+
+    #[tokio::test]
+    async fn redirects_are_limited_and_checked() {
+        let (base, _) = serve(vec![
+            ("/logo.gif", ok(GIF)),
+            ("/one", redirect("/logo.gif")),
+            ("/two", redirect("/one")),
+            ("/file", redirect("file:///etc/passwd")),
+        ]).await;
+        let cache = cache("redirects", &[("image_max_redirects", "1")]);
+        assert!(cache.fetch(&format!("{}/one", base)).await.unwrap().ends_with(".gif"));
+        let error = cache.fetch(&format!("{}/two", base)).await.unwrap_err();
+        assert!(error.contains("Too many redirects"), "{}", error);
+        assert!(cache.fetch(&format!("{}/file", base)).await.is_err());
+        std::fs::remove_dir_all(&cache.dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn downloads_stop_at_max_bytes() {
+        let large = [GIF, &[0u8; 200]].concat();
+        // Without a length the limit has to be enforced on the body as it arrives
+        let unannounced = [b"HTTP/1.1 200 OK\r\nConnection: close\r\n\r\n".as_slice(), &large].concat();
+        let (base, _) = serve(vec![
+            ("/small.gif", ok(GIF)),
+            ("/large.gif", ok(&large)),
+            ("/unannounced.gif", unannounced),
+            ("/empty.gif", ok(b"")),
+            ("/page.html", ok(b"<html><body>Not found</body></html>")),
+        ]).await;
+        let cache = cache("max_bytes", &[("image_max_bytes", "100")]);
+        assert!(cache.fetch(&format!("{}/small.gif", base)).await.is_ok());
+        for path in ["/large.gif", "/unannounced.gif"] {
+            let error = cache.fetch(&format!("{}{}", base, path)).await.unwrap_err();
+            assert_eq!(error, "Image is larger than 100 bytes");
+        }
+        assert_eq!(cache.fetch(&format!("{}/empty.gif", base)).await.unwrap_err(), "Downloaded image is empty");
+        assert_eq!(cache.fetch(&format!("{}/page.html", base)).await.unwrap_err(), "Downloaded content is not an image");
+        assert_eq!(cache.stats().entries, 1);
+        std::fs::remove_dir_all(&cache.dir).unwrap();
+    }
+
+    #[test]
+    fn formats_are_detected_from_their_first_bytes() {
+        let cases: [(&[u8], Option<ImageFormat>); 12] = [
+            (b"\x89PNG\r\n\x1a\n\0\0\0\rIHDR", Some(ImageFormat::Png)),
+            (b"\xff\xd8\xff\xe0\0\x10JFIF", Some(ImageFormat::Jpeg)),
+            (GIF, Some(ImageFormat::Gif)),
+            (b"RIFF\x24\0\0\0WEBPVP8 ", Some(ImageFormat::Webp)),
+            (b"BM\x3a\0\0\0\0\0\0\0\x36\0\0\0", Some(ImageFormat::Bmp)),
+            (b"\0\0\x01\0\x01\0\x10\x10", Some(ImageFormat::Ico)),
+            (b"\0\0\0\x1cftypavif\0\0\0\0", Some(ImageFormat::Avif)),
+            (b"\xef\xbb\xbf<?xml version=\"1.0\"?>\n<!-- logo -->\n<svg xmlns=\"http://www.w3.org/2000/svg\"/>", Some(ImageFormat::Svg)),
+            (b"<html><body><svg/></body></html>", None),
+            (b"\0\0\0\x1cftypisom\0\0\0\0", None),
+            (b"BM", None),
+            (b"", None),
+        ];
+        for (bytes, format) in cases {
+            assert_eq!(ImageFormat::detect(bytes), format, "{:?}", String::from_utf8_lossy(bytes));
+        }
+        for format in ImageFormat::ALL {
+            assert_eq!(ImageFormat::from_extension(format.extension()), Some(format));
+        }
+        assert_eq!(ImageFormat::from_extension("html"), None);
+    }
+
+    fn png(size: u32) -> Vec<u8> {
+        let image = image::RgbaImage::from_fn(size, size, |x, y| image::Rgba([(x % 256) as u8, (y % 256) as u8, ((x * y) % 256) as u8, 255]));
+        let mut bytes = Vec::new();
+        image::DynamicImage::ImageRgba8(image).write_to(&mut Cursor::new(&mut bytes), image::ImageFormat::Png).unwrap();
+        bytes
+    }
+
+    #[test]
+    fn thumbnails_are_smaller_pngs_that_fit_the_size() {
+        let large = png(512);
+        let small = thumbnail(&large, ImageFormat::Png, 64).unwrap();
+        assert_eq!(ImageFormat::detect(&small), Some(ImageFormat::Png));
+        let decoded = image::load_from_memory(&small).unwrap();
+        assert_eq!((decoded.width(), decoded.height()), (64, 64));
+
+        // Nothing to gain for a logo that is already small, or for formats without a decoder
+        assert_eq!(thumbnail(&png(8), ImageFormat::Png, 64), None);
+        assert_eq!(thumbnail(b"<svg/>", ImageFormat::Svg, 64), None);
+        assert_eq!(thumbnail(b"\x89PNG\r\n\x1a\ngarbage", ImageFormat::Png, 64), None);
+    }
+
+    #[tokio::test]
+    async fn cached_logos_are_named_after_their_detected_format() {
+        let (base, _) = serve(vec![("/logo.jpg", ok(GIF)), ("/big.png", ok(&png(256)))]).await;
+        let cache = cache("formats", &[("image_thumbnail_size", "32")]);
+        let file_name = cache.fetch(&format!("{}/logo.jpg", base)).await.unwrap();
+        assert!(file_name.ends_with(".gif"), "{}", file_name);
+        assert_eq!(cache.file(&file_name).unwrap().1, ImageFormat::Gif);
+
+        let file_name = cache.fetch(&format!("{}/big.png", base)).await.unwrap();
+        let (path, format) = cache.file(&file_name).unwrap();
+        assert_eq!(format, ImageFormat::Png);
+        assert_eq!(image::image_dimensions(path).unwrap(), (32, 32));
+        std::fs::remove_dir_all(&cache.dir).unwrap();
+    }
+
+    /// Puts a file of `size` bytes in the cache, saved and last used the given number of hours ago.
+    fn store(cache: &ImageCache, n: u8, size: usize, saved_hours_ago: u64, used_hours_ago: u64) -> String {
+        let key = format!("{:02x}", n).repeat(32);
+        let file_name = format!("{}.png", key);
+        std::fs::create_dir_all(&cache.dir).unwrap();
+        std::fs::write(cache.dir.join(&file_name), vec![0u8; size]).unwrap();
+        let ago = |hours: u64| SystemTime::now() - Duration::from_secs(hours * 3600);
+        cache.index.lock().unwrap().insert(key, CacheEntry {
+            file_name: file_name.clone(),
+            size: size as u64,
+            saved: ago(saved_hours_ago),
+            last_access: ago(used_hours_ago),
+        });
+        file_name
+    }
+
+    #[tokio::test]
+    async fn eviction_removes_old_logos() {
+        let mut cache = cache("evict_age", &[]);
+        cache.max_total_bytes = None;
+        cache.max_age = Some(Duration::from_secs(24 * 3600));
+        let old = store(&cache, 1, 10, 48, 0);
+        let fresh = store(&cache, 2, 10, 1, 1);
+
+        assert_eq!(cache.evict().await, 1);
+        assert!(!cache.dir.join(&old).exists());
+        assert!(cache.file(&old).is_none());
+        assert!(cache.dir.join(&fresh).exists());
+        assert_eq!(cache.stats().bytes, 10);
+        std::fs::remove_dir_all(&cache.dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn eviction_removes_least_recently_used_logos_until_under_the_limit() {
+        let mut cache = cache("evict_size", &[]);
+        cache.max_total_bytes = Some(250);
+        cache.max_age = None;
+        let least_recent = store(&cache, 1, 100, 1, 30);
+        let recent = store(&cache, 2, 100, 40, 1);
+        let less_recent = store(&cache, 3, 100, 2, 20);
+        let used = store(&cache, 4, 100, 3, 25);
+        // Serving a logo counts as using it
+        assert!(cache.file(&used).is_some());
+
+        assert_eq!(cache.evict().await, 2);
+        assert!(!cache.dir.join(&least_recent).exists());
+        assert!(!cache.dir.join(&less_recent).exists());
+        assert!(cache.dir.join(&recent).exists());
+        assert!(cache.dir.join(&used).exists());
+        assert_eq!(cache.stats().bytes, 200);
+
+        // Nothing left to do once the cache fits
+        assert_eq!(cache.evict().await, 0);
+        std::fs::remove_dir_all(&cache.dir).unwrap();
+    }
+
+    #[test]
+    fn only_names_of_cached_files_are_served() {
+        let cache = cache("file_names", &[]);
+        let file_name = store(&cache, 0xab, 10, 0, 0);
+        let key = file_name.trim_end_matches(".png");
+        let (path, format) = cache.file(&file_name).unwrap();
+        assert_eq!(path, cache.dir.join(&file_name));
+        assert_eq!(format, ImageFormat::Png);
+
+        for refused in [
+            format!("{}.gif", key),
+            format!("{}.html", key),
+            format!("{}.png", key.to_uppercase()),
+            format!("{}.png", &key[2..]),
+            format!("{}.png", "z".repeat(64)),
+            format!("{}.png", "cd".repeat(32)),
+            format!("../{}.png", &key[3..]),
+            format!("{}.png/../../config.txt", key),
+            key.to_string(),
+            "../config.txt".to_string(),
+            String::new(),
+        ] {
+            assert!(cache.file(&refused).is_none(), "{}", refused);
+        }
+        std::fs::remove_dir_all(&cache.dir).unwrap();
+    }
+
+    #[test]
+    fn fallback_logos_show_escaped_initials_in_a_stable_colour() {
+        let text = |svg: &str| svg.split("fill=\"#fff\">").nth(1).and_then(|rest| rest.split("</text>").next()).unwrap().to_string();
+        assert_eq!(text(&fallback_svg("BBC One")), "BO");
+        assert_eq!(text(&fallback_svg("das erste HD")), "DE");
+        assert_eq!(text(&fallback_svg("")), "?");
+        assert_eq!(text(&fallback_svg("+++")), "?");
+
+        let svg = fallback_svg("<script>alert(1)</script>");
+        assert_eq!(text(&svg), "SA");
+        assert!(!svg.contains("<script"));
+        let svg = fallback_svg("\"&'<>");
+        assert_eq!(text(&svg), "?");
+        assert!(!svg.contains('&') && !svg.contains('\''));
+
+        assert_eq!(fallback_svg("BBC One"), fallback_svg("BBC One"));
+        assert_ne!(fallback_svg("BBC One"), fallback_svg("BBC Two"));
+    }
+
+    #[tokio::test]
+    async fn fallback_logos_are_cached_and_served() {
+        let cache = cache("fallback", &[]);
+        let file_name = cache.fallback("BBC One").await.unwrap();
+        assert_eq!(cache.fallback("BBC One").await.unwrap(), file_name);
+        let (path, format) = cache.file(&file_name).unwrap();
+        assert_eq!(format, ImageFormat::Svg);
+        assert_eq!(std::fs::read_to_string(path).unwrap(), fallback_svg("BBC One"));
+        std::fs::remove_dir_all(&cache.dir).unwrap();
+    }
+
+    /// A GIF padded to `size` bytes, which is detected but never decoded.
+    fn padded_gif(size: usize) -> Vec<u8> {
+        let mut bytes = GIF.to_vec();
+        bytes.resize(size, 0);
+        bytes
+    }
+
+    #[tokio::test]
+    async fn prefetch_reports_progress_in_downloaded_bytes() {
+        let large = png(256);
+        let (base, requests) = serve(vec![("/a.gif", ok(GIF)), ("/b.png", ok(&large))]).await;
+        let cache = cache("prefetch_progress", &[("image_thumbnail_size", "32")]);
+        let cached = format!("{}/a.gif", base);
+        cache.fetch(&cached).await.unwrap();
+
+        let urls = vec![cached, format!("{}/b.png", base), format!("{}/missing.png", base)];
+        cache.prefetch(urls).await;
+        let progress = cache.prefetch_progress().unwrap();
+        assert!(!progress.running);
+        assert_eq!((progress.total, progress.done, progress.failed), (3, 3, 1));
+        // The thumbnail that was stored is smaller, but the whole PNG was downloaded
+        assert_eq!(progress.bytes, large.len() as u64);
+        assert!(cache.stats().bytes < progress.bytes);
+        assert_eq!(requests.load(Ordering::Relaxed), 3);
+        std::fs::remove_dir_all(&cache.dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn prefetch_stops_when_the_cache_is_full() {
+        let (base, requests) = serve(vec![("/a.gif", ok(&padded_gif(100))), ("/b.gif", ok(&padded_gif(100))), ("/c.gif", ok(&padded_gif(100)))]).await;
+        let mut cache = cache("prefetch_full", &[("image_prefetch_concurrency", "1")]);
+        cache.max_total_bytes = Some(150);
+
+        cache.prefetch(["a", "b", "c"].iter().map(|name| format!("{}/{}.gif", base, name)).collect()).await;
+        let progress = cache.prefetch_progress().unwrap();
+        assert!(!progress.running);
+        assert_eq!((progress.total, progress.done), (3, 2));
+        assert_eq!(requests.load(Ordering::Relaxed), 2);
+        std::fs::remove_dir_all(&cache.dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn prefetch_waits_for_the_rate_limit_before_starting_downloads() {
+        let logo = ok(&padded_gif(500));
+        let (base, _) = serve(vec![("/a.gif", logo.clone()), ("/b.gif", logo.clone()), ("/c.gif", logo)]).await;
+        let mut cache = cache("prefetch_rate", &[("image_prefetch_concurrency", "1")]);
+        cache.prefetch_bytes_per_sec = Some(2000);
+
+        let started = Instant::now();
+        cache.prefetch(["a", "b", "c"].iter().map(|name| format!("{}/{}.gif", base, name)).collect()).await;
+        // The third download may only start once 1000 bytes at 2000 per second are done
+        assert!(started.elapsed() >= Duration::from_millis(500), "{:?}", started.elapsed());
+        assert_eq!(cache.prefetch_progress().unwrap().bytes, 1500);
+
+        // A logo waiting for its turn does not hold up interactive requests for it
+        let (other, _) = serve(vec![("/a.gif", ok(GIF)), ("/d.gif", ok(GIF))]).await;
+        cache.prefetch_bytes_per_sec = Some(1);
+        let prefetch = cache.prefetch(vec![format!("{}/a.gif", other), format!("{}/d.gif", other)]);
+        let fetch = async {
+            tokio::time::sleep(Duration::from_millis(100)).await;
+            tokio::time::timeout(Duration::from_secs(1), cache.fetch(&format!("{}/d.gif", other))).await
+        };
+        let fetched = tokio::select! {
+            fetched = fetch => fetched,
+            _ = prefetch => panic!("The prefetch cannot finish at one byte per second"),
+        };
+        assert!(fetched.unwrap().is_ok());
+        std::fs::remove_dir_all(&cache.dir).unwrap();
+    }
+
+    /// Answers every request with `GIF` after `delay`. Returns the base URL, the number of
+    /// requests and the most that were being answered at once.
+    async fn serve_slowly(delay: Duration) -> (String, Arc<AtomicUsize>, Arc<AtomicUsize>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let base = format!("http://{}", listener.local_addr().unwrap());
+        let (requests, busiest) = (Arc::new(AtomicUsize::new(0)), Arc::new(AtomicUsize::new(0)));
+        let (counter, peak) = (requests.clone(), busiest.clone());
+        let active = Arc::new(AtomicUsize::new(0));
+        tokio::spawn(async move {
+            while let Ok((mut socket, _)) = listener.accept().await {
+                counter.fetch_add(1, Ordering::Relaxed);
+                let (active, peak) = (active.clone(), peak.clone());
+                tokio::spawn(async move {
+                    peak.fetch_max(active.fetch_add(1, Ordering::SeqCst) + 1, Ordering::SeqCst);
+                    let _ = socket.read(&mut [0u8; 2048]).await;
+                    tokio::time::sleep(delay).await;
+                    active.fetch_sub(1, Ordering::SeqCst);
+                    let _ = socket.write_all(&ok(GIF)).await;
+                });
+            }
+        });
+        (base, requests, busiest)
+    }
+
+    #[tokio::test]
+    async fn concurrent_requests_for_a_logo_share_one_download() {
+        let (base, requests, _) = serve_slowly(Duration::from_millis(200)).await;
+        let cache = cache("coalesce", &[]);
+        let url = format!("{}/logo.gif", base);
+
+        let (first, second, third) = tokio::join!(cache.fetch(&url), cache.fetch(&url), cache.fetch(&url));
+        assert_eq!(first.unwrap(), second.unwrap());
+        assert!(third.is_ok());
+        assert_eq!(requests.load(Ordering::Relaxed), 1);
+        assert!(cache.in_flight.lock().unwrap().is_empty());
+
+        cache.fetch(&url).await.unwrap();
+        assert_eq!(requests.load(Ordering::Relaxed), 1);
+        assert_eq!(cache.stats().hits, 1);
+        std::fs::remove_dir_all(&cache.dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn downloads_are_limited_by_the_fetch_concurrency() {
+        let (base, requests, busiest) = serve_slowly(Duration::from_millis(100)).await;
+        let cache = cache("concurrency", &[("image_fetch_concurrency", "2")]);
+        let urls: Vec<String> = (0..6).map(|n| format!("{}/{}.gif", base, n)).collect();
+
+        let results = futures::future::join_all(urls.iter().map(|url| cache.fetch(url))).await;
+        assert!(results.iter().all(Result::is_ok));
+        assert_eq!(requests.load(Ordering::Relaxed), 6);
+        assert_eq!(busiest.load(Ordering::SeqCst), 2);
+        std::fs::remove_dir_all(&cache.dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn failed_logos_back_off_doubling_each_time() {
+        let (base, requests) = serve(Vec::new()).await;
+        let mut cache = cache("backoff", &[]);
+        cache.retry_after = Duration::from_millis(200);
+        let url = format!("{}/missing.png", base);
+        let key = format!("{:x}", Sha256::digest(url.as_bytes()));
+        let retry_in = |cache: &ImageCache| cache.failures.lock().unwrap()[&key].retry_at.saturating_duration_since(Instant::now());
+
+        assert!(cache.fetch(&url).await.is_err());
+        assert_eq!(requests.load(Ordering::Relaxed), 1);
+        assert!(retry_in(&cache) > Duration::from_millis(100));
+        // Not tried again before its backoff has passed
+        assert_eq!(cache.fetch(&url).await.unwrap_err(), "Download failed recently");
+        assert_eq!(requests.load(Ordering::Relaxed), 1);
+
+        tokio::time::sleep(retry_in(&cache)).await;
+        assert!(cache.fetch(&url).await.is_err());
+        assert_eq!(requests.load(Ordering::Relaxed), 2);
+        assert_eq!(cache.failures.lock().unwrap()[&key].attempts, 2);
+        assert!(retry_in(&cache) > Duration::from_millis(300));
+
+        tokio::time::sleep(retry_in(&cache)).await;
+        assert!(cache.fetch(&url).await.is_err());
+        assert_eq!(cache.failures.lock().unwrap()[&key].attempts, 3);
+        assert!(retry_in(&cache) > Duration::from_millis(700));
+        assert_eq!(cache.stats().failures, 1);
+        let _ = std::fs::remove_dir_all(&cache.dir);
+    }
+}
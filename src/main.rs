@@ -1,47 +1,81 @@
-use std::collections::HashMap;
+mod auth;
+mod cli;
+mod epg;
+mod health;
+mod image_cache;
+mod hls;
+mod players;
+mod playlist;
+mod mpv_ipc;
+mod pages;
+mod probe;
+mod profiles;
+mod proxy;
+mod random;
+mod recorder;
+mod scheduler;
+mod server;
+mod renderers;
+mod transcode;
+mod vlc_remote;
+
+use std::collections::{HashMap, HashSet};
 use std::fs::File;
 use std::io::{BufRead, BufReader};
-use std::path::Path;
-use std::process::Command;
-use actix_web::{web, App, HttpServer, Responder, HttpResponse};
-use serde::Serialize;
+use actix_web::{web, App, FromRequest, HttpRequest, HttpServer, Responder, HttpResponse};
+use actix_web::body::{EitherBody, MessageBody};
+use actix_web::cookie::{Cookie, SameSite};
+use actix_web::dev::{Payload, ServiceRequest, ServiceResponse};
+use actix_web::http::header::HeaderValue;
+use actix_web::http::{header, Method, StatusCode};
+use actix_web::middleware::{from_fn, Next};
 use serde::Deserialize;
+use auth::Auth;
+use mpv_ipc::MpvController;
+use pages::{
+    Badge, ChannelListPage, ChannelRow, GuidePage, IndexPage, Link, LoginPage, MediaLabel, PageLink, PlayButton,
+    ImageCachePage, PlayerButtons, PlayerRow, PlayersPage, ProgrammeRow, RecordingRow, RecordingsPage, RemotePage, ScheduleRow,
+    SchedulePage, SeasonLink, SeriesPage, ShowLink, ShowPage, TranscodeRow, TranscodesPage, VariantRow, VariantsPage,
+    WatchPage,
+};
+use players::PlayerRegistry;
+use playlist::{Category, Channel, ChannelKind, Library};
+use epg::Guide;
+use health::{HealthChecker, HealthStatus};
+use image_cache::ImageCache;
+use probe::{MediaProber, ProbeResult};
+use profiles::{PlayerProfiles, PlayerVars};
+use proxy::StreamProxy;
+use recorder::{Recorder, RecordingState};
+use scheduler::{ScheduleState, Scheduler};
+use server::ServerSettings;
+use renderers::Renderers;
+use transcode::{TranscodeManager, TranscodeMode};
+use vlc_remote::VlcRemote;
 use urlencoding::encode;
-use sha2::{Sha256, Digest};
-use tokio::fs::File as TokioFile;
-use tokio::io::AsyncWriteExt;
-use std::io::Read;
 use log::{info, warn, error};
 use std::process::exit;
 use actix_files as fs;
 
-fn read_config() -> HashMap<String, String> {
+fn read_config(path: &str) -> HashMap<String, String> {
     let mut config = HashMap::new();
-    if let Ok(file) = File::open("config.txt") {
+    if let Ok(file) = File::open(path) {
         let reader = BufReader::new(file);
-        for line in reader.lines() {
-            if let Ok(line) = line {
-                let parts: Vec<&str> = line.splitn(2, '=').collect();
-                if parts.len() == 2 {
-                    config.insert(parts[0].trim().to_string(), parts[1].trim().to_string());
-                }
+        for line in reader.lines().map_while(Result::ok) {
+            let parts: Vec<&str> = line.splitn(2, '=').collect();
+            if parts.len() == 2 {
+                config.insert(parts[0].trim().to_string(), parts[1].trim().to_string());
             }
         }
     }
     config
 }
 
-#[derive(Serialize, Clone)]
-struct Channel {
-    name: String,
-    url: String,
-    icon_url: String,
-}
-
-#[derive(Serialize)]
-struct Category {
-    name: String,
-    channels: Vec<Channel>,
+/// The settings of the config file, with those given on the command line taking precedence.
+fn load_config(args: &cli::Args) -> HashMap<String, String> {
+    let mut config = read_config(&args.config_path);
+    config.extend(args.overrides.clone());
+    config
 }
 
 #[derive(Deserialize)]
@@ -50,293 +84,752 @@ struct PaginationQuery {
     page: Option<usize>,
 }
 
-async fn index(categories: web::Data<HashMap<String, Category>>) -> impl Responder {
-    let mut sorted_categories: Vec<(&String, &Category)> = categories.iter().collect();
-    sorted_categories.sort_by(|a, b| a.0.to_lowercase().cmp(&b.0.to_lowercase()));
-
-    let html = format!(r#"
-        <html>
-        <head>
-		<link rel="stylesheet" href="/static/styles.css">
-
-            <title>M3U Playlist</title>
-        </head>
-        <body>
-            <h1>M3U Playlist</h1>
-            <form action="/search" method="get">
-                <input type="text" name="q" placeholder="Search channels...">
-                <input type="submit" value="Search">
-            </form>
-            <ul id="categoryList">
-                {}
-            </ul>
-        </body>
-        </html>
-    "#, 
-        sorted_categories.iter()
-            .filter(|(_, category)| !category.channels.is_empty())
-            .map(|(name, category)| {
-                let display_name = if name.is_empty() { "No Category" } else { name };
-                format!("<li><a href=\"/category/{}\">{} ({})</a></li>", encode(name), display_name, category.channels.len())
-            })
-            .collect::<String>()
-    );
+#[derive(Deserialize, Default, Clone)]
+struct ListFilter {
+    online: Option<bool>,
+    min_height: Option<u32>,
+    codec: Option<String>,
+}
 
-    HttpResponse::Ok()
-        .content_type("text/html; charset=utf-8")
-        .body(html)
+/// Everything besides the channel itself that goes into rendering a list row, extracted from
+/// the app data in one go.
+struct RowContext {
+    health: web::Data<HealthChecker>,
+    media: web::Data<MediaProber>,
+    profiles: web::Data<PlayerProfiles>,
+    vlc: web::Data<VlcRemote>,
+    renderers: web::Data<Renderers>,
 }
 
+impl FromRequest for RowContext {
+    type Error = actix_web::Error;
+    type Future = std::future::Ready<Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        std::future::ready(Ok(RowContext {
+            health: app_data(req),
+            media: app_data(req),
+            profiles: app_data(req),
+            vlc: app_data(req),
+            renderers: app_data(req),
+        }))
+    }
+}
 
-async fn cache_image(url: &str) -> Result<String, Box<dyn std::error::Error>> {
-    let cache_dir = "image_cache";
-    tokio::fs::create_dir_all(cache_dir).await?;
+/// Shared state registered in `main`, for extractors that bundle several of them.
+fn app_data<T: 'static>(req: &HttpRequest) -> web::Data<T> {
+    req.app_data::<web::Data<T>>().expect("Registered in main").clone()
+}
 
-    let mut hasher = Sha256::new();
-    hasher.update(url);
-    let filename = format!("{:x}.jpg", hasher.finalize());
-    let cache_path = Path::new(cache_dir).join(&filename);
+/// A channel list to render, with each channel paired with the category shown next to it, if any.
+struct ChannelList<'a> {
+    title: String,
+    heading: String,
+    list_id: &'static str,
+    base_url: String,
+    channels: Vec<(Option<&'a str>, &'a Channel)>,
+}
 
-    if !cache_path.exists() {
-        let response = reqwest::get(url).await?;
-        
-        // Check if the content type is an image
-        if let Some(content_type) = response.headers().get(reqwest::header::CONTENT_TYPE) {
-            if !content_type.to_str()?.starts_with("image/") {
-                return Err("Downloaded content is not an image".into());
+impl ListFilter {
+    fn matches(&self, channel: &Channel, info: &RowContext) -> bool {
+        if self.online.unwrap_or(false) && !info.health.is_online(&channel.id) {
+            return false;
+        }
+        if self.min_height.is_none() && self.codec.is_none() {
+            return true;
+        }
+        match info.media.media_info(&channel.id) {
+            Some(media) => {
+                self.min_height.is_none_or(|min| media.height.unwrap_or(0) >= min)
+                    && self.codec.as_ref().is_none_or(|codec| media.has_codec(codec))
             }
+            None => false,
         }
+    }
 
-        let bytes = response.bytes().await?;
-        
-        // Check if the downloaded content is not empty
-        if bytes.is_empty() {
-            return Err("Downloaded image is empty".into());
+    /// Appends the active filters to `url` so they survive pagination.
+    fn apply_to(&self, url: &str) -> String {
+        let mut url = url.to_string();
+        if self.online.unwrap_or(false) {
+            url = format!("{}{}online=true", url, query_separator(&url));
         }
-
-        let mut file = TokioFile::create(&cache_path).await?;
-        file.write_all(&bytes).await?;
-        file.flush().await?;
+        if let Some(min_height) = self.min_height {
+            url = format!("{}{}min_height={}", url, query_separator(&url), min_height);
+        }
+        if let Some(codec) = &self.codec {
+            url = format!("{}{}codec={}", url, query_separator(&url), encode(codec));
+        }
+        url
     }
 
-    Ok(format!("/image_cache/{}", filename))
+    fn filter_links(&self, url: &str) -> Vec<Link> {
+        let mut links = Vec::new();
+        let online = self.online.unwrap_or(false);
+        links.push(Link {
+            url: ListFilter { online: Some(!online), ..self.clone() }.apply_to(url),
+            label: if online { "Show offline channels" } else { "Online only" }.to_string(),
+        });
+        for (label, min_height) in [("720p+", 720), ("1080p+", 1080), ("4K", 2160)] {
+            links.push(Link {
+                url: ListFilter { min_height: Some(min_height), ..self.clone() }.apply_to(url),
+                label: label.to_string(),
+            });
+        }
+        for (label, codec) in [("H.264", "h264"), ("HEVC", "hevc")] {
+            links.push(Link {
+                url: ListFilter { codec: Some(codec.to_string()), ..self.clone() }.apply_to(url),
+                label: label.to_string(),
+            });
+        }
+        if online || self.min_height.is_some() || self.codec.is_some() {
+            links.push(Link { url: url.to_string(), label: "Clear filters".to_string() });
+        }
+        links
+    }
 }
 
+fn query_separator(url: &str) -> char {
+    if url.contains('?') { '&' } else { '?' }
+}
 
+fn category_links(categories: &HashMap<String, Category>, base_url: &str) -> Vec<Link> {
+    let mut sorted_categories: Vec<(&String, &Category)> = categories.iter().collect();
+    sorted_categories.sort_by_key(|(name, _)| name.to_lowercase());
+
+    sorted_categories.iter()
+        .filter(|(_, category)| !category.channels.is_empty())
+        .map(|(name, category)| {
+            let display_name = if name.is_empty() { "No Category" } else { name };
+            Link {
+                url: format!("{}/{}", base_url, encode(name)),
+                label: format!("{} ({})", display_name, category.channels.len()),
+            }
+        })
+        .collect()
+}
 
+async fn index(library: web::Data<Library>, auth: web::Data<Auth>) -> impl Responder {
+    pages::render(&IndexPage {
+        logout: auth.is_enabled(),
+        live: category_links(&library.live, "/category"),
+        movies: category_links(&library.movies, "/movies"),
+        show_count: library.shows.len(),
+        episode_count: library.shows.values().map(|show| show.episodes.len()).sum(),
+    })
+}
 
-async fn cached_image(path: web::Path<String>) -> impl Responder {
-    let cache_dir = "image_cache";
-    let file_path = Path::new(cache_dir).join(path.into_inner());
 
-    if file_path.exists() {
-        match File::open(&file_path) {
-            Ok(mut file) => {
-                let mut buffer = Vec::new();
-                if file.read_to_end(&mut buffer).is_ok() {
-                    HttpResponse::Ok()
-                        .content_type("image/jpeg")
-                        .body(actix_web::web::Bytes::from(buffer))
-                } else {
-                    HttpResponse::InternalServerError().finish()
-                }
-            }
-            Err(_) => HttpResponse::InternalServerError().finish(),
+fn is_authenticated(req: &HttpRequest, auth: &Auth) -> bool {
+    if let Some(cookie) = req.cookie(auth::SESSION_COOKIE) {
+        if auth.session_user(cookie.value()).is_some() {
+            return true;
         }
+    }
+    req.headers().get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .is_some_and(|token| auth.is_api_token(token.trim()))
+}
+
+/// Rejects requests without a valid session cookie or API token when authentication is
+/// configured. Static assets and the login page stay public. Pages opened in the browser are
+/// redirected to the login form, everything else gets a 401.
+async fn require_login(
+    req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<EitherBody<impl MessageBody>>, actix_web::Error> {
+    let auth = req.app_data::<web::Data<Auth>>().expect("Auth is registered").clone();
+    let path = req.path();
+    let public = path == "/static" || path.starts_with("/static/") || path == "/login";
+    if !auth.is_enabled() || public || is_authenticated(req.request(), &auth) {
+        return next.call(req).await.map(ServiceResponse::map_into_left_body);
+    }
+
+    let wants_page = req.method() == Method::GET && req.headers().get(header::ACCEPT)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|accept| accept.contains("text/html"));
+    let response = if wants_page {
+        let target = req.uri().path_and_query().map(|pq| pq.as_str()).unwrap_or("/");
+        HttpResponse::SeeOther()
+            .insert_header((header::LOCATION, format!("/login?next={}", encode(target))))
+            .finish()
     } else {
-        HttpResponse::NotFound().finish()
+        HttpResponse::Unauthorized()
+            .insert_header((header::WWW_AUTHENTICATE, "Bearer"))
+            .body("Authentication required")
+    };
+    Ok(req.into_response(response).map_into_right_body())
+}
+
+/// Whether a request was sent by a page of this server. Browsers add `Origin`, or at least
+/// `Referer`, to cross-site POSTs, so requests with neither come from scripts and are allowed.
+fn is_same_origin(req: &HttpRequest) -> bool {
+    let headers = req.headers();
+    let Some(source) = headers.get(header::ORIGIN).or_else(|| headers.get(header::REFERER)) else {
+        return true;
+    };
+    let connection = req.connection_info();
+    // Sandboxed frames send "null", which never matches
+    source.to_str().ok()
+        .and_then(|source| source.split_once("://"))
+        .and_then(|(_, rest)| rest.split('/').next())
+        .is_some_and(|host| host.eq_ignore_ascii_case(connection.host()))
+}
+
+/// Refuses requests that change something (play, record, stop...) when another web site made
+/// the browser send them.
+async fn reject_cross_site(
+    req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<EitherBody<impl MessageBody>>, actix_web::Error> {
+    let safe_method = matches!(*req.method(), Method::GET | Method::HEAD | Method::OPTIONS);
+    if safe_method || is_same_origin(req.request()) {
+        return next.call(req).await.map(ServiceResponse::map_into_left_body);
     }
+    warn!("Refused cross-site {} {}", req.method(), req.path());
+    let response = HttpResponse::Forbidden().body("Cross-site request refused");
+    Ok(req.into_response(response).map_into_right_body())
 }
 
-async fn lazy_load_image(query: web::Query<HashMap<String, String>>) -> impl Responder {
-    if let Some(url) = query.get("url") {
-        if url.is_empty() || url == "/static/placeholder.png" {
-            // Return the placeholder image if the URL is empty or already the placeholder
-            return HttpResponse::Ok()
-                .content_type("text/plain")
-                .body("/static/placeholder.png");
+#[derive(Deserialize)]
+struct LoginQuery {
+    next: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct LoginForm {
+    username: String,
+    password: String,
+}
+
+/// Where to go after logging in. Only paths on this server are followed: browsers ignore tabs
+/// and newlines in `Location` and treat `\\` like `/`, so `/\t/evil.com` would leave the site.
+fn login_target(query: &LoginQuery) -> &str {
+    let same_origin = |next: &str| {
+        let base = reqwest::Url::parse("http://localhost/").unwrap();
+        next.starts_with('/')
+            && !next.starts_with("//")
+            && !next.chars().any(|c| c == '\\' || c.is_whitespace() || c.is_control())
+            && base.join(next).is_ok_and(|url| url.origin() == base.origin())
+    };
+    match query.next.as_deref() {
+        Some(next) if same_origin(next) => next,
+        _ => "/",
+    }
+}
+
+async fn login_page(query: web::Query<LoginQuery>) -> impl Responder {
+    pages::render(&LoginPage { next: login_target(&query).to_string(), failed: false })
+}
+
+async fn login(
+    req: HttpRequest,
+    query: web::Query<LoginQuery>,
+    form: web::Form<LoginForm>,
+    auth: web::Data<Auth>
+) -> impl Responder {
+    let form = form.into_inner();
+    let username = form.username.clone();
+    // Password hashing is deliberately slow, keep it off the async workers
+    let session = {
+        let auth = auth.clone();
+        web::block(move || auth.login(&form.username, &form.password)).await
+    };
+    let Ok(Some(token)) = session else {
+        warn!(
+            "Failed login for {} from {}",
+            username,
+            req.connection_info().realip_remote_addr().unwrap_or("unknown address")
+        );
+        let mut response = pages::render(&LoginPage { next: login_target(&query).to_string(), failed: true });
+        *response.status_mut() = StatusCode::UNAUTHORIZED;
+        return response;
+    };
+
+    info!("{} logged in", username);
+    let max_age = auth.session_lifetime().as_secs() as i64;
+    let cookie = Cookie::build(auth::SESSION_COOKIE, token)
+        .path("/")
+        .http_only(true)
+        .same_site(SameSite::Lax)
+        .secure(req.connection_info().scheme() == "https")
+        .max_age(actix_web::cookie::time::Duration::seconds(max_age))
+        .finish();
+    HttpResponse::SeeOther()
+        .cookie(cookie)
+        .insert_header((header::LOCATION, login_target(&query)))
+        .finish()
+}
+
+async fn logout(req: HttpRequest, auth: web::Data<Auth>) -> impl Responder {
+    if let Some(cookie) = req.cookie(auth::SESSION_COOKIE) {
+        auth.logout(cookie.value());
+    }
+    let mut removal = Cookie::build(auth::SESSION_COOKIE, "").path("/").finish();
+    removal.make_removal();
+    HttpResponse::SeeOther()
+        .cookie(removal)
+        .insert_header((header::LOCATION, "/login"))
+        .finish()
+}
+
+/// Streams an image, answering conditional requests from its ETag and Last-Modified.
+async fn serve_image(req: &HttpRequest, file_path: &std::path::Path, content_type: &str, cache_control: &'static str) -> HttpResponse {
+    match fs::NamedFile::open_async(file_path).await {
+        Ok(file) => {
+            let mut response = file
+                .set_content_type(content_type.parse().unwrap())
+                .into_response(req);
+            let headers = response.headers_mut();
+            headers.insert(header::CACHE_CONTROL, HeaderValue::from_static(cache_control));
+            headers.insert(header::X_CONTENT_TYPE_OPTIONS, HeaderValue::from_static("nosniff"));
+            // Keeps scripts in an SVG logo from running when it is opened directly
+            headers.insert(
+                header::CONTENT_SECURITY_POLICY,
+                HeaderValue::from_static("default-src 'none'; style-src 'unsafe-inline'; sandbox"),
+            );
+            response
         }
+        Err(_) => HttpResponse::NotFound().finish(),
+    }
+}
 
-        // Try to cache the image and return the cached URL
-        match cache_image(url).await {
-            Ok(cached_url) => HttpResponse::Ok()
-                .content_type("text/plain")
-                .body(cached_url),
-            Err(e) => {
-                // If caching fails, log the error and return the placeholder image URL
-                error!("Failed to cache image: {}", e);
-                HttpResponse::Ok()
-                    .content_type("text/plain")
-                    .body("/static/placeholder.png")
+/// Logos are downloaded again when they expire from the cache, so browsers revalidate them daily.
+const LOGO_CACHE_CONTROL: &str = "private, max-age=86400";
+
+async fn cached_image(
+    req: HttpRequest,
+    path: web::Path<String>,
+    images: web::Data<ImageCache>
+) -> HttpResponse {
+    match images.file(&path.into_inner()) {
+        Some((file_path, format)) => serve_image(&req, &file_path, format.mime_type(), LOGO_CACHE_CONTROL).await,
+        None => HttpResponse::NotFound().finish(),
+    }
+}
+
+/// A channel's logo from the image cache, downloaded on the first request. Channels without a
+/// logo, or whose logo cannot be downloaded, get one generated from their name.
+async fn logo(
+    req: HttpRequest,
+    path: web::Path<String>,
+    library: web::Data<Library>,
+    images: web::Data<ImageCache>
+) -> HttpResponse {
+    let Some(channel) = library.channel(&path.into_inner()) else {
+        return HttpResponse::NotFound().finish();
+    };
+    let has_icon = library.has_icon(&channel.icon_url);
+    if has_icon {
+        if let Some((file_path, format)) = images.fetch(&channel.icon_url).await.ok().and_then(|file_name| images.file(&file_name)) {
+            return serve_image(&req, &file_path, format.mime_type(), LOGO_CACHE_CONTROL).await;
+        }
+    }
+    // Cached briefly when the logo failed, so it shows up once the download is retried
+    let cache_control = if has_icon { "private, max-age=300" } else { LOGO_CACHE_CONTROL };
+    match images.fallback(&channel.name).await {
+        Ok(file_name) => {
+            if let Some((file_path, format)) = images.file(&file_name) {
+                return serve_image(&req, &file_path, format.mime_type(), cache_control).await;
             }
         }
-    } else {
-        HttpResponse::BadRequest().finish()
+        Err(e) => warn!("Failed to generate a logo for {}: {}", channel.name, e),
     }
+    serve_image(&req, std::path::Path::new("./static/placeholder.png"), "image/png", cache_control).await
+}
+
+async fn image_cache_page(images: web::Data<ImageCache>) -> impl Responder {
+    let stats = images.stats();
+    let requests = stats.hits + stats.misses;
+    pages::render(&ImageCachePage {
+        entries: stats.entries,
+        size: format_size(stats.bytes),
+        max_size: stats.max_bytes.map(format_size),
+        max_age_days: stats.max_age.map(|age| age.as_secs() / (24 * 3600)),
+        hits: stats.hits,
+        misses: stats.misses,
+        hit_rate: (requests > 0).then(|| stats.hits * 100 / requests),
+        failures: stats.failures,
+        prefetch: images.prefetch_progress().map(|progress| format!(
+            "{} {} of {} logos, {} failed, {} downloaded.",
+            if progress.running { "Prefetching" } else { "Prefetched" },
+            progress.done,
+            progress.total,
+            progress.failed,
+            format_size(progress.bytes),
+        )),
+    })
+}
+
+async fn clear_image_cache(images: web::Data<ImageCache>) -> impl Responder {
+    let removed = images.clear().await;
+    info!("Cleared {} images from the cache", removed);
+    HttpResponse::Ok().finish()
 }
 
+fn health_badge(channel: &Channel, health: &HealthChecker) -> Badge {
+    let (class, label, title) = match health.get(&channel.id) {
+        Some(record) if record.status == HealthStatus::Online => ("online", "online", record.summary()),
+        Some(record) => ("offline", "offline", record.summary()),
+        None => ("unknown", "unchecked", "Not checked yet".to_string()),
+    };
+    Badge { class, label, title }
+}
 
+fn media_label(channel: &Channel, media: &MediaProber) -> Option<MediaLabel> {
+    if !media.is_enabled() {
+        return None;
+    }
+    let (class, label) = match media.get(&channel.id) {
+        Some(ProbeResult::Ok(info)) => ("media-info", info.summary()),
+        Some(ProbeResult::Failed { error, .. }) => ("media-info failed", error),
+        None => ("media-info unknown", "probe".to_string()),
+    };
+    Some(MediaLabel { class, label })
+}
 
+fn channel_row<'a>(channel: &'a Channel, category: Option<&'a str>, info: &RowContext) -> ChannelRow<'a> {
+    ChannelRow {
+        channel,
+        category,
+        health: health_badge(channel, &info.health),
+        media: media_label(channel, &info.media),
+        is_hls: hls::is_hls_url(&channel.url),
+        buttons: player_buttons(&info.profiles, &info.vlc, &info.renderers, &channel.id, None),
+    }
+}
 
+fn player_buttons(
+    profiles: &PlayerProfiles,
+    vlc: &VlcRemote,
+    renderers: &Renderers,
+    channel_id: &str,
+    variant: Option<usize>,
+) -> PlayerButtons {
+    let button = |player: &str, label: &str, enqueue: bool| PlayButton {
+        player: player.to_string(),
+        label: label.to_string(),
+        enqueue,
+    };
+    let mut buttons: Vec<PlayButton> = profiles.iter()
+        .map(|profile| button(&profile.name, &profile.label, false))
+        .collect();
+    if vlc.is_enabled() {
+        // A remote VLC can be driven without a local vlc_path
+        if profiles.get("vlc").is_none() {
+            buttons.push(button("vlc", "Play with VLC", false));
+        }
+        buttons.push(button("vlc", "Queue in VLC", true));
+    }
+    PlayerButtons {
+        channel_id: channel_id.to_string(),
+        variant,
+        buttons,
+        cast: renderers.is_enabled(),
+    }
+}
 
+/// Renders one page of a channel list.
+fn channel_page(list: ChannelList, query: &PaginationQuery, filter: &ListFilter, info: &RowContext) -> HttpResponse {
+    let channels: Vec<&(Option<&str>, &Channel)> = list.channels.iter().filter(|(_, c)| filter.matches(c, info)).collect();
+    let filters = filter.filter_links(&list.base_url);
+    let base_url = filter.apply_to(&list.base_url);
+
+    let page_size = query.page_size.unwrap_or(100).max(1);
+    let page = query.page.unwrap_or(1).max(1);
+
+    let total_channels = channels.len();
+    let total_pages = total_channels.div_ceil(page_size);
+
+    let start_index = std::cmp::min((page - 1) * page_size, total_channels);
+    let end_index = std::cmp::min(start_index + page_size, total_channels);
+
+    pages::render(&ChannelListPage {
+        title: list.title,
+        heading: list.heading,
+        list_id: list.list_id,
+        first: start_index + 1,
+        last: end_index,
+        total: total_channels,
+        pagination: pagination(page, total_pages, page_size, &base_url),
+        sep: query_separator(&base_url),
+        base_url,
+        filters,
+        rows: channels[start_index..end_index].iter()
+            .map(|(category, channel)| channel_row(channel, *category, info))
+            .collect(),
+    })
+}
 
+fn category_response(
+    categories: &HashMap<String, Category>,
+    encoded_name: String,
+    base_url: &str,
+    title_suffix: &str,
+    query: &PaginationQuery,
+    filter: &ListFilter,
+    info: &RowContext,
+) -> HttpResponse {
+    let Ok(category_name) = urlencoding::decode(&encoded_name).map(|name| name.into_owned()) else {
+        return HttpResponse::BadRequest().body("Invalid category name");
+    };
 
+    if let Some(category) = categories.get(&category_name) {
+        let title = format!("{} {}", category.name, title_suffix);
+        let list = ChannelList {
+            heading: title.clone(),
+            title,
+            list_id: "channelList",
+            base_url: format!("{}/{}", base_url, encode(&category_name)),
+            channels: category.channels.iter().map(|c| (None, c)).collect(),
+        };
+        channel_page(list, query, filter, info)
+    } else {
+        HttpResponse::NotFound().finish()
+    }
+}
 
 async fn category(
     path: web::Path<String>,
     query: web::Query<PaginationQuery>,
-    categories: web::Data<HashMap<String, Category>>
+    filter: web::Query<ListFilter>,
+    library: web::Data<Library>,
+    info: RowContext
 ) -> impl Responder {
-    let category_name = urlencoding::decode(&path.into_inner()).expect("Failed to decode category name").into_owned();
-    
-    if let Some(category) = categories.get(&category_name) {
-        let page_size = query.page_size.unwrap_or(100);
-        let page = query.page.unwrap_or(1);
-        
-        let total_channels = category.channels.len();
-        let total_pages = (total_channels + page_size - 1) / page_size;
-        
-        let start_index = (page - 1) * page_size;
-        let end_index = std::cmp::min(start_index + page_size, total_channels);
-        
-let channels_html = category.channels[start_index..end_index].iter().map(|channel| {
-    format!(r#"
-        <li>
-            <img src="/static/placeholder.png" data-src="/lazy_load_image?url={}" alt="{}" class="thumbnail lazyload">
-            <a href="{}" target="_blank">{}</a>
-            <button onclick="playChannel('{}', 'mpv')">Play with mpv</button>
-            <button onclick="playChannel('{}', 'vlc')">Play with VLC</button>
-        </li>
-    "#, encode(&channel.icon_url), channel.name, channel.url, channel.name, channel.url, channel.url)
-}).collect::<String>();
-
-
-        let pagination_html = generate_pagination_html(page, total_pages, page_size, &category_name);
-
-    let html = format!(r#"
-        <html>
-        <head>
-            <link rel="stylesheet" href="/static/styles.css">
-            <title>{} Channels</title>
-            <script src="/static/script.js"></script>
-        </head>
-        <body>
-            <h1>{} Channels</h1>
-            <p>Showing {}-{} of {} channels</p>
-            <div>
-                <a href="/category/{}?page_size=100">100</a> |
-                <a href="/category/{}?page_size=1000">1000</a> |
-                <a href="/category/{}?page_size=10000">10000</a> |
-                <a href="/category/{}?page_size={}">All</a>
-            </div>
-            <div class="view-controls">
-                <button onclick="setView('list')">List View</button>
-                <button onclick="setView('grid')">Grid View</button>
-            </div>
-            {}
-            <ul id="channelList" class="list-view">
-                {}
-            </ul>
-            {}
-        </body>
-        </html>
-    "#,
-            category.name,
-            category.name,
-            start_index + 1,
-            end_index,
-            total_channels,
-            encode(&category_name),
-            encode(&category_name),
-            encode(&category_name),
-            encode(&category_name),
-            total_channels,
-            pagination_html,
-            channels_html,
-            pagination_html
-        );
+    category_response(&library.live, path.into_inner(), "/category", "Channels", &query, &filter, &info)
+}
+
+async fn movie_category(
+    path: web::Path<String>,
+    query: web::Query<PaginationQuery>,
+    filter: web::Query<ListFilter>,
+    library: web::Data<Library>,
+    info: RowContext
+) -> impl Responder {
+    category_response(&library.movies, path.into_inner(), "/movies", "Movies", &query, &filter, &info)
+}
+
+async fn series_index(
+    query: web::Query<PaginationQuery>,
+    library: web::Data<Library>
+) -> impl Responder {
+    let mut shows: Vec<&playlist::Show> = library.shows.values().collect();
+    shows.sort_by_key(|show| show.name.to_lowercase());
+
+    let page_size = query.page_size.unwrap_or(100).max(1);
+    let page = query.page.unwrap_or(1).max(1);
+    let total_shows = shows.len();
+    let total_pages = total_shows.div_ceil(page_size);
+    let start_index = std::cmp::min((page - 1) * page_size, total_shows);
+    let end_index = std::cmp::min(start_index + page_size, total_shows);
+
+    pages::render(&SeriesPage {
+        first: start_index + 1,
+        last: end_index,
+        total: total_shows,
+        pagination: pagination(page, total_pages, page_size, "/series"),
+        shows: shows[start_index..end_index].iter()
+            .map(|show| ShowLink {
+                url: format!("/series/{}", encode(&show.name)),
+                name: show.name.clone(),
+                seasons: show.seasons().len(),
+                episodes: show.episodes.len(),
+            })
+            .collect(),
+    })
+}
 
-        HttpResponse::Ok()
-            .content_type("text/html; charset=utf-8")
-            .body(html)
+async fn show(
+    path: web::Path<String>,
+    library: web::Data<Library>
+) -> impl Responder {
+    let Ok(show_name) = urlencoding::decode(&path.into_inner()).map(|name| name.into_owned()) else {
+        return HttpResponse::BadRequest().body("Invalid show name");
+    };
+
+    if let Some(show) = library.shows.get(&show_name) {
+        pages::render(&ShowPage {
+            name: show.name.clone(),
+            seasons: show.seasons().iter()
+                .map(|season| SeasonLink {
+                    url: format!("/series/{}/{}", encode(&show.name), season),
+                    number: *season,
+                    episodes: show.season(*season).len(),
+                })
+                .collect(),
+        })
     } else {
         HttpResponse::NotFound().finish()
     }
 }
 
-fn generate_pagination_html(current_page: usize, total_pages: usize, page_size: usize, base_url: &str) -> String {
-    let mut pagination_html = String::new();
-    
+async fn season(
+    path: web::Path<(String, u32)>,
+    query: web::Query<PaginationQuery>,
+    filter: web::Query<ListFilter>,
+    library: web::Data<Library>,
+    info: RowContext
+) -> impl Responder {
+    let (show_name, season) = path.into_inner();
+    let Ok(show_name) = urlencoding::decode(&show_name).map(|name| name.into_owned()) else {
+        return HttpResponse::BadRequest().body("Invalid show name");
+    };
+
+    match library.shows.get(&show_name) {
+        Some(show) => {
+            let episodes = show.season(season);
+            if episodes.is_empty() {
+                return HttpResponse::NotFound().finish();
+            }
+            let title = format!("{} - Season {}", show.name, season);
+            let list = ChannelList {
+                heading: title.clone(),
+                title,
+                list_id: "channelList",
+                base_url: format!("/series/{}/{}", encode(&show.name), season),
+                channels: episodes.iter().map(|e| (None, &e.channel)).collect(),
+            };
+            channel_page(list, &query, &filter, &info)
+        }
+        None => HttpResponse::NotFound().finish(),
+    }
+}
+
+fn pagination(current_page: usize, total_pages: usize, page_size: usize, base_url: &str) -> Vec<PageLink> {
+    let mut links = Vec::new();
+    let sep = query_separator(base_url);
+    let link = |label: String, page: usize| PageLink {
+        label,
+        url: Some(format!("{}{}page_size={}&page={}", base_url, sep, page_size, page)),
+    };
+
     if total_pages > 1 {
-        pagination_html.push_str("<div class='pagination'>");
-        
         if current_page > 1 {
-            pagination_html.push_str(&format!("<a href='{}?page_size={}&page={}'>Previous</a> ", 
-                base_url, page_size, current_page - 1));
+            links.push(link("Previous".to_string(), current_page - 1));
         }
-        
+
         let start_page = std::cmp::max(1, current_page.saturating_sub(2));
         let end_page = std::cmp::min(total_pages, start_page + 4);
-        
+
         for i in start_page..=end_page {
             if i == current_page {
-                pagination_html.push_str(&format!("<span>{}</span> ", i));
+                links.push(PageLink { label: i.to_string(), url: None });
             } else {
-                pagination_html.push_str(&format!("<a href='{}?page_size={}&page={}'>{}</a> ", 
-                    base_url, page_size, i, i));
+                links.push(link(i.to_string(), i));
             }
         }
-        
+
         if current_page < total_pages {
-            pagination_html.push_str(&format!("<a href='{}?page_size={}&page={}'>Next</a>", 
-                base_url, page_size, current_page + 1));
+            links.push(link("Next".to_string(), current_page + 1));
+        }
+    }
+
+    links
+}
+
+async fn variant_url(client: &reqwest::Client, url: String, variant: Option<usize>) -> Result<String, HttpResponse> {
+    let Some(index) = variant else { return Ok(url) };
+    match hls::fetch_master(client, &url).await {
+        Ok(Some(master)) => match master.variants.get(index) {
+            Some(variant) => Ok(variant.url.clone()),
+            None => Err(HttpResponse::BadRequest().body("Invalid variant specified")),
+        },
+        Ok(None) => Err(HttpResponse::BadRequest().body("Stream is not an HLS master playlist")),
+        Err(e) => {
+            error!("Failed to fetch HLS master {}: {}", url, e);
+            Err(HttpResponse::BadGateway().body("Failed to fetch HLS master playlist"))
         }
-        
-        pagination_html.push_str("</div>");
     }
-    
-    pagination_html
 }
 
+#[derive(Deserialize)]
+struct PlayQuery {
+    variant: Option<usize>,
+    /// Append to VLC's playlist instead of replacing the current stream (VLC remote only).
+    enqueue: Option<bool>,
+}
 
+/// The ways a channel can be played on the server, extracted from the app data in one go.
+struct Playback {
+    profiles: web::Data<PlayerProfiles>,
+    players: web::Data<PlayerRegistry>,
+    mpv: web::Data<MpvController>,
+    vlc: web::Data<VlcRemote>,
+}
+
+impl FromRequest for Playback {
+    type Error = actix_web::Error;
+    type Future = std::future::Ready<Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        std::future::ready(Ok(Playback {
+            profiles: app_data(req),
+            players: app_data(req),
+            mpv: app_data(req),
+            vlc: app_data(req),
+        }))
+    }
+}
 
 async fn play(
     path: web::Path<(String, String)>,
-    config: web::Data<HashMap<String, String>>
+    query: web::Query<PlayQuery>,
+    library: web::Data<Library>,
+    client: web::Data<reqwest::Client>,
+    playback: Playback
 ) -> impl Responder {
-    let (player, url) = path.into_inner();
-    let url = urlencoding::decode(&url).expect("Failed to decode URL").into_owned();
-    
-    let player_path = match player.as_str() {
-        "mpv" => config.get("mpv_path").cloned(),
-        "vlc" => config.get("vlc_path").cloned(),
-        _ => return HttpResponse::BadRequest().body("Invalid player specified"),
+    let Playback { profiles, players, mpv, vlc } = playback;
+    let (player, id) = path.into_inner();
+    let Some(channel) = library.channel(&id) else {
+        return HttpResponse::NotFound().body("Channel not found");
     };
+    let url = match variant_url(&client, channel.url.clone(), query.variant).await {
+        Ok(url) => url,
+        Err(response) => return response,
+    };
+
+    let vars = PlayerVars::for_channel(&url, Some(channel));
 
-    let player_path = match player_path {
-        Some(path) => path,
-        None => return HttpResponse::InternalServerError().body(format!("{} path not specified in config", player)),
+    // Hand the stream to the VLC HTTP interface, which may also be on another machine
+    if player == "vlc" && vlc.is_enabled() {
+        let enqueue = query.enqueue.unwrap_or(false);
+        return match vlc.play(Some(&channel.id), &vars, enqueue, &players).await {
+            Ok(()) => HttpResponse::Ok().json(serde_json::json!({ "remote": true })),
+            Err(e) => {
+                error!("Failed to play through the VLC HTTP interface: {}", e);
+                HttpResponse::InternalServerError().body(e)
+            }
+        };
+    }
+
+    let profile = match profiles.get(&player) {
+        Some(profile) => profile,
+        None => return HttpResponse::BadRequest().body("Invalid player specified"),
     };
 
-    let mut command = Command::new(&player_path);
-    command.arg(&url);
+    // Reuse the running mpv instead of opening another window
+    if player == "mpv" && mpv.is_enabled() {
+        return match mpv.play(Some(&channel.id), &vars, &players).await {
+            Ok(()) => HttpResponse::Ok().json(serde_json::json!({ "remote": true })),
+            Err(e) => {
+                error!("Failed to play through mpv IPC: {}", e);
+                HttpResponse::InternalServerError().body(e)
+            }
+        };
+    }
+
+    let mut command = profile.command(&vars);
 
     match command.spawn() {
-        Ok(mut child) => {
-            match child.wait() {
-                Ok(status) => {
-                    if status.success() {
-                        HttpResponse::Ok().finish()
-                    } else {
-                        warn!("{} exited with non-zero status", player);
-                        HttpResponse::InternalServerError().body(format!("{} exited with non-zero status", player))
-                    }
-                }
-                Err(e) => {
-                    error!("Failed to wait for {} process: {}", player, e);
-                    HttpResponse::InternalServerError().body(format!("Failed to wait for {} process", player))
-                }
-            }
+        Ok(child) => {
+            let id = players.register(&player, &url, child);
+
+            // Players that fail straight away (bad URL, missing libraries) are logged and listed on /players
+            tokio::spawn(async move {
+                tokio::time::sleep(std::time::Duration::from_secs(2)).await;
+                players.check();
+            });
+            HttpResponse::Ok().json(serde_json::json!({ "id": id }))
         }
         Err(e) => {
             error!("Failed to execute {}: {}", player, e);
@@ -346,119 +839,751 @@ async fn play(
 }
 
 
+async fn list_renderers(renderers: web::Data<Renderers>) -> impl Responder {
+    HttpResponse::Ok().json(renderers.list())
+}
 
+async fn discover_renderers(renderers: web::Data<Renderers>) -> impl Responder {
+    if !renderers.discovery_enabled() {
+        return HttpResponse::NotFound().body("DLNA discovery is not enabled");
+    }
+    match renderers.discover().await {
+        Ok(_) => HttpResponse::Ok().json(renderers.list()),
+        Err(e) => {
+            error!("SSDP discovery failed: {}", e);
+            HttpResponse::InternalServerError().body(e)
+        }
+    }
+}
 
+async fn cast(
+    path: web::Path<(String, String)>,
+    query: web::Query<PlayQuery>,
+    library: web::Data<Library>,
+    client: web::Data<reqwest::Client>,
+    renderers: web::Data<Renderers>
+) -> impl Responder {
+    let (renderer, id) = path.into_inner();
+    if !renderers.list().iter().any(|r| r.id == renderer) {
+        return HttpResponse::NotFound().body("Unknown renderer");
+    }
+    let Some(channel) = library.channel(&id) else {
+        return HttpResponse::NotFound().body("Channel not found");
+    };
+    let url = match variant_url(&client, channel.url.clone(), query.variant).await {
+        Ok(url) => url,
+        Err(response) => return response,
+    };
 
-fn clean_channel_name(name: &str) -> String {
-    let name = name.trim_start_matches("tvg-id=\"\"").trim();
-    let name = name.trim_start_matches("tvg-name=\"").trim();
-    if let Some(index) = name.find('"') {
-        name[..index].trim().to_string()
-    } else {
-        name.to_string()
+    let vars = PlayerVars::for_channel(&url, Some(channel));
+    match renderers.play(&renderer, &vars).await {
+        Ok(()) => HttpResponse::Ok().finish(),
+        Err(e) => {
+            error!("Failed to play on {}: {}", renderer, e);
+            HttpResponse::BadGateway().body(e)
+        }
+    }
+}
+
+async fn list_players(players: web::Data<PlayerRegistry>) -> impl Responder {
+    pages::render(&PlayersPage {
+        players: players.list().into_iter()
+            .map(|status| PlayerRow { link: playlist::is_stream_url(&status.url), status })
+            .collect(),
+        failures: players.failures(),
+    })
+}
+
+async fn stop_player(
+    path: web::Path<u64>,
+    players: web::Data<PlayerRegistry>
+) -> impl Responder {
+    match players.stop(path.into_inner()).await {
+        Ok(true) => HttpResponse::Ok().finish(),
+        Ok(false) => HttpResponse::NotFound().finish(),
+        Err(e) => {
+            error!("Failed to stop player: {}", e);
+            HttpResponse::InternalServerError().body("Failed to stop player")
+        }
+    }
+}
+
+async fn mpv_remote(mpv: web::Data<MpvController>) -> impl Responder {
+    pages::render(&RemotePage {
+        name: "mpv",
+        target: "mpv",
+        notice: (!mpv.is_enabled()).then_some("Set mpv_ipc = true and mpv_path in config.txt to use the remote."),
+    })
+}
+
+async fn mpv_status(mpv: web::Data<MpvController>) -> impl Responder {
+    HttpResponse::Ok().json(mpv.status().await)
+}
+
+async fn mpv_action(
+    path: web::Path<String>,
+    mpv: web::Data<MpvController>,
+    library: web::Data<Library>,
+    players: web::Data<PlayerRegistry>
+) -> impl Responder {
+    if !mpv.is_enabled() {
+        return HttpResponse::NotFound().body("mpv IPC is not enabled");
+    }
+
+    let result = match path.into_inner().as_str() {
+        "pause" => mpv.command(serde_json::json!(["cycle", "pause"])).await.map(|_| ()),
+        "volume_up" => mpv.command(serde_json::json!(["add", "volume", 5])).await.map(|_| ()),
+        "volume_down" => mpv.command(serde_json::json!(["add", "volume", -5])).await.map(|_| ()),
+        "stop" => mpv.stop().await,
+        action @ ("next" | "previous") => {
+            let offset = if action == "next" { 1 } else { -1 };
+            match mpv.current_channel().and_then(|id| library.neighbour(&id, offset)) {
+                Some(channel) => {
+                    let vars = PlayerVars::for_channel(&channel.url, Some(channel));
+                    mpv.play(Some(&channel.id), &vars, &players).await
+                }
+                None => Err("No channel to switch to".to_string()),
+            }
+        }
+        _ => return HttpResponse::BadRequest().body("Invalid action"),
+    };
+
+    match result {
+        Ok(()) => HttpResponse::Ok().json(mpv.status().await),
+        Err(e) => HttpResponse::InternalServerError().body(e),
+    }
+}
+
+async fn vlc_remote_page(vlc: web::Data<VlcRemote>) -> impl Responder {
+    pages::render(&RemotePage {
+        name: "VLC",
+        target: "vlc",
+        notice: (!vlc.is_enabled()).then_some("Set vlc_remote = true in config.txt to use the remote."),
+    })
+}
+
+async fn vlc_status(vlc: web::Data<VlcRemote>) -> impl Responder {
+    HttpResponse::Ok().json(vlc.status().await)
+}
+
+async fn vlc_action(
+    path: web::Path<String>,
+    vlc: web::Data<VlcRemote>,
+    library: web::Data<Library>,
+    players: web::Data<PlayerRegistry>
+) -> impl Responder {
+    if !vlc.is_enabled() {
+        return HttpResponse::NotFound().body("VLC remote is not enabled");
+    }
+
+    let result = match path.into_inner().as_str() {
+        "pause" => vlc.command("pl_pause").await,
+        "volume_up" => vlc.change_volume(5).await,
+        "volume_down" => vlc.change_volume(-5).await,
+        "stop" => vlc.stop().await,
+        action @ ("next" | "previous") => {
+            let offset = if action == "next" { 1 } else { -1 };
+            match vlc.current_channel().and_then(|id| library.neighbour(&id, offset)) {
+                Some(channel) => {
+                    let vars = PlayerVars::for_channel(&channel.url, Some(channel));
+                    vlc.play(Some(&channel.id), &vars, false, &players).await
+                }
+                // Nothing we started is playing, so walk VLC's own playlist
+                None => vlc.command(if action == "next" { "pl_next" } else { "pl_previous" }).await,
+            }
+        }
+        _ => return HttpResponse::BadRequest().body("Invalid action"),
+    };
+
+    match result {
+        Ok(()) => HttpResponse::Ok().json(vlc.status().await),
+        Err(e) => HttpResponse::InternalServerError().body(e),
     }
 }
 
 async fn search(
     query: web::Query<HashMap<String, String>>,
     pagination: web::Query<PaginationQuery>,
-    categories: web::Data<HashMap<String, Category>>
+    filter: web::Query<ListFilter>,
+    library: web::Data<Library>,
+    info: RowContext
 ) -> impl Responder {
     if let Some(q) = query.get("q") {
         let search_term = q.to_lowercase();
         let mut results = Vec::new();
 
-        for category in categories.values() {
-            for channel in &category.channels {
-                if channel.name.to_lowercase().contains(&search_term) {
-                    results.push((category.name.clone(), channel.clone()));
-                }
+        for (category_name, channel) in library.all_channels() {
+            if channel.name.to_lowercase().contains(&search_term) {
+                results.push((Some(category_name), channel));
             }
         }
 
         results.sort_by(|a, b| a.1.name.cmp(&b.1.name));
 
-        let page_size = pagination.page_size.unwrap_or(100);
-        let page = pagination.page.unwrap_or(1);
-        
-        let total_results = results.len();
-        let total_pages = (total_results + page_size - 1) / page_size;
-        
-        let start_index = (page - 1) * page_size;
-        let end_index = std::cmp::min(start_index + page_size, total_results);
-
-        let results_html = results[start_index..end_index].iter().map(|(category_name, channel)| {
-            format!(r#"
-                <li>
-                    <img src="/static/placeholder.png" data-src="/lazy_load_image?url={}" alt="{}" class="thumbnail lazyload">
-                    <a href="{}" target="_blank">{}</a> (Category: {})
-                    <button onclick="playChannel('{}', 'mpv')">Play with mpv</button>
-                    <button onclick="playChannel('{}', 'vlc')">Play with VLC</button>
-                </li>
-            "#, encode(&channel.icon_url), channel.name, channel.url, channel.name, category_name, channel.url, channel.url)
-        }).collect::<String>();
-
-        let pagination_html = generate_pagination_html(page, total_pages, page_size, &format!("/search?q={}", encode(q)));
-
-        let html = format!(r#"
-            <html>
-            <head>
-                <link rel="stylesheet" href="/static/styles.css">
-                <title>Search Results</title>
-                <script src="/static/script.js"></script>
-            </head>
-            <body>
-                <h1>Search Results for "{}"</h1>
-                <p>Showing {}-{} of {} channels</p>
-                <div>
-                    <a href="/search?q={}&page_size=100">100</a> |
-                    <a href="/search?q={}&page_size=1000">1000</a> |
-                    <a href="/search?q={}&page_size=10000">10000</a> |
-                    <a href="/search?q={}&page_size={}">All</a>
-                </div>
-                <div class="view-controls">
-                    <button onclick="setView('list')">List View</button>
-                    <button onclick="setView('grid')">Grid View</button>
-                </div>
-                <ul id="searchResults" class="list-view">
-                    {}
-                </ul>
-                {}
-            </body>
-            </html>
-        "#, 
-            q,
-            start_index + 1,
-            end_index,
-            total_results,
-            encode(q),
-            encode(q),
-            encode(q),
-            encode(q),
-            total_results,
-            results_html,
-            pagination_html
-        );
-
-        HttpResponse::Ok()
-            .content_type("text/html; charset=utf-8")
-            .body(html)
+        let list = ChannelList {
+            title: "Search Results".to_string(),
+            heading: format!("Search Results for \"{}\"", q),
+            list_id: "searchResults",
+            base_url: format!("/search?q={}", encode(q)),
+            channels: results,
+        };
+        channel_page(list, &pagination, &filter, &info)
     } else {
         HttpResponse::BadRequest().finish()
     }
 }
 
 
+async fn variants(
+    path: web::Path<String>,
+    library: web::Data<Library>,
+    profiles: web::Data<PlayerProfiles>,
+    vlc: web::Data<VlcRemote>,
+    renderers: web::Data<Renderers>,
+    client: web::Data<reqwest::Client>
+) -> impl Responder {
+    let channel = match library.channel(&path.into_inner()) {
+        Some(channel) => channel,
+        None => return HttpResponse::NotFound().finish(),
+    };
+
+    let mut page = VariantsPage { name: channel.name.clone(), message: None, variants: Vec::new() };
+    match hls::fetch_master(&client, &channel.url).await {
+        Ok(Some(master)) => {
+            page.variants = master.variants.iter().enumerate().map(|(index, variant)| VariantRow {
+                label: variant.label(),
+                audio: variant.audio_group.as_ref()
+                    .map(|group| master.audio_group(group).iter()
+                        .map(|a| match &a.language {
+                            Some(language) => format!("{} ({})", a.name, language),
+                            None => a.name.clone(),
+                        })
+                        .collect::<Vec<_>>()
+                        .join(", "))
+                    .unwrap_or_default(),
+                buttons: player_buttons(&profiles, &vlc, &renderers, &channel.id, Some(index)),
+            }).collect();
+        }
+        Ok(None) => {
+            page.message = Some("This stream is not an HLS master playlist and has no variants to choose from.".to_string());
+        }
+        Err(e) => {
+            error!("Failed to fetch HLS master {}: {}", channel.url, e);
+            page.message = Some(format!("Failed to fetch the playlist: {}", e));
+        }
+    }
+
+    pages::render(&page)
+}
+
+#[derive(Deserialize)]
+struct ProxyQuery {
+    url: Option<String>,
+}
+
+async fn proxy_stream(
+    req: HttpRequest,
+    path: web::Path<String>,
+    query: web::Query<ProxyQuery>,
+    library: web::Data<Library>,
+    proxy: web::Data<StreamProxy>
+) -> impl Responder {
+    match library.channel(&path.into_inner()) {
+        Some(channel) => {
+            let range = req.headers().get("Range").and_then(|v| v.to_str().ok());
+            proxy.forward(channel, query.url.as_deref(), range).await
+        }
+        None => HttpResponse::NotFound().finish(),
+    }
+}
+
+/// The web player's libraries, pinned to one release: the config key that overrides the URL,
+/// the copy under `static/vendor` and the same release on jsDelivr.
+const PLAYER_SCRIPTS: [(&str, &str, &str); 2] = [
+    ("hls_js_url", "hls.min.js", "https://cdn.jsdelivr.net/npm/hls.js@1.5.20/dist/hls.min.js"),
+    ("mpegts_js_url", "mpegts.js", "https://cdn.jsdelivr.net/npm/mpegts.js@1.7.3/dist/mpegts.js"),
+];
+
+/// Serves the vendored copy of a player library, falling back to the CDN when it is missing.
+fn player_script_url(file: &str, cdn_url: &str) -> String {
+    if std::path::Path::new("./static/vendor").join(file).is_file() {
+        return format!("/static/vendor/{}", file);
+    }
+    warn!("static/vendor/{} is missing, the web player loads it from {}", file, cdn_url);
+    cdn_url.to_string()
+}
+
+#[derive(Deserialize)]
+struct TranscodeQuery {
+    mode: Option<String>,
+}
+
+async fn watch(
+    path: web::Path<String>,
+    query: web::Query<TranscodeQuery>,
+    library: web::Data<Library>,
+    config: web::Data<HashMap<String, String>>,
+    transcoder: web::Data<TranscodeManager>
+) -> impl Responder {
+    let channel = match library.channel(&path.into_inner()) {
+        Some(channel) => channel,
+        None => return HttpResponse::NotFound().finish(),
+    };
+
+    let lower_url = channel.url.to_lowercase();
+    // Browsers cannot open UDP or RTMP streams at all, so these always go through ffmpeg
+    let mode = query.mode.as_deref().or((!playlist::is_http_url(&channel.url)).then_some("remux"));
+    let mut start_url = None;
+    let (src, stream_type) = if let (true, Some(mode)) = (transcoder.is_enabled(), mode) {
+        start_url = Some(format!("/transcode/{}/start?mode={}", channel.id, encode(mode)));
+        (format!("/transcode/{}/index.m3u8", channel.id), "hls")
+    } else if hls::is_hls_url(&channel.url) {
+        (format!("/proxy/{}", channel.id), "hls")
+    } else if channel.kind == ChannelKind::Live || lower_url.ends_with(".ts") {
+        (format!("/proxy/{}", channel.id), "mpegts")
+    } else {
+        (format!("/proxy/{}", channel.id), "file")
+    };
+
+    pages::render(&WatchPage {
+        name: channel.name.clone(),
+        channel_id: channel.id.clone(),
+        hls_js_url: config.get("hls_js_url").cloned().unwrap_or_default(),
+        mpegts_js_url: config.get("mpegts_js_url").cloned().unwrap_or_default(),
+        src,
+        stream_type,
+        start_url,
+        transcode: transcoder.is_enabled(),
+    })
+}
+
+/// Starts (or switches the mode of) the ffmpeg session whose files `transcode_file` serves.
+/// A POST, so the cross-site check applies before a process is spawned.
+async fn start_transcode(
+    path: web::Path<String>,
+    query: web::Query<TranscodeQuery>,
+    library: web::Data<Library>,
+    transcoder: web::Data<TranscodeManager>
+) -> impl Responder {
+    let channel = match library.channel(&path.into_inner()) {
+        Some(channel) => channel,
+        None => return HttpResponse::NotFound().finish(),
+    };
+    match transcoder.start(channel, TranscodeMode::from_query(query.mode.as_deref())).await {
+        Ok(_) => HttpResponse::NoContent().finish(),
+        Err(e) => HttpResponse::ServiceUnavailable().body(e),
+    }
+}
+
+async fn transcode_file(
+    path: web::Path<(String, String)>,
+    transcoder: web::Data<TranscodeManager>
+) -> impl Responder {
+    let (id, file) = path.into_inner();
+    if !transcode::is_valid_file_name(&file) {
+        return HttpResponse::BadRequest().finish();
+    }
+
+    let dir = match transcoder.touch(&id) {
+        Some(dir) => dir,
+        None => return HttpResponse::NotFound().finish(),
+    };
+
+    let file_path = dir.join(&file);
+    if !transcode::wait_for_file(&file_path, std::time::Duration::from_secs(20)).await {
+        return HttpResponse::GatewayTimeout().body("ffmpeg did not produce any output");
+    }
+
+    match tokio::fs::read(&file_path).await {
+        Ok(bytes) => HttpResponse::Ok()
+            .content_type(if file.ends_with(".m3u8") { "application/vnd.apple.mpegurl" } else { "video/mp2t" })
+            .insert_header(("Cache-Control", "no-cache"))
+            .body(bytes),
+        Err(_) => HttpResponse::NotFound().finish(),
+    }
+}
+
+async fn transcodes(transcoder: web::Data<TranscodeManager>) -> impl Responder {
+    pages::render(&TranscodesPage {
+        enabled: transcoder.is_enabled(),
+        idle_timeout_secs: transcoder.idle_timeout().as_secs(),
+        sessions: transcoder.statuses().into_iter().map(|status| TranscodeRow {
+            mode: format!("{:?}", status.mode),
+            running_secs: status.running_secs,
+            idle_secs: status.idle_secs,
+            state: status.exit_status.as_ref().map(|s| format!("ffmpeg exited: {}", s)).unwrap_or_else(|| "ffmpeg running".to_string()),
+            channel_id: status.channel_id,
+            channel_name: status.channel_name,
+        }).collect(),
+    })
+}
+
+async fn stop_transcode(
+    path: web::Path<String>,
+    transcoder: web::Data<TranscodeManager>
+) -> impl Responder {
+    if transcoder.stop(&path.into_inner()).await {
+        HttpResponse::Ok().finish()
+    } else {
+        HttpResponse::NotFound().finish()
+    }
+}
+
+#[derive(Deserialize)]
+struct RecordQuery {
+    /// Record for this many minutes; until stopped when absent.
+    minutes: Option<u64>,
+}
+
+async fn record_channel(
+    path: web::Path<String>,
+    query: web::Query<RecordQuery>,
+    library: web::Data<Library>,
+    recorder: web::Data<Recorder>
+) -> impl Responder {
+    let channel = match library.channel(&path.into_inner()) {
+        Some(channel) => channel,
+        None => return HttpResponse::NotFound().body("Unknown channel"),
+    };
+    let duration = query.minutes.filter(|&m| m > 0).map(|m| std::time::Duration::from_secs(m * 60));
+    match recorder.start(channel, duration, None) {
+        Ok(id) => HttpResponse::Ok().json(serde_json::json!({ "id": id })),
+        Err(e) => {
+            error!("Failed to start recording: {}", e);
+            HttpResponse::InternalServerError().body(e)
+        }
+    }
+}
+
+fn format_size(bytes: u64) -> String {
+    match bytes {
+        b if b >= 1 << 30 => format!("{:.2} GB", b as f64 / (1u64 << 30) as f64),
+        b if b >= 1 << 20 => format!("{:.1} MB", b as f64 / (1u64 << 20) as f64),
+        b if b >= 1 << 10 => format!("{} KB", b / 1024),
+        b => format!("{} B", b),
+    }
+}
+
+fn format_duration(secs: u64) -> String {
+    if secs >= 3600 {
+        format!("{}h {:02}m", secs / 3600, secs % 3600 / 60)
+    } else {
+        format!("{}m {:02}s", secs / 60, secs % 60)
+    }
+}
+
+async fn recordings(recorder: web::Data<Recorder>) -> impl Responder {
+    let now = health::unix_now();
+    let recordings = recorder.statuses().into_iter().map(|recording| {
+        let active = recording.state == RecordingState::Recording;
+        let details = match (active, recording.ends_at, &recording.error) {
+            (true, Some(ends_at), _) => format!("recording for {}, ends in {}", format_duration(now.saturating_sub(recording.started_at)), format_duration(ends_at.saturating_sub(now))),
+            (true, None, _) => format!("recording for {}, until stopped", format_duration(now.saturating_sub(recording.started_at))),
+            (false, _, Some(error)) => format!("failed: {}", error),
+            (false, _, None) => recording.state.label().to_string(),
+        };
+        RecordingRow {
+            id: recording.id,
+            state: recording.state.label(),
+            size: format_size(recording.size_bytes),
+            details,
+            active,
+            title: recording.title,
+            file_name: recording.file_name,
+        }
+    }).collect();
+
+    pages::render(&RecordingsPage {
+        dir: recorder.dir().display().to_string(),
+        uses_ffmpeg: recorder.uses_ffmpeg(),
+        recordings,
+    })
+}
+
+async fn stop_recording(
+    path: web::Path<u64>,
+    recorder: web::Data<Recorder>
+) -> impl Responder {
+    if recorder.stop(path.into_inner()) {
+        HttpResponse::Ok().finish()
+    } else {
+        HttpResponse::NotFound().finish()
+    }
+}
+
+async fn delete_recording(
+    path: web::Path<u64>,
+    recorder: web::Data<Recorder>
+) -> impl Responder {
+    match recorder.delete(path.into_inner()) {
+        Ok(true) => HttpResponse::Ok().finish(),
+        Ok(false) => HttpResponse::NotFound().finish(),
+        Err(e) => HttpResponse::Conflict().body(e),
+    }
+}
+
+async fn recording_file(
+    req: HttpRequest,
+    path: web::Path<u64>,
+    recorder: web::Data<Recorder>
+) -> HttpResponse {
+    let Some(file_path) = recorder.file_path(path.into_inner()) else {
+        return HttpResponse::NotFound().finish();
+    };
+    match fs::NamedFile::open_async(&file_path).await {
+        Ok(file) => {
+            let content_type = recorder::content_type(&file_path.to_string_lossy());
+            file.set_content_type(content_type.parse().unwrap()).into_response(&req)
+        }
+        Err(_) => HttpResponse::NotFound().finish(),
+    }
+}
+
+async fn guide(
+    path: web::Path<String>,
+    library: web::Data<Library>,
+    guide: web::Data<Guide>,
+    scheduler: web::Data<Scheduler>
+) -> impl Responder {
+    let channel = match library.channel(&path.into_inner()) {
+        Some(channel) => channel,
+        None => return HttpResponse::NotFound().body("Unknown channel"),
+    };
+    let programmes = channel.tvg_id.as_deref().map(|tvg_id| guide.upcoming(tvg_id)).unwrap_or_default();
+    let now = epg::now();
+
+    pages::render(&GuidePage {
+        name: channel.name.clone(),
+        channel_id: channel.id.clone(),
+        notice: if !guide.is_enabled() {
+            Some("Set epg_path in config.txt to load a guide.")
+        } else if programmes.is_empty() {
+            Some("No upcoming programmes in the guide for this channel.")
+        } else {
+            None
+        },
+        programmes: programmes.into_iter().map(|programme| ProgrammeRow {
+            start: programme.start,
+            airing: programme.is_airing(now),
+            time: format!("{} - {}", epg::format_time(programme.start), epg::format_clock(programme.stop)),
+            scheduled: scheduler.is_scheduled(&channel.id, programme.start),
+            title: programme.title,
+            description: programme.description,
+        }).collect(),
+    })
+}
+
+async fn schedule_programme(
+    path: web::Path<(String, i64)>,
+    library: web::Data<Library>,
+    guide: web::Data<Guide>,
+    scheduler: web::Data<Scheduler>,
+    recorder: web::Data<Recorder>
+) -> impl Responder {
+    let (channel_id, start) = path.into_inner();
+    let Some(channel) = library.channel(&channel_id) else {
+        return HttpResponse::NotFound().body("Unknown channel");
+    };
+    let Some(programme) = channel.tvg_id.as_deref().and_then(|tvg_id| guide.programme(tvg_id, start)) else {
+        return HttpResponse::NotFound().body("Programme not found in the guide");
+    };
+    match scheduler.schedule(channel, &programme, &recorder).await {
+        Ok(entry) => HttpResponse::Ok().json(entry),
+        Err(e) => HttpResponse::Conflict().body(e),
+    }
+}
+
+async fn schedule(scheduler: web::Data<Scheduler>) -> impl Responder {
+    let entries = scheduler.list().into_iter().map(|entry| {
+        let (start, stop) = entry.window();
+        ScheduleRow {
+            id: entry.id,
+            state: entry.state.label(),
+            time: format!("{} - {}", epg::format_time(entry.start), epg::format_clock(entry.stop)),
+            window: format!("{} - {}", epg::format_clock(start), epg::format_clock(stop)),
+            status: match &entry.error {
+                Some(error) => format!("failed: {}", error),
+                None => entry.state.label().to_string(),
+            },
+            pending: entry.state == ScheduleState::Scheduled || entry.state == ScheduleState::Recording,
+            title: entry.title,
+            channel_name: entry.channel_name,
+        }
+    }).collect();
+
+    pages::render(&SchedulePage { entries })
+}
+
+async fn cancel_scheduled(
+    path: web::Path<u64>,
+    scheduler: web::Data<Scheduler>,
+    recorder: web::Data<Recorder>
+) -> impl Responder {
+    if scheduler.cancel(path.into_inner(), &recorder).await {
+        HttpResponse::Ok().finish()
+    } else {
+        HttpResponse::NotFound().finish()
+    }
+}
+
+async fn health_status(
+    path: web::Path<String>,
+    health: web::Data<HealthChecker>
+) -> impl Responder {
+    match health.get(&path.into_inner()) {
+        Some(record) => HttpResponse::Ok().json(record),
+        None => HttpResponse::NotFound().finish(),
+    }
+}
+
+async fn check_health(
+    path: web::Path<String>,
+    library: web::Data<Library>,
+    health: web::Data<HealthChecker>
+) -> impl Responder {
+    let id = path.into_inner();
+    match library.channel(&id) {
+        Some(channel) => HttpResponse::Ok().json(health.check(&id, &channel.url).await),
+        None => HttpResponse::NotFound().finish(),
+    }
+}
+
+async fn media_status(
+    path: web::Path<String>,
+    media: web::Data<MediaProber>
+) -> impl Responder {
+    match media.get(&path.into_inner()) {
+        Some(result) => HttpResponse::Ok().json(result),
+        None => HttpResponse::NotFound().finish(),
+    }
+}
+
+async fn probe_media(
+    path: web::Path<String>,
+    library: web::Data<Library>,
+    media: web::Data<MediaProber>
+) -> impl Responder {
+    let id = path.into_inner();
+    match library.channel(&id) {
+        Some(channel) => match media.probe(channel).await {
+            Ok(result) => HttpResponse::Ok().json(result),
+            Err(e) => HttpResponse::InternalServerError().body(e),
+        },
+        None => HttpResponse::NotFound().finish(),
+    }
+}
+
+/// Probes every live channel that has not been probed yet, then repeats for newly added ones.
+async fn run_media_probes(library: web::Data<Library>, media: web::Data<MediaProber>, interval: std::time::Duration) {
+    loop {
+        let channels: Vec<Channel> = library.all_channels()
+            .filter(|(_, channel)| channel.kind == ChannelKind::Live && playlist::is_http_url(&channel.url))
+            .map(|(_, channel)| channel.clone())
+            .collect();
+        media.probe_all(channels).await;
+        tokio::time::sleep(interval).await;
+    }
+}
+
+/// Periodically re-checks every live channel in the background.
+async fn run_health_checks(library: web::Data<Library>, health: web::Data<HealthChecker>, interval: std::time::Duration) {
+    loop {
+        let channels: Vec<(String, String)> = library.all_channels()
+            .filter(|(_, channel)| channel.kind == ChannelKind::Live && playlist::is_http_url(&channel.url))
+            .map(|(_, channel)| (channel.id.clone(), channel.url.clone()))
+            .collect();
+        info!("Checking {} live channels", channels.len());
+        health.check_all(channels).await;
+        tokio::time::sleep(interval).await;
+    }
+}
+
+/// Collects the logos of the categories and shows named in `image_prefetch`, or of all of them
+/// when it is `all`, and downloads them into the image cache.
+async fn run_logo_prefetch(library: web::Data<Library>, images: web::Data<ImageCache>, selection: String) {
+    let names: HashSet<&str> = selection.split(',').map(str::trim).filter(|name| !name.is_empty()).collect();
+    let all = names.contains("all");
+    let mut seen = HashSet::new();
+    let urls: Vec<String> = library.all_channels()
+        .filter(|(category, _)| all || names.contains(category))
+        .map(|(_, channel)| channel.icon_url.as_str())
+        .filter(|url| library.has_icon(url) && seen.insert(*url))
+        .map(str::to_string)
+        .collect();
+    info!("Prefetching {} logos", urls.len());
+    images.prefetch(urls).await;
+}
+
+async fn run_guide_refresh(library: web::Data<Library>, guide: web::Data<Guide>, interval: std::time::Duration) {
+    loop {
+        let tvg_ids = library.tvg_ids().map(str::to_string).collect();
+        match guide.load(tvg_ids).await {
+            Ok(count) => info!("Loaded {} programmes from the guide", count),
+            Err(e) => error!("Failed to load the guide: {}", e),
+        }
+        tokio::time::sleep(interval).await;
+    }
+}
 
 #[actix_web::main]
 async fn main() {
     // Initialize logger
     env_logger::init();
 
+    let args = match cli::parse_args(std::env::args().skip(1)) {
+        Ok(args) => args,
+        Err(e) => {
+            eprintln!("{}\n\n{}", e, cli::usage());
+            exit(2);
+        }
+    };
+    if args.help {
+        print!("{}", cli::usage());
+        return;
+    }
+    if args.hash_password {
+        eprint!("Password: ");
+        let mut password = String::new();
+        if let Err(e) = std::io::stdin().read_line(&mut password) {
+            eprintln!("Failed to read the password: {}", e);
+            exit(1);
+        }
+        match auth::hash_password(password.trim_end_matches(['\r', '\n'])) {
+            Ok(hash) => println!("{}", hash),
+            Err(e) => {
+                eprintln!("Failed to hash the password: {}", e);
+                exit(1);
+            }
+        }
+        return;
+    }
+
     info!("Starting M3U Playlist Browser");
-    info!("Access the application at http://localhost:8080");
 
-    let config = read_config();
+    let mut config = load_config(&args);
+    for (key, file, cdn_url) in PLAYER_SCRIPTS {
+        if !config.contains_key(key) {
+            config.insert(key.to_string(), player_script_url(file, cdn_url));
+        }
+    }
+    let settings = match ServerSettings::from_config(&config) {
+        Ok(settings) => settings,
+        Err(e) => {
+            error!("{}", e);
+            exit(1);
+        }
+    };
+    let tls_config = match settings.load_tls_config() {
+        Ok(tls_config) => tls_config,
+        Err(e) => {
+            error!("{}", e);
+            exit(1);
+        }
+    };
+    let auth_data = web::Data::new(Auth::from_config(&config));
+    if !auth_data.is_enabled() && !settings.is_loopback() {
+        warn!("Listening on {} without authentication, anyone on the network can use the server", settings.bind_address);
+    }
     let playlist_path = config.get("playlist_path").cloned().unwrap_or_else(|| "playlist.m3u".to_string());
     
     // Check if playlist file exists
@@ -467,87 +1592,109 @@ async fn main() {
         exit(1);
     }
 
-    let file = match File::open(&playlist_path) {
-        Ok(file) => file,
+    let library = match playlist::load_playlist(&playlist_path) {
+        Ok(library) => library,
         Err(e) => {
-            error!("Failed to open playlist file: {}", e);
+            error!("Failed to load playlist file: {}", e);
             exit(1);
         }
     };
+    info!(
+        "Loaded {} live categories, {} movie categories and {} shows",
+        library.live.len(),
+        library.movies.len(),
+        library.shows.len()
+    );
 
-    let reader = BufReader::new(file);
-
-    let mut categories: HashMap<String, Category> = HashMap::new();
-    let mut current_category: Option<String> = None;
-    let mut current_channel: Option<Channel> = None;
-
-    for line in reader.lines() {
-        let line = line.expect("Failed to read line");
-
-        if line.starts_with("#EXTINF") {
-            let parts: Vec<&str> = line.split(',').collect();
-            if parts.len() >= 1 {
-				let mut category_name = if parts[0].contains("group-title=\"\"") || parts[0].contains("group=\"\"") {
-					"No Category".to_string()
-				} else {
-					"Uncategorized".to_string()
-				};
-                let mut channel_name = clean_channel_name(parts[0].trim_start_matches("#EXTINF:-1").trim());
-                let mut icon_url = "".to_string();
-
-                // Check for the most common ways to identify categories or groups
-                if let Some(group_title_index) = parts[0].find("group-title=\"") {
-                    let group_title_start = group_title_index + "group-title=\"".len();
-                    if let Some(group_title_end) = parts[0][group_title_start..].find('"') {
-                        category_name = parts[0][group_title_start..group_title_start + group_title_end].to_string();
-                        channel_name = clean_channel_name(parts[1].trim());
-                    }
-                } else if let Some(group_index) = parts[0].find("group=\"") {
-                    let group_start = group_index + "group=\"".len();
-                    if let Some(group_end) = parts[0][group_start..].find('"') {
-                        category_name = parts[0][group_start..group_start + group_end].to_string();
-                        channel_name = clean_channel_name(parts[1].trim());
-                    }
-                }
-
-                // Check for the icon or thumbnail URL
-                if let Some(tvg_logo_index) = parts[0].find("tvg-logo=\"") {
-                    let tvg_logo_start = tvg_logo_index + "tvg-logo=\"".len();
-                    if let Some(tvg_logo_end) = parts[0][tvg_logo_start..].find('"') {
-                        icon_url = parts[0][tvg_logo_start..tvg_logo_start + tvg_logo_end].to_string();
-                    }
+    let library_data = web::Data::new(library);
+    let client_data = web::Data::new(reqwest::Client::new());
+    let players_data = web::Data::new(PlayerRegistry::default());
+    let mpv_data = web::Data::new(MpvController::from_config(&config));
+    let vlc_data = web::Data::new(VlcRemote::from_config(&config));
+    let renderers_data = web::Data::new(Renderers::from_config(&config));
+    if renderers_data.discovery_enabled() {
+        let renderers = renderers_data.clone();
+        tokio::spawn(async move {
+            match renderers.discover().await {
+                Ok(count) => info!("Found {} DLNA renderers", count),
+                Err(e) => warn!("SSDP discovery failed: {}", e),
+            }
+        });
+    }
+    let proxy_data = web::Data::new(StreamProxy::new(reqwest::Client::new()));
+    let images_data = web::Data::new(ImageCache::from_config(&config));
+    if images_data.has_limits() {
+        let images = images_data.clone();
+        tokio::spawn(async move {
+            loop {
+                let removed = images.evict().await;
+                if removed > 0 {
+                    info!("Removed {} images from the cache", removed);
                 }
+                tokio::time::sleep(std::time::Duration::from_secs(300)).await;
+            }
+        });
+    }
+    if let Some(selection) = config.get("image_prefetch").filter(|v| !v.is_empty()) {
+        tokio::spawn(run_logo_prefetch(library_data.clone(), images_data.clone(), selection.clone()));
+    }
+    let transcoder_data = web::Data::new(TranscodeManager::from_config(&config));
+    let recorder_data = web::Data::new(Recorder::from_config(&config));
+    let guide_data = web::Data::new(Guide::from_config(&config));
+    let scheduler_data = web::Data::new(Scheduler::from_config(&config));
+
+    if guide_data.is_enabled() {
+        let hours = config.get("epg_refresh_hours").and_then(|v| v.parse::<u64>().ok()).unwrap_or(12).max(1);
+        tokio::spawn(run_guide_refresh(
+            library_data.clone(),
+            guide_data.clone(),
+            std::time::Duration::from_secs(hours * 3600),
+        ));
+    }
+    {
+        let (scheduler, library, recorder) = (scheduler_data.clone(), library_data.clone(), recorder_data.clone());
+        tokio::spawn(async move {
+            loop {
+                scheduler.tick(&library, &recorder).await;
+                tokio::time::sleep(std::time::Duration::from_secs(15)).await;
+            }
+        });
+    }
 
-				if category_name.is_empty() {
-					category_name = "No Category".to_string();
-				}
-
-				current_category = Some(category_name.clone());
-				current_channel = Some(Channel {
-					name: channel_name,
-					url: "".to_string(),
-					icon_url: if icon_url.is_empty() { "/static/placeholder.png".to_string() } else { icon_url },
-				});
-
+    if transcoder_data.is_enabled() {
+        let transcoder = transcoder_data.clone();
+        tokio::spawn(async move {
+            loop {
+                tokio::time::sleep(std::time::Duration::from_secs(10)).await;
+                transcoder.reap().await;
             }
-        } else if line.starts_with("http") {
-            if let Some(category_name) = current_category.clone() {
-                if let Some(channel) = current_channel.take() {
-                    let category = categories.entry(category_name.clone()).or_insert(Category {
-                        name: category_name,
-                        channels: Vec::new(),
-                    });
-                    category.channels.push(Channel {
-                        name: channel.name,
-                        url: line.clone(),
-                        icon_url: channel.icon_url,
-                    });
-                }
+        });
+    }
+    let health_data = web::Data::new(HealthChecker::from_config(&config));
+
+    let media_data = web::Data::new(MediaProber::from_config(&config));
+
+    if media_data.is_enabled() {
+        if let Some(minutes) = config.get("ffprobe_interval_minutes").and_then(|v| v.parse::<u64>().ok()) {
+            if minutes > 0 {
+                tokio::spawn(run_media_probes(
+                    library_data.clone(),
+                    media_data.clone(),
+                    std::time::Duration::from_secs(minutes * 60),
+                ));
             }
         }
     }
 
-    let categories_data = web::Data::new(categories);
+    if let Some(minutes) = config.get("health_check_interval_minutes").and_then(|v| v.parse::<u64>().ok()) {
+        if minutes > 0 {
+            tokio::spawn(run_health_checks(
+                library_data.clone(),
+                health_data.clone(),
+                std::time::Duration::from_secs(minutes * 60),
+            ));
+        }
+    }
     
     // Clone config before moving it into web::Data
     let config_clone = config.clone();
@@ -560,22 +1707,87 @@ async fn main() {
     if !config_clone.contains_key("mpv_path") {
         warn!("MPV path not specified in config.txt");
     }
+    let profiles_data = web::Data::new(PlayerProfiles::from_config(&config_clone));
+    if profiles_data.is_empty() {
+        warn!("No players configured, play buttons will not be shown");
+    }
 
-    info!("Starting server on http://localhost:8080");
-    match HttpServer::new(move || {
+    info!("Starting server on {}", settings.url());
+    let server = HttpServer::new(move || {
         App::new()
-            .app_data(categories_data.clone())
+            .wrap(from_fn(require_login))
+            .wrap(from_fn(reject_cross_site))
+            .app_data(auth_data.clone())
+            .app_data(library_data.clone())
+            .app_data(client_data.clone())
+            .app_data(proxy_data.clone())
+            .app_data(images_data.clone())
+            .app_data(players_data.clone())
+            .app_data(mpv_data.clone())
+            .app_data(vlc_data.clone())
+            .app_data(renderers_data.clone())
+            .app_data(profiles_data.clone())
+            .app_data(transcoder_data.clone())
+            .app_data(recorder_data.clone())
+            .app_data(guide_data.clone())
+            .app_data(scheduler_data.clone())
+            .app_data(health_data.clone())
+            .app_data(media_data.clone())
             .app_data(config_data.clone())
 			.service(fs::Files::new("/static", "./static").show_files_listing())
             .route("/", web::get().to(index))
+            .route("/login", web::get().to(login_page))
+            .route("/login", web::post().to(login))
+            .route("/logout", web::post().to(logout))
             .route("/category/{name}", web::get().to(category))
-            .route("/play/{player}/{url}", web::get().to(play))
+            .route("/movies/{name}", web::get().to(movie_category))
+            .route("/series", web::get().to(series_index))
+            .route("/series/{show}", web::get().to(show))
+            .route("/series/{show}/{season}", web::get().to(season))
+            .route("/play/{player}/{id}", web::post().to(play))
+            .route("/players", web::get().to(list_players))
+            .route("/players/{id}/stop", web::post().to(stop_player))
+            .route("/mpv", web::get().to(mpv_remote))
+            .route("/mpv/status", web::get().to(mpv_status))
+            .route("/mpv/{action}", web::post().to(mpv_action))
+            .route("/vlc", web::get().to(vlc_remote_page))
+            .route("/vlc/status", web::get().to(vlc_status))
+            .route("/vlc/{action}", web::post().to(vlc_action))
+            .route("/renderers", web::get().to(list_renderers))
+            .route("/renderers/discover", web::post().to(discover_renderers))
+            .route("/cast/{renderer}/{id}", web::post().to(cast))
             .route("/image_cache/{filename}", web::get().to(cached_image))
-            .route("/lazy_load_image", web::get().to(lazy_load_image))
+            .route("/logo/{id}", web::get().to(logo))
             .route("/search", web::get().to(search))
-    })
-    .bind("127.0.0.1:8080")
-    {
+            .route("/variants/{id}", web::get().to(variants))
+            .route("/watch/{id}", web::get().to(watch))
+            .route("/proxy/{id}", web::get().to(proxy_stream))
+            .route("/transcode/{id}/start", web::post().to(start_transcode))
+            .route("/transcode/{id}/{file}", web::get().to(transcode_file))
+            .route("/images", web::get().to(image_cache_page))
+            .route("/images/clear", web::post().to(clear_image_cache))
+            .route("/transcodes", web::get().to(transcodes))
+            .route("/transcodes/{id}/stop", web::post().to(stop_transcode))
+            .route("/record/{id}", web::post().to(record_channel))
+            .route("/recordings", web::get().to(recordings))
+            .route("/recordings/{id}/stop", web::post().to(stop_recording))
+            .route("/recordings/{id}/delete", web::post().to(delete_recording))
+            .route("/recordings/{id}/file", web::get().to(recording_file))
+            .route("/guide/{id}", web::get().to(guide))
+            .route("/schedule", web::get().to(schedule))
+            .route("/schedule/{id}/cancel", web::post().to(cancel_scheduled))
+            .route("/schedule/{channel_id}/{start}", web::post().to(schedule_programme))
+            .route("/health/{id}", web::get().to(health_status))
+            .route("/health/{id}", web::post().to(check_health))
+            .route("/probe/{id}", web::get().to(media_status))
+            .route("/probe/{id}", web::post().to(probe_media))
+    });
+    let address = (settings.bind_address.as_str(), settings.port);
+    let server = match tls_config {
+        Some(tls_config) => server.bind_rustls_0_23(address, tls_config),
+        None => server.bind(address),
+    };
+    match server {
         Ok(server) => {
             if let Err(e) = server.run().await {
                 error!("Server error: {}", e);
@@ -583,8 +1795,103 @@ async fn main() {
             }
         }
         Err(e) => {
-            error!("Failed to bind to address: {}", e);
+            error!("Failed to bind to {}:{}: {}", settings.bind_address, settings.port, e);
             exit(1);
         }
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn login_only_returns_to_paths_on_this_server() {
+        let target = |next: &str| login_target(&LoginQuery { next: Some(next.to_string()) }).to_string();
+        assert_eq!(target("/watch/3?mode=hls"), "/watch/3?mode=hls");
+        assert_eq!(target("/"), "/");
+        for outside in ["//x", "/\t/x", "/\n/x", "/ /x", "/\\x", "\\\\x", "https://x", "x", ""] {
+            assert_eq!(target(outside), "/", "{:?}", outside);
+        }
+        assert_eq!(login_target(&LoginQuery { next: None }), "/");
+    }
+
+    #[test]
+    fn command_line_settings_override_the_config_file() {
+        let path = std::env::temp_dir().join(format!("m3u_browser_config_test_{}.txt", std::process::id()));
+        std::fs::write(&path, "port = 8080\nbind_address=0.0.0.0\nplaylist_url = http://example.com/a.m3u?x=1\nnot a setting\n").unwrap();
+        let args = cli::parse_args(["--config".to_string(), path.display().to_string(), "--port=9000".to_string()]).unwrap();
+        let config = load_config(&args);
+        assert_eq!(config["port"], "9000");
+        assert_eq!(config["bind_address"], "0.0.0.0");
+        assert_eq!(config["playlist_url"], "http://example.com/a.m3u?x=1");
+        assert_eq!(config.len(), 3);
+        std::fs::remove_file(&path).unwrap();
+
+        // A missing config file leaves just the command-line settings
+        let args = cli::parse_args(["--config=/nonexistent/config.txt".to_string(), "--bind".to_string(), "::".to_string()]).unwrap();
+        assert_eq!(load_config(&args), HashMap::from([("bind_address".to_string(), "::".to_string())]));
+    }
+
+    #[actix_web::test]
+    async fn requests_need_a_session_or_the_api_token() {
+        let auth = web::Data::new(Auth::from_config(&HashMap::from([
+            ("api_token".to_string(), "s3cret".to_string()),
+            ("user.alice".to_string(), auth::hash_password("pw").unwrap()),
+        ])));
+        let session = auth.login("alice", "pw").unwrap();
+        let app = actix_web::test::init_service(
+            App::new()
+                .app_data(auth.clone())
+                .wrap(from_fn(require_login))
+                .route("/channels", web::get().to(HttpResponse::Ok))
+                .route("/login", web::get().to(HttpResponse::Ok))
+                .route("/static/app.js", web::get().to(HttpResponse::Ok))
+        ).await;
+        let status = |request: actix_web::test::TestRequest| {
+            let app = &app;
+            async move { actix_web::test::call_service(app, request.to_request()).await.status() }
+        };
+        let get = |path: &str| actix_web::test::TestRequest::get().uri(path);
+
+        assert_eq!(status(get("/channels")).await, StatusCode::UNAUTHORIZED);
+        assert_eq!(status(get("/channels").insert_header((header::AUTHORIZATION, "Bearer s3cret"))).await, StatusCode::OK);
+        assert_eq!(status(get("/channels").insert_header((header::AUTHORIZATION, "Bearer s3cre"))).await, StatusCode::UNAUTHORIZED);
+        assert_eq!(status(get("/channels").insert_header((header::AUTHORIZATION, "Basic s3cret"))).await, StatusCode::UNAUTHORIZED);
+        assert_eq!(status(get("/channels").cookie(Cookie::new(auth::SESSION_COOKIE, session))).await, StatusCode::OK);
+        assert_eq!(status(get("/channels").cookie(Cookie::new(auth::SESSION_COOKIE, "forged"))).await, StatusCode::UNAUTHORIZED);
+        assert_eq!(status(get("/login")).await, StatusCode::OK);
+        assert_eq!(status(get("/static/app.js")).await, StatusCode::OK);
+
+        let page = actix_web::test::call_service(&app, get("/channels?page=2").insert_header((header::ACCEPT, "text/html")).to_request()).await;
+        assert_eq!(page.status(), StatusCode::SEE_OTHER);
+        assert_eq!(page.headers().get(header::LOCATION).unwrap(), "/login?next=%2Fchannels%3Fpage%3D2");
+    }
+
+    #[actix_web::test]
+    async fn cross_site_changes_are_refused() {
+        let app = actix_web::test::init_service(
+            App::new()
+                .wrap(from_fn(reject_cross_site))
+                .route("/play/1", web::post().to(HttpResponse::Ok))
+                .route("/play/1", web::get().to(HttpResponse::Ok))
+        ).await;
+        let status = |request: actix_web::test::TestRequest| {
+            let app = &app;
+            async move {
+                let request = request.uri("/play/1").insert_header((header::HOST, "tv.local:8080"));
+                actix_web::test::call_service(app, request.to_request()).await.status()
+            }
+        };
+        let post = actix_web::test::TestRequest::post;
+
+        assert_eq!(status(post().insert_header((header::ORIGIN, "https://evil.example"))).await, StatusCode::FORBIDDEN);
+        assert_eq!(status(post().insert_header((header::ORIGIN, "null"))).await, StatusCode::FORBIDDEN);
+        assert_eq!(status(post().insert_header((header::REFERER, "http://tv.local:8080.evil.example/"))).await, StatusCode::FORBIDDEN);
+        assert_eq!(status(post().insert_header((header::ORIGIN, "http://tv.local:8080"))).await, StatusCode::OK);
+        assert_eq!(status(post().insert_header((header::REFERER, "http://TV.local:8080/channels"))).await, StatusCode::OK);
+        // Scripts send neither header, and reading is always allowed
+        assert_eq!(status(post()).await, StatusCode::OK);
+        assert_eq!(status(actix_web::test::TestRequest::get().insert_header((header::ORIGIN, "https://evil.example"))).await, StatusCode::OK);
+    }
+}
@@ -0,0 +1,324 @@
+use std::collections::HashMap;
+use std::process::{Command, Stdio};
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+use serde::Serialize;
+use serde_json::{json, Value};
+use tokio::io::{AsyncBufReadExt, AsyncRead, AsyncWrite, AsyncWriteExt, BufReader, Lines, ReadHalf, WriteHalf};
+use log::info;
+use crate::players::PlayerRegistry;
+use crate::profiles::PlayerVars;
+
+#[cfg(unix)]
+type IpcStream = tokio::net::UnixStream;
+#[cfg(windows)]
+type IpcStream = tokio::net::windows::named_pipe::NamedPipeClient;
+
+/// The socket goes in `$XDG_RUNTIME_DIR`, or else in a new directory only we can enter,
+/// so other users can neither drive our mpv nor put their own socket in its place.
+#[cfg(unix)]
+fn default_socket() -> Result<String, String> {
+    use std::os::unix::fs::DirBuilderExt;
+
+    if let Some(dir) = std::env::var_os("XDG_RUNTIME_DIR").filter(|dir| !dir.is_empty()) {
+        return Ok(std::path::Path::new(&dir).join("m3u_browser_mpv.sock").to_string_lossy().into_owned());
+    }
+    let dir = std::env::temp_dir().join(format!("m3u_browser-{}-{}", std::process::id(), crate::random::random_hex(4)));
+    std::fs::DirBuilder::new()
+        .mode(0o700)
+        .create(&dir)
+        .map_err(|e| format!("Failed to create {}: {}", dir.display(), e))?;
+    Ok(dir.join("mpv.sock").to_string_lossy().into_owned())
+}
+
+#[cfg(windows)]
+fn default_socket() -> Result<String, String> {
+    Ok(r"\\.\pipe\m3u_browser_mpv".to_string())
+}
+
+#[derive(Serialize)]
+pub struct MpvStatus {
+    pub running: bool,
+    pub title: Option<String>,
+    pub paused: Option<bool>,
+    pub volume: Option<f64>,
+    pub channel_id: Option<String>,
+}
+
+/// Drives a single long-running mpv through its JSON IPC socket, so each play
+/// replaces the current stream instead of opening another window.
+pub struct MpvController {
+    mpv_path: Option<String>,
+    enabled: bool,
+    socket_path: Mutex<Option<String>>,
+    next_request_id: AtomicU64,
+    current_channel: Mutex<Option<String>>,
+}
+
+impl MpvController {
+    pub fn from_config(config: &HashMap<String, String>) -> Self {
+        MpvController {
+            mpv_path: config.get("mpv_path").cloned(),
+            enabled: config.get("mpv_ipc").map(String::as_str) == Some("true"),
+            socket_path: Mutex::new(config.get("mpv_ipc_socket").cloned()),
+            next_request_id: AtomicU64::new(1),
+            current_channel: Mutex::new(None),
+        }
+    }
+
+    /// True when `mpv_ipc = true` is set and an mpv path is configured.
+    pub fn is_enabled(&self) -> bool {
+        self.enabled && self.mpv_path.is_some()
+    }
+
+    pub fn current_channel(&self) -> Option<String> {
+        self.current_channel.lock().unwrap().clone()
+    }
+
+    /// `mpv_ipc_socket`, or the default location picked the first time it is needed.
+    fn socket_path(&self) -> Result<String, String> {
+        let mut socket_path = self.socket_path.lock().unwrap();
+        if socket_path.is_none() {
+            *socket_path = Some(default_socket()?);
+        }
+        Ok(socket_path.clone().unwrap_or_default())
+    }
+
+    async fn connect(&self) -> Result<Connection<'_, IpcStream>, String> {
+        let socket_path = self.socket_path()?;
+        #[cfg(unix)]
+        let stream = tokio::net::UnixStream::connect(&socket_path).await;
+        #[cfg(windows)]
+        let stream = tokio::net::windows::named_pipe::ClientOptions::new().open(&socket_path);
+
+        let stream = stream.map_err(|e| format!("mpv is not running: {}", e))?;
+        Ok(Connection::new(stream, &self.next_request_id))
+    }
+
+    /// Sends one command on its own connection and returns its `data` field.
+    pub async fn command(&self, args: Value) -> Result<Value, String> {
+        self.connect().await?.command(args).await
+    }
+
+    pub async fn is_running(&self) -> bool {
+        self.command(json!(["get_property", "pid"])).await.is_ok()
+    }
+
+    /// Starts the mpv instance unless it is already listening on the socket.
+    async fn ensure_running(&self, players: &PlayerRegistry) -> Result<(), String> {
+        if self.is_running().await {
+            return Ok(());
+        }
+
+        let mpv_path = self.mpv_path.as_ref().ok_or("mpv path not specified in config")?;
+        let socket_path = self.socket_path()?;
+        let child = Command::new(mpv_path)
+            .arg("--idle=yes")
+            .arg("--force-window=yes")
+            .arg(format!("--input-ipc-server={}", socket_path))
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn()
+            .map_err(|e| format!("Failed to execute mpv: {}", e))?;
+        info!("Started mpv with IPC socket {}", socket_path);
+        players.register("mpv (remote)", &socket_path, child);
+
+        let started = Instant::now();
+        while started.elapsed() < Duration::from_secs(10) {
+            tokio::time::sleep(Duration::from_millis(200)).await;
+            if self.is_running().await {
+                return Ok(());
+            }
+        }
+        Err("mpv did not open its IPC socket".to_string())
+    }
+
+    /// Replaces whatever mpv is playing with `vars.url`.
+    pub async fn play(&self, channel_id: Option<&str>, vars: &PlayerVars<'_>, players: &PlayerRegistry) -> Result<(), String> {
+        self.ensure_running(players).await?;
+
+        // Reset per-channel options so one channel's headers never leak into the next
+        let mut connection = self.connect().await?;
+        connection.command(json!(["set_property", "user-agent", vars.user_agent])).await?;
+        connection.command(json!(["set_property", "referrer", vars.referrer])).await?;
+        connection.command(json!(["set_property", "http-header-fields", vars.headers])).await?;
+        connection.command(json!(["set_property", "force-media-title", vars.title])).await?;
+        connection.command(json!(["loadfile", vars.url, "replace"])).await?;
+
+        *self.current_channel.lock().unwrap() = channel_id.map(str::to_string);
+        Ok(())
+    }
+
+    pub async fn status(&self) -> MpvStatus {
+        let mut status = MpvStatus {
+            running: false,
+            title: None,
+            paused: None,
+            volume: None,
+            channel_id: self.current_channel(),
+        };
+        let Ok(mut connection) = self.connect().await else {
+            return status;
+        };
+        let title = connection.command(json!(["get_property", "media-title"])).await;
+        status.running = title.is_ok();
+        status.title = title.ok().and_then(|v| v.as_str().map(str::to_string));
+        status.paused = connection.command(json!(["get_property", "pause"])).await.ok().and_then(|v| v.as_bool());
+        status.volume = connection.command(json!(["get_property", "volume"])).await.ok().and_then(|v| v.as_f64());
+        status
+    }
+
+    pub async fn stop(&self) -> Result<(), String> {
+        self.command(json!(["stop"])).await?;
+        *self.current_channel.lock().unwrap() = None;
+        Ok(())
+    }
+}
+
+/// One IPC connection, used for all the commands of a single operation.
+struct Connection<'a, S> {
+    lines: Lines<BufReader<ReadHalf<S>>>,
+    writer: WriteHalf<S>,
+    next_request_id: &'a AtomicU64,
+}
+
+impl<'a, S: AsyncRead + AsyncWrite> Connection<'a, S> {
+    fn new(stream: S, next_request_id: &'a AtomicU64) -> Self {
+        let (reader, writer) = tokio::io::split(stream);
+        Connection { lines: BufReader::new(reader).lines(), writer, next_request_id }
+    }
+
+    /// Sends one command and returns its `data` field, skipping any event lines mpv emits meanwhile.
+    async fn command(&mut self, args: Value) -> Result<Value, String> {
+        let request_id = self.next_request_id.fetch_add(1, Ordering::Relaxed);
+        let line = format!("{}\n", json!({ "command": args, "request_id": request_id }));
+        tokio::time::timeout(Duration::from_secs(5), self.exchange(&line, request_id))
+            .await
+            .map_err(|_| "mpv did not answer".to_string())?
+    }
+
+    async fn exchange(&mut self, line: &str, request_id: u64) -> Result<Value, String> {
+        self.writer.write_all(line.as_bytes()).await.map_err(|e| e.to_string())?;
+
+        while let Some(line) = self.lines.next_line().await.map_err(|e| e.to_string())? {
+            let Ok(response) = serde_json::from_str::<Value>(&line) else { continue };
+            if response["request_id"].as_u64() != Some(request_id) {
+                continue;
+            }
+            return match response["error"].as_str() {
+                Some("success") => Ok(response["data"].clone()),
+                Some(error) => Err(format!("mpv error: {}", error)),
+                None => Err("Malformed mpv response".to_string()),
+            };
+        }
+        Err("mpv closed the connection".to_string())
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use tokio::net::UnixListener;
+
+    /// Stands in for mpv: records every command and answers property reads from `properties`,
+    /// with an event line before each reply. Returns the commands received per connection.
+    fn fake_mpv(socket: &std::path::Path, properties: Value) -> Arc<Mutex<Vec<Vec<Value>>>> {
+        let listener = UnixListener::bind(socket).unwrap();
+        let connections = Arc::new(Mutex::new(Vec::new()));
+        let recorded = connections.clone();
+        tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                let index = {
+                    let mut connections = recorded.lock().unwrap();
+                    connections.push(Vec::new());
+                    connections.len() - 1
+                };
+                let recorded = recorded.clone();
+                let properties = properties.clone();
+                tokio::spawn(async move {
+                    let (reader, mut writer) = tokio::io::split(stream);
+                    let mut lines = BufReader::new(reader).lines();
+                    while let Ok(Some(line)) = lines.next_line().await {
+                        let request: Value = serde_json::from_str(&line).unwrap();
+                        let command = request["command"].clone();
+                        recorded.lock().unwrap()[index].push(command.clone());
+                        let data = match command[0].as_str() {
+                            Some("get_property") => properties[command[1].as_str().unwrap()].clone(),
+                            _ => Value::Null,
+                        };
+                        let reply = format!(
+                            "{}\n{}\n",
+                            json!({ "event": "property-change" }),
+                            json!({ "request_id": request["request_id"], "error": "success", "data": data }),
+                        );
+                        writer.write_all(reply.as_bytes()).await.unwrap();
+                    }
+                });
+            }
+        });
+        connections
+    }
+
+    fn controller(socket: &std::path::Path) -> MpvController {
+        let config = HashMap::from([
+            ("mpv_ipc".to_string(), "true".to_string()),
+            ("mpv_path".to_string(), "mpv".to_string()),
+            ("mpv_ipc_socket".to_string(), socket.to_string_lossy().into_owned()),
+        ]);
+        MpvController::from_config(&config)
+    }
+
+    fn socket_path(name: &str) -> std::path::PathBuf {
+        let path = std::env::temp_dir().join(format!("m3u_browser_test_{}_{}.sock", name, std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        path
+    }
+
+    #[tokio::test]
+    async fn play_sends_every_command_on_one_connection() {
+        let socket = socket_path("play");
+        let connections = fake_mpv(&socket, json!({ "pid": 42 }));
+        let mpv = controller(&socket);
+        let vars = PlayerVars {
+            url: "http://example.com/live.m3u8",
+            title: "News",
+            user_agent: "Agent",
+            referrer: "",
+            headers: String::new(),
+        };
+        mpv.play(Some("abc"), &vars, &PlayerRegistry::default()).await.unwrap();
+
+        let connections = connections.lock().unwrap().clone();
+        assert_eq!(connections.len(), 2, "one probe and one connection for the stream");
+        assert_eq!(connections[0], [json!(["get_property", "pid"])]);
+        assert_eq!(connections[1], [
+            json!(["set_property", "user-agent", "Agent"]),
+            json!(["set_property", "referrer", ""]),
+            json!(["set_property", "http-header-fields", ""]),
+            json!(["set_property", "force-media-title", "News"]),
+            json!(["loadfile", "http://example.com/live.m3u8", "replace"]),
+        ]);
+        assert_eq!(mpv.current_channel().as_deref(), Some("abc"));
+        std::fs::remove_file(&socket).unwrap();
+    }
+
+    #[tokio::test]
+    async fn status_reads_properties_and_reports_a_missing_mpv() {
+        let socket = socket_path("status");
+        let connections = fake_mpv(&socket, json!({ "media-title": "News", "pause": true, "volume": 55.0 }));
+        let status = controller(&socket).status().await;
+        assert!(status.running);
+        assert_eq!(status.title.as_deref(), Some("News"));
+        assert_eq!(status.paused, Some(true));
+        assert_eq!(status.volume, Some(55.0));
+        assert_eq!(connections.lock().unwrap().len(), 1);
+        std::fs::remove_file(&socket).unwrap();
+
+        let status = controller(&socket_path("missing")).status().await;
+        assert!(!status.running);
+        assert_eq!(status.title, None);
+    }
+}
@@ -0,0 +1,378 @@
+use actix_web::HttpResponse;
+use askama::Template;
+use log::error;
+use crate::players::{PlayerFailure, PlayerStatus};
+use crate::playlist::{is_stream_url, Channel};
+
+/// Renders a page template, answering with a 500 if it fails.
+pub fn render(page: &impl Template) -> HttpResponse {
+    match page.render() {
+        Ok(html) => HttpResponse::Ok()
+            .content_type("text/html; charset=utf-8")
+            .body(html),
+        Err(e) => {
+            error!("Failed to render page: {}", e);
+            HttpResponse::InternalServerError().finish()
+        }
+    }
+}
+
+pub struct Link {
+    pub url: String,
+    pub label: String,
+}
+
+/// A page number in the pagination bar; the current page has no URL.
+pub struct PageLink {
+    pub label: String,
+    pub url: Option<String>,
+}
+
+pub struct PlayButton {
+    pub player: String,
+    pub label: String,
+    pub enqueue: bool,
+}
+
+/// The play buttons and "Play on" picker of a channel or one of its variants.
+pub struct PlayerButtons {
+    pub channel_id: String,
+    pub variant: Option<usize>,
+    pub buttons: Vec<PlayButton>,
+    /// script.js fills the renderer picker from `/renderers`.
+    pub cast: bool,
+}
+
+pub struct Badge {
+    pub class: &'static str,
+    pub label: &'static str,
+    pub title: String,
+}
+
+pub struct MediaLabel {
+    pub class: &'static str,
+    pub label: String,
+}
+
+pub struct ChannelRow<'a> {
+    pub channel: &'a Channel,
+    /// Shown in search results, where channels of all categories are mixed.
+    pub category: Option<&'a str>,
+    pub health: Badge,
+    pub media: Option<MediaLabel>,
+    pub is_hls: bool,
+    pub buttons: PlayerButtons,
+}
+
+impl ChannelRow<'_> {
+    /// Only stream URLs are shown as links. Anything else, like a `javascript:` URL in a
+    /// hostile playlist, is shown as text.
+    pub fn has_link(&self) -> bool {
+        is_stream_url(&self.channel.url)
+    }
+}
+
+#[derive(Template)]
+#[template(path = "index.html")]
+pub struct IndexPage {
+    pub logout: bool,
+    pub live: Vec<Link>,
+    pub movies: Vec<Link>,
+    pub show_count: usize,
+    pub episode_count: usize,
+}
+
+/// Categories, seasons and search results.
+#[derive(Template)]
+#[template(path = "channel_list.html")]
+pub struct ChannelListPage<'a> {
+    pub title: String,
+    pub heading: String,
+    pub list_id: &'static str,
+    pub first: usize,
+    pub last: usize,
+    pub total: usize,
+    /// The list's URL with its filters, which page size links extend.
+    pub base_url: String,
+    pub sep: char,
+    pub filters: Vec<Link>,
+    pub pagination: Vec<PageLink>,
+    pub rows: Vec<ChannelRow<'a>>,
+}
+
+pub struct ShowLink {
+    pub url: String,
+    pub name: String,
+    pub seasons: usize,
+    pub episodes: usize,
+}
+
+#[derive(Template)]
+#[template(path = "series.html")]
+pub struct SeriesPage {
+    pub first: usize,
+    pub last: usize,
+    pub total: usize,
+    pub pagination: Vec<PageLink>,
+    pub shows: Vec<ShowLink>,
+}
+
+pub struct SeasonLink {
+    pub url: String,
+    pub number: u32,
+    pub episodes: usize,
+}
+
+#[derive(Template)]
+#[template(path = "show.html")]
+pub struct ShowPage {
+    pub name: String,
+    pub seasons: Vec<SeasonLink>,
+}
+
+pub struct PlayerRow {
+    pub status: PlayerStatus,
+    pub link: bool,
+}
+
+#[derive(Template)]
+#[template(path = "players.html")]
+pub struct PlayersPage {
+    pub players: Vec<PlayerRow>,
+    pub failures: Vec<PlayerFailure>,
+}
+
+/// The mpv and VLC remotes, which only differ in the endpoints they call.
+#[derive(Template)]
+#[template(path = "remote.html")]
+pub struct RemotePage {
+    pub name: &'static str,
+    pub target: &'static str,
+    pub notice: Option<&'static str>,
+}
+
+pub struct VariantRow {
+    pub label: String,
+    pub audio: String,
+    pub buttons: PlayerButtons,
+}
+
+#[derive(Template)]
+#[template(path = "variants.html")]
+pub struct VariantsPage {
+    pub name: String,
+    /// Shown instead of the list when there are no variants to choose from.
+    pub message: Option<String>,
+    pub variants: Vec<VariantRow>,
+}
+
+#[derive(Template)]
+#[template(path = "watch.html")]
+pub struct WatchPage {
+    pub name: String,
+    pub channel_id: String,
+    pub hls_js_url: String,
+    pub mpegts_js_url: String,
+    pub src: String,
+    pub stream_type: &'static str,
+    /// Where the page has to POST to before `src` exists, for server-side conversions.
+    pub start_url: Option<String>,
+    pub transcode: bool,
+}
+
+#[derive(Template)]
+#[template(path = "images.html")]
+pub struct ImageCachePage {
+    pub entries: usize,
+    pub size: String,
+    pub max_size: Option<String>,
+    pub max_age_days: Option<u64>,
+    pub hits: u64,
+    pub misses: u64,
+    /// In percent, unknown until a logo has been requested.
+    pub hit_rate: Option<u64>,
+    pub failures: usize,
+    /// Progress of the `image_prefetch` job, if it has run.
+    pub prefetch: Option<String>,
+}
+
+pub struct TranscodeRow {
+    pub channel_id: String,
+    pub channel_name: String,
+    pub mode: String,
+    pub running_secs: u64,
+    pub idle_secs: u64,
+    pub state: String,
+}
+
+#[derive(Template)]
+#[template(path = "transcodes.html")]
+pub struct TranscodesPage {
+    pub enabled: bool,
+    pub idle_timeout_secs: u64,
+    pub sessions: Vec<TranscodeRow>,
+}
+
+pub struct RecordingRow {
+    pub id: u64,
+    pub state: &'static str,
+    pub title: String,
+    pub file_name: String,
+    pub size: String,
+    pub details: String,
+    /// Still recording, so it can be stopped but not deleted.
+    pub active: bool,
+}
+
+#[derive(Template)]
+#[template(path = "recordings.html")]
+pub struct RecordingsPage {
+    pub dir: String,
+    pub uses_ffmpeg: bool,
+    pub recordings: Vec<RecordingRow>,
+}
+
+pub struct ProgrammeRow {
+    pub start: i64,
+    pub airing: bool,
+    pub time: String,
+    pub title: String,
+    pub scheduled: bool,
+    pub description: Option<String>,
+}
+
+#[derive(Template)]
+#[template(path = "guide.html")]
+pub struct GuidePage {
+    pub name: String,
+    pub channel_id: String,
+    pub notice: Option<&'static str>,
+    pub programmes: Vec<ProgrammeRow>,
+}
+
+pub struct ScheduleRow {
+    pub id: u64,
+    pub state: &'static str,
+    pub time: String,
+    pub title: String,
+    pub channel_name: String,
+    pub window: String,
+    pub status: String,
+    /// Not recorded yet or still recording, so removing it cancels the recording.
+    pub pending: bool,
+}
+
+#[derive(Template)]
+#[template(path = "schedule.html")]
+pub struct SchedulePage {
+    pub entries: Vec<ScheduleRow>,
+}
+
+#[derive(Template)]
+#[template(path = "login.html")]
+pub struct LoginPage {
+    pub next: String,
+    pub failed: bool,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::playlist::ChannelKind;
+
+    const PAYLOAD: &str = r#"<script>alert(1)</script>"'); alert(2); //"#;
+
+    fn hostile_channel(url: &str) -> Channel {
+        Channel {
+            id: "0123456789abcdef".to_string(),
+            name: PAYLOAD.to_string(),
+            url: url.to_string(),
+            icon_url: PAYLOAD.to_string(),
+            kind: ChannelKind::Live,
+            tvg_id: None,
+            headers: Vec::new(),
+        }
+    }
+
+    fn buttons(channel_id: &str, player: &str) -> PlayerButtons {
+        PlayerButtons {
+            channel_id: channel_id.to_string(),
+            variant: None,
+            buttons: vec![PlayButton { player: player.to_string(), label: PAYLOAD.to_string(), enqueue: false }],
+            cast: true,
+        }
+    }
+
+    fn list_page<'a>(channels: &'a [Channel]) -> ChannelListPage<'a> {
+        ChannelListPage {
+            title: PAYLOAD.to_string(),
+            heading: format!("Search Results for \"{}\"", PAYLOAD),
+            list_id: "searchResults",
+            first: 1,
+            last: channels.len(),
+            total: channels.len(),
+            base_url: format!("/search?q={}", PAYLOAD),
+            sep: '&',
+            filters: vec![Link { url: PAYLOAD.to_string(), label: PAYLOAD.to_string() }],
+            pagination: vec![PageLink { label: PAYLOAD.to_string(), url: Some(PAYLOAD.to_string()) }],
+            rows: channels.iter().map(|channel| ChannelRow {
+                channel,
+                category: Some(PAYLOAD),
+                health: Badge { class: "unknown", label: "unchecked", title: PAYLOAD.to_string() },
+                media: Some(MediaLabel { class: "media-info", label: PAYLOAD.to_string() }),
+                is_hls: false,
+                buttons: buttons(&channel.id, "mpv"),
+            }).collect(),
+        }
+    }
+
+    /// The payload's tag and quotes must never reach the page as markup.
+    fn assert_inert(html: &str) {
+        assert!(!html.contains("<script>alert"), "unescaped tag in {}", html);
+        assert!(!html.contains("\"'); alert"), "unescaped quotes in {}", html);
+    }
+
+    #[test]
+    fn playlist_values_are_escaped_in_channel_lists() {
+        let channels = [hostile_channel(&format!("http://example.com/{}", PAYLOAD))];
+        let html = list_page(&channels).render().unwrap();
+
+        assert_inert(&html);
+        assert!(html.contains("&#60;script&#62;alert(1)&#60;/script&#62;&#34;&#39;); alert(2); //"));
+        assert!(html.contains(r#"<img src="/logo/0123456789abcdef" loading="lazy""#));
+    }
+
+    #[test]
+    fn script_urls_are_not_linked() {
+        let channels = [hostile_channel("javascript:alert(3)"), hostile_channel(" JavaScript:alert(3)")];
+        let html = list_page(&channels).render().unwrap();
+
+        assert!(!html.to_lowercase().contains(r#"href="javascript:"#));
+        assert!(!html.to_lowercase().contains(r#"href=" javascript:"#));
+        assert!(!is_stream_url("data:text/html,<script>alert(3)</script>"));
+        assert!(!is_stream_url("javascript://%0aalert(3)"));
+        assert!(is_stream_url("HTTPS://example.com/live.m3u8"));
+    }
+
+    #[test]
+    fn handler_arguments_stay_inside_their_javascript_strings() {
+        let channels = [hostile_channel("http://example.com/live.m3u8")];
+        let mut page = list_page(&channels);
+        page.rows[0].buttons = buttons(PAYLOAD, PAYLOAD);
+        let html = page.render().unwrap();
+
+        assert_inert(&html);
+        // Quoted as a JSON string, so the browser hands the payload to the function as one argument
+        let argument = r#"&#34;\u003cscript\u003ealert(1)\u003c/script\u003e\&#34;\u0027); alert(2); //&#34;"#;
+        assert!(html.contains(&format!("onclick=\"playChannel({}, {}, null, false)\"", argument, argument)));
+        assert!(html.contains(&format!("onclick=\"castChannel(this, {}, null)\"", argument)));
+    }
+
+    #[test]
+    fn login_target_cannot_leave_the_form_action() {
+        let html = LoginPage { next: PAYLOAD.to_string(), failed: true }.render().unwrap();
+
+        assert_inert(&html);
+        assert!(html.contains(r#"action="/login?next=%3Cscript%3Ealert%281%29%3C%2Fscript%3E%22%27%29%3B%20alert%282%29%3B%20%2F%2F""#));
+    }
+}
@@ -0,0 +1,163 @@
+use std::collections::HashMap;
+use std::collections::VecDeque;
+use std::process::Child;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Instant;
+use serde::Serialize;
+use log::warn;
+
+/// How many failed players `/players` remembers.
+const MAX_FAILURES: usize = 10;
+
+struct RunningPlayer {
+    player: String,
+    url: String,
+    child: Child,
+    started_at: Instant,
+}
+
+#[derive(Serialize)]
+pub struct PlayerStatus {
+    pub id: u64,
+    pub player: String,
+    pub url: String,
+    pub pid: u32,
+    pub running_secs: u64,
+}
+
+/// A player that exited with an error, such as a bad URL or missing libraries.
+#[derive(Serialize, Clone)]
+pub struct PlayerFailure {
+    pub id: u64,
+    pub player: String,
+    pub url: String,
+    /// How the process ended, e.g. `exit status: 1`.
+    pub status: String,
+    pub ran_secs: u64,
+}
+
+/// Keeps track of external players launched by `play()` so they can be listed and stopped.
+#[derive(Default)]
+pub struct PlayerRegistry {
+    next_id: AtomicU64,
+    players: Mutex<HashMap<u64, RunningPlayer>>,
+    /// The most recent failures first.
+    failures: Mutex<VecDeque<PlayerFailure>>,
+}
+
+impl PlayerRegistry {
+    pub fn register(&self, player: &str, url: &str, child: Child) -> u64 {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed) + 1;
+        let mut players = self.players.lock().unwrap();
+        self.prune(&mut players);
+        players.insert(id, RunningPlayer {
+            player: player.to_string(),
+            url: url.to_string(),
+            child,
+            started_at: Instant::now(),
+        });
+        id
+    }
+
+    /// Forgets players that have exited, recording the ones that failed.
+    pub fn check(&self) {
+        self.prune(&mut self.players.lock().unwrap());
+    }
+
+    pub fn failures(&self) -> Vec<PlayerFailure> {
+        self.failures.lock().unwrap().iter().cloned().collect()
+    }
+
+    pub fn list(&self) -> Vec<PlayerStatus> {
+        let mut players = self.players.lock().unwrap();
+        self.prune(&mut players);
+        let mut statuses: Vec<PlayerStatus> = players.iter().map(|(id, running)| PlayerStatus {
+            id: *id,
+            player: running.player.clone(),
+            url: running.url.clone(),
+            pid: running.child.id(),
+            running_secs: running.started_at.elapsed().as_secs(),
+        }).collect();
+        statuses.sort_by_key(|s| s.id);
+        statuses
+    }
+
+    /// Kills a player and waits for it to exit on a blocking thread, so no worker is held up.
+    pub async fn stop(&self, id: u64) -> std::io::Result<bool> {
+        let running = self.players.lock().unwrap().remove(&id);
+        let Some(mut running) = running else { return Ok(false) };
+        running.child.kill()?;
+        tokio::task::spawn_blocking(move || running.child.wait())
+            .await
+            .map_err(std::io::Error::other)??;
+        Ok(true)
+    }
+
+    /// Drops players that have exited, which also reaps their zombie processes.
+    fn prune(&self, players: &mut HashMap<u64, RunningPlayer>) {
+        players.retain(|id, running| {
+            let status = match running.child.try_wait() {
+                Ok(None) => return true,
+                Ok(Some(status)) if status.success() => return false,
+                Ok(Some(status)) => status.to_string(),
+                Err(e) => e.to_string(),
+            };
+            warn!("{} exited with {}", running.player, status);
+            let mut failures = self.failures.lock().unwrap();
+            failures.push_front(PlayerFailure {
+                id: *id,
+                player: running.player.clone(),
+                url: running.url.clone(),
+                status,
+                ran_secs: running.started_at.elapsed().as_secs(),
+            });
+            failures.truncate(MAX_FAILURES);
+            false
+        });
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use std::process::Command;
+
+    #[tokio::test]
+    async fn stop_kills_and_forgets_the_player() {
+        let players = PlayerRegistry::default();
+        let child = Command::new("sleep").arg("30").spawn().unwrap();
+        let id = players.register("sleep", "http://example.com/live.ts", child);
+        assert_eq!(players.list().len(), 1);
+
+        assert!(players.stop(id).await.unwrap());
+        assert!(players.list().is_empty());
+        assert!(!players.stop(id).await.unwrap());
+        // Stopping a player is not a failure
+        assert!(players.failures().is_empty());
+    }
+
+    #[tokio::test]
+    async fn players_that_fail_are_reported() {
+        let players = PlayerRegistry::default();
+        let failing = players.register("false", "http://example.com/bad.ts", Command::new("false").spawn().unwrap());
+        players.register("true", "http://example.com/good.ts", Command::new("true").spawn().unwrap());
+        tokio::time::sleep(std::time::Duration::from_millis(300)).await;
+
+        players.check();
+        assert!(players.list().is_empty());
+        let failures = players.failures();
+        assert_eq!(failures.len(), 1);
+        assert_eq!((failures[0].id, failures[0].player.as_str()), (failing, "false"));
+        assert_eq!(failures[0].status, "exit status: 1");
+
+        for _ in 0..MAX_FAILURES {
+            players.register("false", "http://example.com/bad.ts", Command::new("false").spawn().unwrap());
+        }
+        tokio::time::sleep(std::time::Duration::from_millis(300)).await;
+        players.check();
+        let failures = players.failures();
+        assert_eq!(failures.len(), MAX_FAILURES);
+        assert!(failures.iter().all(|failure| failure.id != failing));
+    }
+}
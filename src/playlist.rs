@@ -0,0 +1,448 @@
+use std::collections::{HashMap, HashSet};
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+use serde::Serialize;
+use sha2::{Sha256, Digest};
+
+#[derive(Serialize, Clone, Copy, PartialEq, Eq, Debug)]
+pub enum ChannelKind {
+    Live,
+    Movie,
+    Series,
+}
+
+#[derive(Serialize, Clone)]
+pub struct Channel {
+    pub id: String,
+    pub name: String,
+    pub url: String,
+    pub icon_url: String,
+    pub kind: ChannelKind,
+    /// The `tvg-id` attribute, which links the channel to its programmes in an XMLTV guide.
+    pub tvg_id: Option<String>,
+    /// HTTP headers the stream requires, from `#EXTVLCOPT` and `#EXTHTTP` lines.
+    pub headers: Vec<(String, String)>,
+}
+
+#[derive(Serialize)]
+pub struct Category {
+    pub name: String,
+    pub channels: Vec<Channel>,
+}
+
+#[derive(Serialize, Clone)]
+pub struct Episode {
+    pub season: u32,
+    pub episode: u32,
+    pub channel: Channel,
+}
+
+#[derive(Serialize)]
+pub struct Show {
+    pub name: String,
+    pub episodes: Vec<Episode>,
+}
+
+impl Show {
+    /// Returns the distinct season numbers in ascending order.
+    pub fn seasons(&self) -> Vec<u32> {
+        let mut seasons: Vec<u32> = self.episodes.iter().map(|e| e.season).collect();
+        seasons.sort_unstable();
+        seasons.dedup();
+        seasons
+    }
+
+    pub fn season(&self, season: u32) -> Vec<&Episode> {
+        self.episodes.iter().filter(|e| e.season == season).collect()
+    }
+}
+
+/// Everything loaded from the playlist, split into live channels, movies and series.
+#[derive(Default)]
+pub struct Library {
+    pub live: HashMap<String, Category>,
+    pub movies: HashMap<String, Category>,
+    pub shows: HashMap<String, Show>,
+    index: HashMap<String, (ChannelKind, String, usize)>,
+    tvg_ids: HashSet<String>,
+    icon_urls: HashSet<String>,
+}
+
+impl Library {
+    fn add(&mut self, category_name: String, channel: Channel) {
+        match channel.kind {
+            ChannelKind::Live | ChannelKind::Movie => {
+                let map = if channel.kind == ChannelKind::Live { &mut self.live } else { &mut self.movies };
+                map.entry(category_name.clone())
+                    .or_insert(Category {
+                        name: category_name,
+                        channels: Vec::new(),
+                    })
+                    .channels
+                    .push(channel);
+            }
+            ChannelKind::Series => {
+                let (show_name, season, episode) = parse_episode(&channel.name)
+                    .unwrap_or_else(|| (channel.name.clone(), 1, 0));
+                let show = self.shows.entry(show_name.clone()).or_insert(Show {
+                    name: show_name,
+                    episodes: Vec::new(),
+                });
+                let episode = if episode == 0 { show.episodes.len() as u32 + 1 } else { episode };
+                show.episodes.push(Episode { season, episode, channel });
+            }
+        }
+    }
+
+    fn sort_episodes(&mut self) {
+        for show in self.shows.values_mut() {
+            show.episodes.sort_by_key(|e| (e.season, e.episode));
+        }
+    }
+
+    fn build_index(&mut self) {
+        let mut index = HashMap::new();
+        for (kind, categories) in [(ChannelKind::Live, &self.live), (ChannelKind::Movie, &self.movies)] {
+            for category in categories.values() {
+                for (i, channel) in category.channels.iter().enumerate() {
+                    index.entry(channel.id.clone()).or_insert((kind, category.name.clone(), i));
+                }
+            }
+        }
+        for show in self.shows.values() {
+            for (i, episode) in show.episodes.iter().enumerate() {
+                index.entry(episode.channel.id.clone()).or_insert((ChannelKind::Series, show.name.clone(), i));
+            }
+        }
+        self.index = index;
+
+        self.tvg_ids = self.live.values()
+            .flat_map(|category| category.channels.iter())
+            .filter_map(|channel| channel.tvg_id.clone())
+            .collect();
+
+        self.icon_urls = self.all_channels()
+            .map(|(_, channel)| channel.icon_url.clone())
+            .filter(|url| url.starts_with("http://") || url.starts_with("https://"))
+            .collect();
+    }
+
+    /// Looks up a channel by the ID derived from its stream URL.
+    pub fn channel(&self, id: &str) -> Option<&Channel> {
+        let (kind, key, i) = self.index.get(id)?;
+        match kind {
+            ChannelKind::Live => self.live.get(key)?.channels.get(*i),
+            ChannelKind::Movie => self.movies.get(key)?.channels.get(*i),
+            ChannelKind::Series => self.shows.get(key)?.episodes.get(*i).map(|e| &e.channel),
+        }
+    }
+
+    /// The `tvg-id`s of all live channels, used to skip guide entries for other channels.
+    pub fn tvg_ids(&self) -> impl Iterator<Item = &str> {
+        self.tvg_ids.iter().map(String::as_str)
+    }
+
+    /// Whether a channel of the playlist uses this logo. Only these are fetched for the image cache.
+    pub fn has_icon(&self, url: &str) -> bool {
+        self.icon_urls.contains(url)
+    }
+
+    /// Returns the channel `offset` places away from `id` within the same category, season or show.
+    pub fn neighbour(&self, id: &str, offset: isize) -> Option<&Channel> {
+        let (kind, key, i) = self.index.get(id)?;
+        let i = i.checked_add_signed(offset)?;
+        match kind {
+            ChannelKind::Live => self.live.get(key)?.channels.get(i),
+            ChannelKind::Movie => self.movies.get(key)?.channels.get(i),
+            ChannelKind::Series => self.shows.get(key)?.episodes.get(i).map(|e| &e.channel),
+        }
+    }
+
+    /// Iterates over every channel together with the name of the category or show it belongs to.
+    pub fn all_channels(&self) -> impl Iterator<Item = (&str, &Channel)> {
+        self.live.values()
+            .chain(self.movies.values())
+            .flat_map(|category| category.channels.iter().map(move |c| (category.name.as_str(), c)))
+            .chain(self.shows.values()
+                .flat_map(|show| show.episodes.iter().map(move |e| (show.name.as_str(), &e.channel))))
+    }
+}
+
+const MOVIE_EXTENSIONS: [&str; 8] = [".mp4", ".mkv", ".avi", ".mov", ".wmv", ".m4v", ".mpg", ".webm"];
+
+/// Guesses whether an entry is a live channel, a movie or a series episode from its URL and name.
+pub fn classify_channel(name: &str, url: &str) -> ChannelKind {
+    let lower_url = url.to_lowercase();
+    if lower_url.contains("/series/") {
+        return ChannelKind::Series;
+    }
+    if lower_url.contains("/movie/") || lower_url.contains("/movies/") {
+        return ChannelKind::Movie;
+    }
+    if parse_episode(name).is_some() {
+        return ChannelKind::Series;
+    }
+    let path = lower_url.split(['?', '#']).next().unwrap_or("");
+    if MOVIE_EXTENSIONS.iter().any(|ext| path.ends_with(ext)) {
+        return ChannelKind::Movie;
+    }
+    ChannelKind::Live
+}
+
+/// Extracts `(show, season, episode)` from names like `Show Name S01E02`, `Show - S1 E2` or `Show 1x02`.
+pub fn parse_episode(name: &str) -> Option<(String, u32, u32)> {
+    let bytes = name.as_bytes();
+    for start in 0..bytes.len() {
+        if start > 0 && bytes[start - 1].is_ascii_alphanumeric() {
+            continue;
+        }
+        if let Some((season, episode)) = season_episode_at(name, start) {
+            let show = name[..start].trim_end_matches(|c: char| c.is_whitespace() || "-|._:".contains(c));
+            let show = if show.is_empty() { name.trim() } else { show };
+            return Some((show.to_string(), season, episode));
+        }
+    }
+    None
+}
+
+/// Parses `S01E02`, `S1 E2` or `1x02` starting at `start`.
+fn season_episode_at(name: &str, start: usize) -> Option<(u32, u32)> {
+    let bytes = name.as_bytes();
+    let digits_end = |from: usize| from + bytes[from..].iter().take_while(|b| b.is_ascii_digit()).count();
+
+    if bytes[start].eq_ignore_ascii_case(&b'S') {
+        let season_end = digits_end(start + 1);
+        if !(1..=2).contains(&(season_end - start - 1)) {
+            return None;
+        }
+        let mut i = season_end;
+        while i < bytes.len() && bytes[i] == b' ' {
+            i += 1;
+        }
+        if i >= bytes.len() || !bytes[i].eq_ignore_ascii_case(&b'E') {
+            return None;
+        }
+        let episode_end = digits_end(i + 1);
+        if !(1..=3).contains(&(episode_end - i - 1)) {
+            return None;
+        }
+        return Some((name[start + 1..season_end].parse().ok()?, name[i + 1..episode_end].parse().ok()?));
+    }
+
+    // `1x02` needs a two or three digit episode standing on its own, so sizes like `1920x1080` don't match.
+    let season_end = digits_end(start);
+    if !(1..=2).contains(&(season_end - start))
+        || season_end >= bytes.len()
+        || !bytes[season_end].eq_ignore_ascii_case(&b'x')
+    {
+        return None;
+    }
+    let episode_end = digits_end(season_end + 1);
+    if !(2..=3).contains(&(episode_end - season_end - 1))
+        || bytes.get(episode_end).is_some_and(|b| b.is_ascii_alphanumeric())
+    {
+        return None;
+    }
+    Some((name[start..season_end].parse().ok()?, name[season_end + 1..episode_end].parse().ok()?))
+}
+
+/// Stable identifier for a channel, derived from its stream URL so it survives reloads.
+pub fn channel_id(url: &str) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(url);
+    format!("{:x}", hasher.finalize())[..16].to_string()
+}
+
+pub fn clean_channel_name(name: &str) -> String {
+    let name = name.trim_start_matches("tvg-id=\"\"").trim();
+    let name = name.trim_start_matches("tvg-name=\"").trim();
+    if let Some(index) = name.find('"') {
+        name[..index].trim().to_string()
+    } else {
+        name.to_string()
+    }
+}
+
+/// Schemes of the stream URLs the loader accepts. Only http(s) streams can be proxied and checked,
+/// the others are played by the players or through ffmpeg.
+pub const STREAM_SCHEMES: [&str; 10] = ["http", "https", "rtmp", "rtmps", "rtsp", "rtp", "udp", "mms", "mmsh", "srt"];
+
+pub fn is_stream_url(url: &str) -> bool {
+    url.split_once("://").is_some_and(|(scheme, _)| STREAM_SCHEMES.iter().any(|s| s.eq_ignore_ascii_case(scheme)))
+}
+
+/// Whether a stream can be fetched over HTTP, by the proxy and the health checker.
+pub fn is_http_url(url: &str) -> bool {
+    url.split_once("://").is_some_and(|(scheme, _)| scheme.eq_ignore_ascii_case("http") || scheme.eq_ignore_ascii_case("https"))
+}
+
+pub fn load_playlist(playlist_path: &str) -> std::io::Result<Library> {
+    let file = File::open(playlist_path)?;
+    let reader = BufReader::new(file);
+
+    let mut library = Library::default();
+    let mut current_category: Option<String> = None;
+    let mut current_channel: Option<Channel> = None;
+
+    for line in reader.lines() {
+        let line = line?;
+
+        if line.starts_with("#EXTINF") {
+            let parts: Vec<&str> = line.split(',').collect();
+            let mut category_name = if parts[0].contains("group-title=\"\"") || parts[0].contains("group=\"\"") {
+                "No Category".to_string()
+            } else {
+                "Uncategorized".to_string()
+            };
+            let mut channel_name = clean_channel_name(parts[0].trim_start_matches("#EXTINF:-1").trim());
+            let mut icon_url = "".to_string();
+            let mut tvg_id = None;
+
+            // Check for the most common ways to identify categories or groups
+            if let Some(group_title_index) = parts[0].find("group-title=\"") {
+                let group_title_start = group_title_index + "group-title=\"".len();
+                if let Some(group_title_end) = parts[0][group_title_start..].find('"') {
+                    category_name = parts[0][group_title_start..group_title_start + group_title_end].to_string();
+                    channel_name = clean_channel_name(parts[1].trim());
+                }
+            } else if let Some(group_index) = parts[0].find("group=\"") {
+                let group_start = group_index + "group=\"".len();
+                if let Some(group_end) = parts[0][group_start..].find('"') {
+                    category_name = parts[0][group_start..group_start + group_end].to_string();
+                    channel_name = clean_channel_name(parts[1].trim());
+                }
+            }
+
+            // Check for the icon or thumbnail URL
+            if let Some(tvg_logo_index) = parts[0].find("tvg-logo=\"") {
+                let tvg_logo_start = tvg_logo_index + "tvg-logo=\"".len();
+                if let Some(tvg_logo_end) = parts[0][tvg_logo_start..].find('"') {
+                    icon_url = parts[0][tvg_logo_start..tvg_logo_start + tvg_logo_end].to_string();
+                }
+            }
+
+            // Check for the guide ID
+            if let Some(tvg_id_index) = parts[0].find("tvg-id=\"") {
+                let tvg_id_start = tvg_id_index + "tvg-id=\"".len();
+                if let Some(tvg_id_end) = parts[0][tvg_id_start..].find('"') {
+                    let id = parts[0][tvg_id_start..tvg_id_start + tvg_id_end].trim();
+                    if !id.is_empty() {
+                        tvg_id = Some(id.to_string());
+                    }
+                }
+            }
+
+            if category_name.is_empty() {
+                category_name = "No Category".to_string();
+            }
+
+            current_category = Some(category_name.clone());
+            current_channel = Some(Channel {
+                id: "".to_string(),
+                name: channel_name,
+                url: "".to_string(),
+                icon_url: if icon_url.is_empty() { "/static/placeholder.png".to_string() } else { icon_url },
+                kind: ChannelKind::Live,
+                tvg_id,
+                headers: Vec::new(),
+            });
+        } else if let Some(option) = line.strip_prefix("#EXTVLCOPT:") {
+            if let Some(channel) = current_channel.as_mut() {
+                let header = match option.split_once('=') {
+                    Some(("http-user-agent", value)) => Some(("User-Agent", value)),
+                    Some(("http-referrer", value)) | Some(("http-referer", value)) => Some(("Referer", value)),
+                    Some(("http-origin", value)) => Some(("Origin", value)),
+                    _ => None,
+                };
+                if let Some((name, value)) = header {
+                    channel.headers.push((name.to_string(), value.trim().to_string()));
+                }
+            }
+        } else if let Some(json) = line.strip_prefix("#EXTHTTP:") {
+            if let Some(channel) = current_channel.as_mut() {
+                if let Ok(serde_json::Value::Object(map)) = serde_json::from_str(json) {
+                    for (name, value) in map {
+                        if let Some(value) = value.as_str() {
+                            channel.headers.push((name, value.to_string()));
+                        }
+                    }
+                }
+            }
+        } else if is_stream_url(&line) {
+            if let Some(category_name) = current_category.clone() {
+                if let Some(channel) = current_channel.take() {
+                    let kind = classify_channel(&channel.name, &line);
+                    library.add(category_name, Channel {
+                        id: channel_id(&line),
+                        url: line.clone(),
+                        kind,
+                        ..channel
+                    });
+                }
+            }
+        }
+    }
+
+    library.sort_episodes();
+    library.build_index();
+    Ok(library)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn load(name: &str, contents: &str) -> Library {
+        let path = std::env::temp_dir().join(format!("m3u_browser_{}_{}.m3u", name, std::process::id()));
+        std::fs::write(&path, contents).unwrap();
+        let library = load_playlist(path.to_str().unwrap()).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        library
+    }
+
+    #[test]
+    fn loader_keeps_udp_and_rtmp_streams() {
+        let library = load("schemes", concat!(
+            "#EXTM3U\n",
+            "#EXTINF:-1 group-title=\"Local\",Multicast\nudp://@239.0.0.1:1234\n",
+            "#EXTINF:-1 group-title=\"Local\",Rtmp\nrtmp://example.com/live/stream\n",
+            "#EXTINF:-1 group-title=\"Local\",Web\nhttp://example.com/live.m3u8\n",
+            "#EXTINF:-1 group-title=\"Local\",Script\njavascript:alert(1)\n",
+        ));
+        let urls: Vec<&str> = library.live["Local"].channels.iter().map(|c| c.url.as_str()).collect();
+        assert_eq!(urls, ["udp://@239.0.0.1:1234", "rtmp://example.com/live/stream", "http://example.com/live.m3u8"]);
+        assert!(library.channel(&channel_id("udp://@239.0.0.1:1234")).is_some());
+        assert!(!is_http_url("udp://@239.0.0.1:1234"));
+        assert!(is_http_url("HTTPS://example.com/live.m3u8"));
+    }
+
+    #[test]
+    fn episodes_are_parsed_from_common_name_formats() {
+        assert_eq!(parse_episode("Show Name S01E02"), Some(("Show Name".to_string(), 1, 2)));
+        assert_eq!(parse_episode("Show - s1 e12"), Some(("Show".to_string(), 1, 12)));
+        assert_eq!(parse_episode("Other Show 1x02"), Some(("Other Show".to_string(), 1, 2)));
+        assert_eq!(parse_episode("Other Show | 10X103 The End"), Some(("Other Show".to_string(), 10, 103)));
+        assert_eq!(parse_episode("S02E03"), Some(("S02E03".to_string(), 2, 3)));
+    }
+
+    #[test]
+    fn unparsable_titles_are_not_episodes() {
+        assert_eq!(parse_episode("News 24"), None);
+        assert_eq!(parse_episode("Sports HD"), None);
+        assert_eq!(parse_episode("Movie 1920x1080"), None);
+        assert_eq!(parse_episode("Channel 4x4 Offroad"), None);
+        assert_eq!(parse_episode("BoxSet S123E01"), None);
+        assert_eq!(parse_episode("Classic S01"), None);
+    }
+
+    #[test]
+    fn channels_are_classified_by_url_then_name() {
+        assert_eq!(classify_channel("Anything", "http://host/series/user/pass/1.mkv"), ChannelKind::Series);
+        assert_eq!(classify_channel("Anything", "http://host/movie/user/pass/1.mkv"), ChannelKind::Movie);
+        assert_eq!(classify_channel("Show S01E01", "http://host/stream/1.mkv"), ChannelKind::Series);
+        assert_eq!(classify_channel("Show 2x05", "http://host/stream/2"), ChannelKind::Series);
+        assert_eq!(classify_channel("A Film", "http://host/films/a.MP4?token=1"), ChannelKind::Movie);
+        assert_eq!(classify_channel("News", "http://host/live/news.m3u8"), ChannelKind::Live);
+        assert_eq!(classify_channel("Multicast", "udp://@239.0.0.1:1234"), ChannelKind::Live);
+    }
+}
@@ -0,0 +1,299 @@
+use std::collections::HashMap;
+use std::process::Stdio;
+use std::sync::RwLock;
+use std::time::Duration;
+use futures::stream::{self, StreamExt};
+use serde::Serialize;
+use serde_json::Value;
+use tokio::process::Command;
+use log::info;
+use crate::health::unix_now;
+use crate::playlist::Channel;
+
+#[derive(Serialize, Clone, Debug, Default)]
+pub struct MediaInfo {
+    pub width: Option<u32>,
+    pub height: Option<u32>,
+    pub video_codec: Option<String>,
+    pub audio_codecs: Vec<String>,
+    pub audio_languages: Vec<String>,
+    pub bitrate: Option<u64>,
+    pub probed_at: u64,
+}
+
+impl MediaInfo {
+    /// Compact label such as `1080p HEVC · AAC · eng, fra · 4.2 Mbps`.
+    pub fn summary(&self) -> String {
+        let mut parts = Vec::new();
+        let video = [
+            self.height.map(|h| format!("{}p", h)),
+            self.video_codec.as_deref().map(codec_label),
+        ];
+        let video: Vec<String> = video.into_iter().flatten().collect();
+        if !video.is_empty() {
+            parts.push(video.join(" "));
+        }
+        if !self.audio_codecs.is_empty() {
+            parts.push(self.audio_codecs.iter().map(|c| codec_label(c)).collect::<Vec<_>>().join("/"));
+        }
+        if !self.audio_languages.is_empty() {
+            parts.push(self.audio_languages.join(", "));
+        }
+        if let Some(bitrate) = self.bitrate {
+            parts.push(format!("{:.1} Mbps", bitrate as f64 / 1_000_000.0));
+        }
+        parts.join(" · ")
+    }
+
+    pub fn has_codec(&self, codec: &str) -> bool {
+        let codec = normalize_codec(codec);
+        self.video_codec.iter().chain(self.audio_codecs.iter()).any(|c| normalize_codec(c) == codec)
+    }
+}
+
+#[derive(Serialize, Clone, Debug)]
+#[serde(tag = "result", rename_all = "lowercase")]
+pub enum ProbeResult {
+    Ok(MediaInfo),
+    Failed { error: String, probed_at: u64 },
+}
+
+/// Maps ffprobe codec names to the names people actually search for.
+fn codec_label(codec: &str) -> String {
+    match codec {
+        "h264" => "H.264".to_string(),
+        "hevc" => "HEVC".to_string(),
+        "mpeg2video" => "MPEG-2".to_string(),
+        "aac" => "AAC".to_string(),
+        "ac3" => "AC-3".to_string(),
+        "eac3" => "E-AC-3".to_string(),
+        "mp2" => "MP2".to_string(),
+        "mp3" => "MP3".to_string(),
+        other => other.to_uppercase(),
+    }
+}
+
+fn normalize_codec(codec: &str) -> String {
+    match codec.to_lowercase().replace(['.', '-'], "").as_str() {
+        "h265" | "hevc" => "hevc".to_string(),
+        "h264" | "avc" => "h264".to_string(),
+        other => other.to_string(),
+    }
+}
+
+/// Runs ffprobe against stream URLs and caches the result per channel ID.
+pub struct MediaProber {
+    ffprobe_path: Option<String>,
+    timeout: Duration,
+    concurrency: usize,
+    records: RwLock<HashMap<String, ProbeResult>>,
+}
+
+impl MediaProber {
+    pub fn from_config(config: &HashMap<String, String>) -> Self {
+        MediaProber {
+            ffprobe_path: config.get("ffprobe_path").cloned(),
+            timeout: Duration::from_secs(config.get("ffprobe_timeout_secs").and_then(|v| v.parse().ok()).unwrap_or(20)),
+            concurrency: config.get("ffprobe_concurrency").and_then(|v| v.parse().ok()).unwrap_or(2usize).max(1),
+            records: RwLock::new(HashMap::new()),
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.ffprobe_path.is_some()
+    }
+
+    pub fn get(&self, id: &str) -> Option<ProbeResult> {
+        self.records.read().unwrap().get(id).cloned()
+    }
+
+    pub fn media_info(&self, id: &str) -> Option<MediaInfo> {
+        match self.get(id) {
+            Some(ProbeResult::Ok(info)) => Some(info),
+            _ => None,
+        }
+    }
+
+    pub async fn probe(&self, channel: &Channel) -> Result<ProbeResult, String> {
+        let ffprobe_path = self.ffprobe_path.as_ref().ok_or("ffprobe path not specified in config")?;
+        let result = match run_ffprobe(ffprobe_path, channel, self.timeout).await {
+            Ok(info) => ProbeResult::Ok(info),
+            Err(error) => ProbeResult::Failed { error, probed_at: unix_now() },
+        };
+        self.records.write().unwrap().insert(channel.id.clone(), result.clone());
+        Ok(result)
+    }
+
+    /// Probes every channel that has not been probed yet.
+    pub async fn probe_all(&self, channels: Vec<Channel>) {
+        let pending: Vec<Channel> = channels.into_iter().filter(|channel| self.get(&channel.id).is_none()).collect();
+        let total = pending.len();
+        stream::iter(pending)
+            .for_each_concurrent(self.concurrency, |channel| async move {
+                let _ = self.probe(&channel).await;
+            })
+            .await;
+        info!("Probed {} channels with ffprobe", total);
+    }
+}
+
+/// Runs ffprobe with the headers the channel needs, the same way it is transcoded.
+async fn run_ffprobe(ffprobe_path: &str, channel: &Channel, timeout: Duration) -> Result<MediaInfo, String> {
+    let child = Command::new(ffprobe_path)
+        .args(["-v", "quiet", "-print_format", "json", "-show_streams", "-show_format"])
+        .args(crate::transcode::input_args(channel))
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .kill_on_drop(true)
+        .spawn()
+        .map_err(|e| format!("Failed to execute ffprobe: {}", e))?;
+
+    let output = tokio::time::timeout(timeout, child.wait_with_output())
+        .await
+        .map_err(|_| "ffprobe timed out".to_string())?
+        .map_err(|e| format!("Failed to wait for ffprobe: {}", e))?;
+
+    if !output.status.success() {
+        return Err("ffprobe could not open the stream".to_string());
+    }
+
+    let json: Value = serde_json::from_slice(&output.stdout).map_err(|e| format!("Invalid ffprobe output: {}", e))?;
+    Ok(parse_ffprobe_output(&json))
+}
+
+fn parse_ffprobe_output(json: &Value) -> MediaInfo {
+    let mut info = MediaInfo {
+        probed_at: unix_now(),
+        ..Default::default()
+    };
+
+    for stream in json["streams"].as_array().into_iter().flatten() {
+        let codec = stream["codec_name"].as_str().map(str::to_string);
+        match stream["codec_type"].as_str() {
+            Some("video") if info.video_codec.is_none() => {
+                info.video_codec = codec;
+                info.width = stream["width"].as_u64().map(|w| w as u32);
+                info.height = stream["height"].as_u64().map(|h| h as u32);
+            }
+            Some("audio") => {
+                if let Some(codec) = codec {
+                    if !info.audio_codecs.contains(&codec) {
+                        info.audio_codecs.push(codec);
+                    }
+                }
+                if let Some(language) = stream["tags"]["language"].as_str() {
+                    if language != "und" && !info.audio_languages.iter().any(|l| l == language) {
+                        info.audio_languages.push(language.to_string());
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+
+    // ffprobe reports numbers inside "format" as strings
+    info.bitrate = json["format"]["bit_rate"].as_str().and_then(|b| b.parse().ok());
+    info
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::playlist::ChannelKind;
+
+    const FFPROBE_OUTPUT: &str = r#"{
+        "streams": [
+            {"index": 0, "codec_name": "hevc", "codec_type": "video", "width": 1920, "height": 1080},
+            {"index": 1, "codec_name": "h264", "codec_type": "video", "width": 320, "height": 180},
+            {"index": 2, "codec_name": "aac", "codec_type": "audio", "tags": {"language": "eng"}},
+            {"index": 3, "codec_name": "ac3", "codec_type": "audio", "tags": {"language": "fra"}},
+            {"index": 4, "codec_name": "aac", "codec_type": "audio", "tags": {"language": "und"}},
+            {"index": 5, "codec_name": "aac", "codec_type": "audio", "tags": {"language": "eng"}},
+            {"index": 6, "codec_name": "dvb_subtitle", "codec_type": "subtitle", "tags": {"language": "deu"}},
+            {"index": 7, "codec_type": "data"}
+        ],
+        "format": {"format_name": "mpegts", "bit_rate": "4200000"}
+    }"#;
+
+    fn media_info(json: &str) -> MediaInfo {
+        parse_ffprobe_output(&serde_json::from_str(json).unwrap())
+    }
+
+    #[test]
+    fn ffprobe_output_is_summarised() {
+        let info = media_info(FFPROBE_OUTPUT);
+        // The first video stream is the main one
+        assert_eq!((info.width, info.height), (Some(1920), Some(1080)));
+        assert_eq!(info.video_codec.as_deref(), Some("hevc"));
+        assert_eq!(info.audio_codecs, ["aac", "ac3"]);
+        assert_eq!(info.audio_languages, ["eng", "fra"]);
+        assert_eq!(info.bitrate, Some(4200000));
+        assert_eq!(info.summary(), "1080p HEVC · AAC/AC-3 · eng, fra · 4.2 Mbps");
+    }
+
+    #[test]
+    fn incomplete_ffprobe_output_leaves_fields_empty() {
+        let radio = media_info(r#"{"streams": [{"codec_name": "mp2", "codec_type": "audio"}], "format": {"bit_rate": "N/A"}}"#);
+        assert_eq!(radio.video_codec, None);
+        assert_eq!(radio.height, None);
+        assert_eq!(radio.bitrate, None);
+        assert_eq!(radio.summary(), "MP2");
+        assert_eq!(media_info("{}").summary(), "");
+    }
+
+    #[test]
+    fn codec_filters_accept_common_spellings() {
+        let info = media_info(FFPROBE_OUTPUT);
+        for codec in ["hevc", "HEVC", "h265", "H.265", "h-265", "aac", "AC3"] {
+            assert!(info.has_codec(codec), "{}", codec);
+        }
+        // The second video stream is not what the channel is filtered by
+        for codec in ["h264", "avc", "mp3", ""] {
+            assert!(!info.has_codec(codec), "{}", codec);
+        }
+        let avc = MediaInfo { video_codec: Some("h264".to_string()), ..Default::default() };
+        assert!(avc.has_codec("AVC") && avc.has_codec("H.264"));
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn ffprobe_gets_the_channel_headers() {
+        use std::os::unix::fs::PermissionsExt;
+        let dir = std::env::temp_dir().join(format!("m3u_browser_probe_test_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        // Stands in for ffprobe: saves its arguments NUL-separated and prints a fixed result
+        let ffprobe = dir.join("ffprobe");
+        std::fs::write(&ffprobe, format!(
+            "#!/bin/sh\nprintf '%s\\0' \"$@\" > {}/args\necho '{}'\n",
+            dir.display(),
+            r#"{"streams": [{"codec_name": "h264", "codec_type": "video", "height": 720}]}"#,
+        )).unwrap();
+        std::fs::set_permissions(&ffprobe, std::fs::Permissions::from_mode(0o755)).unwrap();
+
+        let prober = MediaProber::from_config(&HashMap::from([("ffprobe_path".to_string(), ffprobe.display().to_string())]));
+        let channel = Channel {
+            id: "9".to_string(),
+            name: "Nine".to_string(),
+            url: "https://example.com/live.m3u8".to_string(),
+            icon_url: String::new(),
+            kind: ChannelKind::Live,
+            tvg_id: None,
+            headers: vec![
+                ("User-Agent".to_string(), "Player/1.0".to_string()),
+                ("Referer".to_string(), "https://example.com/".to_string()),
+            ],
+        };
+        assert!(matches!(prober.probe(&channel).await.unwrap(), ProbeResult::Ok(_)));
+        assert_eq!(prober.media_info("9").unwrap().height, Some(720));
+
+        let args = std::fs::read_to_string(dir.join("args")).unwrap();
+        let args: Vec<&str> = args.trim_end_matches('\0').split('\0').collect();
+        assert_eq!(args[args.len() - 6..], [
+            "-user_agent", "Player/1.0",
+            "-headers", "Referer: https://example.com/\r\n",
+            "-i", "https://example.com/live.m3u8",
+        ]);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
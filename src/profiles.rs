@@ -0,0 +1,256 @@
+use std::collections::{BTreeMap, HashMap};
+use std::process::{Command, Stdio};
+use log::warn;
+use crate::playlist::Channel;
+
+/// An external player configured in `config.txt`, e.g.
+///
+/// ```text
+/// player.ffplay.path = /usr/bin/ffplay
+/// player.ffplay.label = Play with ffplay
+/// player.ffplay.args = -window_title {title} -user_agent {user_agent} {url}
+/// ```
+#[derive(Clone, Debug)]
+pub struct PlayerProfile {
+    pub name: String,
+    pub label: String,
+    pub path: String,
+    pub args: Vec<String>,
+}
+
+/// Values substituted into a profile's argument template.
+pub struct PlayerVars<'a> {
+    pub url: &'a str,
+    pub title: &'a str,
+    pub user_agent: &'a str,
+    pub referrer: &'a str,
+    pub headers: String,
+}
+
+impl<'a> PlayerVars<'a> {
+    pub fn for_channel(url: &'a str, channel: Option<&'a Channel>) -> Self {
+        let header = |name: &str| {
+            channel
+                .and_then(|c| c.headers.iter().find(|(key, _)| key.eq_ignore_ascii_case(name)))
+                .map(|(_, value)| value.as_str())
+                .unwrap_or("")
+        };
+        PlayerVars {
+            url,
+            title: channel.map(|c| c.name.as_str()).unwrap_or(""),
+            user_agent: header("User-Agent"),
+            referrer: header("Referer"),
+            headers: channel
+                .map(|c| c.headers.iter().map(|(k, v)| format!("{}: {}", k, v)).collect::<Vec<_>>().join(","))
+                .unwrap_or_default(),
+        }
+    }
+}
+
+const DEFAULT_MPV_ARGS: &str =
+    "--force-media-title={title} --user-agent={user_agent} --referrer={referrer} --http-header-fields={headers} {url}";
+const DEFAULT_VLC_ARGS: &str =
+    "--meta-title={title} --http-user-agent={user_agent} --http-referrer={referrer} {url}";
+
+/// Splits an argument template on whitespace, keeping double-quoted sections together.
+fn split_template(template: &str) -> Vec<String> {
+    let mut args = Vec::new();
+    let mut current = String::new();
+    let mut in_quotes = false;
+    let mut has_token = false;
+    for c in template.chars() {
+        match c {
+            '"' => {
+                in_quotes = !in_quotes;
+                has_token = true;
+            }
+            c if c.is_whitespace() && !in_quotes => {
+                if has_token {
+                    args.push(std::mem::take(&mut current));
+                    has_token = false;
+                }
+            }
+            c => {
+                current.push(c);
+                has_token = true;
+            }
+        }
+    }
+    if has_token {
+        args.push(current);
+    }
+    args
+}
+
+fn is_valid_name(name: &str) -> bool {
+    !name.is_empty() && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+}
+
+impl PlayerProfile {
+    /// Expands the argument template. Arguments whose placeholders are all empty are
+    /// dropped, so `--user-agent={user_agent}` disappears for channels without one. When the
+    /// value is a separate argument (`-user_agent {user_agent}`), the option before it goes too.
+    /// A `{url}` argument of its own is preceded by `--`, so the player never reads the URL as
+    /// an option; options placed after it are taken as file names too.
+    pub fn expand_args(&self, vars: &PlayerVars) -> Vec<String> {
+        let placeholders = [
+            ("{url}", vars.url),
+            ("{title}", vars.title),
+            ("{user_agent}", vars.user_agent),
+            ("{referrer}", vars.referrer),
+            ("{headers}", vars.headers.as_str()),
+        ];
+        let mut args: Vec<String> = Vec::new();
+        let mut previous_is_option = false;
+        for arg in &self.args {
+            let mut expanded = arg.clone();
+            let mut used = false;
+            let mut filled = false;
+            for (placeholder, value) in placeholders {
+                if expanded.contains(placeholder) {
+                    used = true;
+                    filled |= !value.is_empty();
+                    expanded = expanded.replace(placeholder, value);
+                }
+            }
+            if used && !filled {
+                if previous_is_option {
+                    args.pop();
+                }
+                previous_is_option = false;
+                continue;
+            }
+            previous_is_option = !used && arg.starts_with('-') && !arg.contains('=');
+            if arg == "{url}" {
+                args.push("--".to_string());
+            }
+            args.push(expanded);
+        }
+        args
+    }
+
+    pub fn command(&self, vars: &PlayerVars) -> Command {
+        let mut command = Command::new(&self.path);
+        // Players write plenty to the terminal, which nobody reads when started from the web page
+        command.args(self.expand_args(vars))
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null());
+        command
+    }
+}
+
+/// All configured players, in the order their buttons are shown.
+pub struct PlayerProfiles {
+    profiles: Vec<PlayerProfile>,
+}
+
+impl PlayerProfiles {
+    pub fn from_config(config: &HashMap<String, String>) -> Self {
+        let mut profiles = Vec::new();
+
+        // The original mpv_path / vlc_path settings keep working as built-in profiles
+        if let Some(path) = config.get("mpv_path") {
+            profiles.push(PlayerProfile {
+                name: "mpv".to_string(),
+                label: "Play with mpv".to_string(),
+                path: path.clone(),
+                args: split_template(DEFAULT_MPV_ARGS),
+            });
+        }
+        if let Some(path) = config.get("vlc_path") {
+            profiles.push(PlayerProfile {
+                name: "vlc".to_string(),
+                label: "Play with VLC".to_string(),
+                path: path.clone(),
+                args: split_template(DEFAULT_VLC_ARGS),
+            });
+        }
+
+        let mut custom: BTreeMap<&str, HashMap<&str, &String>> = BTreeMap::new();
+        for (key, value) in config {
+            if let Some((name, field)) = key.strip_prefix("player.").and_then(|rest| rest.rsplit_once('.')) {
+                custom.entry(name).or_default().insert(field, value);
+            }
+        }
+        for (name, fields) in custom {
+            if !is_valid_name(name) {
+                warn!("Ignoring player profile with invalid name: {}", name);
+                continue;
+            }
+            let Some(path) = fields.get("path") else {
+                warn!("Player profile {} has no path", name);
+                continue;
+            };
+            let args = split_template(fields.get("args").map(|a| a.as_str()).unwrap_or("{url}"));
+            // Only a `{url}` of its own can be put behind `--`; inside another argument a URL
+            // starting with `-` would reach the player as an option
+            if let Some(arg) = args.iter().find(|arg| arg.contains("{url}") && *arg != "{url}") {
+                warn!("Ignoring player profile {}: {{url}} has to be an argument of its own, not part of {}", name, arg);
+                continue;
+            }
+            let profile = PlayerProfile {
+                name: name.to_string(),
+                label: fields.get("label").map(|l| l.to_string()).unwrap_or_else(|| format!("Play with {}", name)),
+                path: path.to_string(),
+                args,
+            };
+            // A custom profile named mpv or vlc overrides the built-in one
+            match profiles.iter_mut().find(|p| p.name == name) {
+                Some(existing) => *existing = profile,
+                None => profiles.push(profile),
+            }
+        }
+
+        PlayerProfiles { profiles }
+    }
+
+    pub fn get(&self, name: &str) -> Option<&PlayerProfile> {
+        self.profiles.iter().find(|p| p.name == name)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &PlayerProfile> {
+        self.profiles.iter()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.profiles.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn profiles(entries: &[(&str, &str)]) -> PlayerProfiles {
+        PlayerProfiles::from_config(&entries.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect())
+    }
+
+    #[test]
+    fn url_is_passed_after_a_separator_and_empty_options_are_dropped() {
+        let profiles = profiles(&[
+            ("player.ffplay.path", "/usr/bin/ffplay"),
+            ("player.ffplay.args", "-window_title {title} -user_agent {user_agent} -fs {url}"),
+        ]);
+        let vars = PlayerVars { url: "-i/etc/passwd", title: "News", user_agent: "", referrer: "", headers: String::new() };
+        assert_eq!(
+            profiles.get("ffplay").unwrap().expand_args(&vars),
+            ["-window_title", "News", "-fs", "--", "-i/etc/passwd"]
+        );
+    }
+
+    #[test]
+    fn templates_embedding_the_url_are_rejected() {
+        let profiles = profiles(&[
+            ("mpv_path", "/usr/bin/mpv"),
+            ("player.mpv.path", "/usr/bin/mpv"),
+            ("player.mpv.args", "--playlist={url}"),
+            ("player.other.path", "/usr/bin/other"),
+            ("player.other.args", "\"file={url}\""),
+            ("player.plain.path", "/usr/bin/plain"),
+        ]);
+        let names: Vec<&str> = profiles.iter().map(|p| p.name.as_str()).collect();
+        assert_eq!(names, ["mpv", "plain"]);
+        assert_eq!(profiles.get("mpv").unwrap().args, split_template(DEFAULT_MPV_ARGS));
+    }
+}
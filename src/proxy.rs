@@ -0,0 +1,282 @@
+use std::collections::{HashMap, HashSet};
+use std::sync::RwLock;
+use std::time::Instant;
+use actix_web::http::StatusCode;
+use actix_web::HttpResponse;
+use urlencoding::encode;
+use log::error;
+use crate::hls;
+use crate::playlist::Channel;
+
+/// Headers copied from the upstream response so players can seek and size the stream.
+const FORWARDED_HEADERS: [&str; 4] = ["content-type", "content-length", "content-range", "accept-ranges"];
+
+/// Playlists whose hosts are remembered per channel. A playlist's own host is among the ones it
+/// allows, so a player that keeps refreshing it never loses access to it.
+const MAX_PLAYLISTS_PER_CHANNEL: usize = 32;
+/// Channels whose hosts are remembered, forgetting the ones least recently played.
+const MAX_CHANNELS: usize = 256;
+
+/// The hosts each of a channel's playlists referenced when it was last fetched, oldest first.
+struct AllowedHosts {
+    updated: Instant,
+    playlists: Vec<(String, HashSet<String>)>,
+}
+
+/// Relays channel streams to the browser, adding the headers each channel needs
+/// and routing every URL inside HLS playlists back through `/proxy/{id}`.
+pub struct StreamProxy {
+    client: reqwest::Client,
+    // Hosts each channel's playlists pointed at, so `?url=` cannot be used to fetch anything else
+    allowed_hosts: RwLock<HashMap<String, AllowedHosts>>,
+}
+
+fn host_of(url: &str) -> Option<String> {
+    reqwest::Url::parse(url).ok()?.host_str().map(str::to_lowercase)
+}
+
+pub fn proxy_url(id: &str, url: &str) -> String {
+    format!("/proxy/{}?url={}", id, encode(url))
+}
+
+/// Rewrites every URI in an HLS playlist (segments, variant playlists, keys, maps and renditions)
+/// to an absolute URL behind the proxy. Returns the rewritten playlist and the hosts it referenced.
+pub fn rewrite_playlist(id: &str, base_url: &str, text: &str) -> (String, HashSet<String>) {
+    let mut hosts = HashSet::new();
+    let mut rewrite = |uri: &str| {
+        let absolute = hls::resolve_url(base_url, uri);
+        if let Some(host) = host_of(&absolute) {
+            hosts.insert(host);
+        }
+        proxy_url(id, &absolute)
+    };
+
+    let mut output = String::with_capacity(text.len() * 2);
+    for line in text.lines() {
+        let trimmed = line.trim();
+        if trimmed.is_empty() {
+            output.push('\n');
+            continue;
+        }
+        if !trimmed.starts_with('#') {
+            output.push_str(&rewrite(trimmed));
+            output.push('\n');
+            continue;
+        }
+
+        let mut rest = trimmed;
+        while let Some(start) = rest.find("URI=\"") {
+            let value_start = start + "URI=\"".len();
+            let Some(value_len) = rest[value_start..].find('"') else { break };
+            output.push_str(&rest[..value_start]);
+            output.push_str(&rewrite(&rest[value_start..value_start + value_len]));
+            rest = &rest[value_start + value_len..];
+        }
+        output.push_str(rest);
+        output.push('\n');
+    }
+
+    (output, hosts)
+}
+
+impl StreamProxy {
+    pub fn new(client: reqwest::Client) -> Self {
+        StreamProxy {
+            client,
+            allowed_hosts: RwLock::new(HashMap::new()),
+        }
+    }
+
+    /// Replaces the hosts allowed by a playlist with the ones it referenced this time.
+    fn allow_hosts(&self, id: &str, playlist_url: &str, hosts: HashSet<String>) {
+        let mut allowed_hosts = self.allowed_hosts.write().unwrap();
+        if !allowed_hosts.contains_key(id) && allowed_hosts.len() >= MAX_CHANNELS {
+            let oldest = allowed_hosts.iter().min_by_key(|(_, allowed)| allowed.updated).map(|(id, _)| id.clone());
+            if let Some(oldest) = oldest {
+                allowed_hosts.remove(&oldest);
+            }
+        }
+        let allowed = allowed_hosts.entry(id.to_string()).or_insert_with(|| AllowedHosts { updated: Instant::now(), playlists: Vec::new() });
+        allowed.updated = Instant::now();
+        allowed.playlists.retain(|(url, _)| url != playlist_url);
+        allowed.playlists.push((playlist_url.to_string(), hosts));
+        if allowed.playlists.len() > MAX_PLAYLISTS_PER_CHANNEL {
+            allowed.playlists.remove(0);
+        }
+    }
+
+    fn is_allowed(&self, channel: &Channel, url: &str) -> bool {
+        let Some(host) = host_of(url) else { return false };
+        if !url.starts_with("http://") && !url.starts_with("https://") {
+            return false;
+        }
+        host_of(&channel.url).as_deref() == Some(host.as_str())
+            || self.allowed_hosts.read().unwrap().get(&channel.id)
+                .is_some_and(|allowed| allowed.playlists.iter().any(|(_, hosts)| hosts.contains(&host)))
+    }
+
+    /// Fetches `target` (or the channel URL itself) with the channel's headers and relays it.
+    pub async fn forward(&self, channel: &Channel, target: Option<&str>, range: Option<&str>) -> HttpResponse {
+        let url = match target {
+            Some(target) if self.is_allowed(channel, target) => target,
+            Some(_) => return HttpResponse::Forbidden().body("URL does not belong to this channel"),
+            None if crate::playlist::is_http_url(&channel.url) => channel.url.as_str(),
+            None => return HttpResponse::BadRequest().body("Only http(s) streams can be proxied, use transcoding for this channel"),
+        };
+
+        let mut request = self.client.get(url);
+        for (name, value) in &channel.headers {
+            request = request.header(name.as_str(), value.as_str());
+        }
+        if let Some(range) = range {
+            request = request.header(reqwest::header::RANGE, range);
+        }
+
+        let response = match request.send().await {
+            Ok(response) => response,
+            Err(e) => {
+                error!("Failed to fetch {} for proxy: {}", url, e);
+                return HttpResponse::BadGateway().body("Failed to reach upstream stream");
+            }
+        };
+
+        let status = StatusCode::from_u16(response.status().as_u16()).unwrap_or(StatusCode::BAD_GATEWAY);
+        if !status.is_success() {
+            return HttpResponse::build(status).finish();
+        }
+
+        let content_type = response.headers().get(reqwest::header::CONTENT_TYPE)
+            .and_then(|v| v.to_str().ok())
+            .unwrap_or("")
+            .to_lowercase();
+
+        if content_type.contains("mpegurl") || hls::is_hls_url(response.url().as_str()) {
+            let final_url = response.url().to_string();
+            let mut hosts = HashSet::new();
+            if let Some(host) = host_of(&final_url) {
+                hosts.insert(host);
+            }
+            let text = match hls::read_playlist(response).await {
+                Ok(text) => text,
+                Err(e) => {
+                    error!("Failed to read playlist {}: {}", url, e);
+                    return HttpResponse::BadGateway().finish();
+                }
+            };
+            let (playlist, playlist_hosts) = rewrite_playlist(&channel.id, &final_url, &text);
+            hosts.extend(playlist_hosts);
+            self.allow_hosts(&channel.id, &final_url, hosts);
+
+            return HttpResponse::Ok()
+                .content_type("application/vnd.apple.mpegurl")
+                .insert_header(("Cache-Control", "no-cache"))
+                .body(playlist);
+        }
+
+        let mut builder = HttpResponse::build(status);
+        for name in FORWARDED_HEADERS {
+            if let Some(value) = response.headers().get(name).and_then(|v| v.to_str().ok()) {
+                builder.insert_header((name, value.to_string()));
+            }
+        }
+        builder.streaming(response.bytes_stream())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::playlist::ChannelKind;
+
+    fn channel(url: &str) -> Channel {
+        Channel {
+            id: "7".to_string(),
+            name: "Seven".to_string(),
+            url: url.to_string(),
+            icon_url: String::new(),
+            kind: ChannelKind::Live,
+            tvg_id: None,
+            headers: Vec::new(),
+        }
+    }
+
+    fn hosts(names: &[&str]) -> HashSet<String> {
+        names.iter().map(|name| name.to_string()).collect()
+    }
+
+    #[test]
+    fn playlists_are_rewritten_through_the_proxy() {
+        let playlist = "#EXTM3U
+#EXT-X-KEY:METHOD=AES-128,URI=\"keys/1.bin\",IV=0x1
+#EXT-X-MAP:URI=\"https://init.example.org/init.mp4\"
+
+#EXTINF:6.0,
+segment1.ts
+#EXTINF:6.0,
+https://CDN.example.net/a/segment2.ts?x=1&y=2
+";
+        let (rewritten, referenced) = rewrite_playlist("7", "https://example.com/live/index.m3u8", playlist);
+        let lines: Vec<&str> = rewritten.lines().collect();
+        assert_eq!(lines, [
+            "#EXTM3U",
+            "#EXT-X-KEY:METHOD=AES-128,URI=\"/proxy/7?url=https%3A%2F%2Fexample.com%2Flive%2Fkeys%2F1.bin\",IV=0x1",
+            "#EXT-X-MAP:URI=\"/proxy/7?url=https%3A%2F%2Finit.example.org%2Finit.mp4\"",
+            "",
+            "#EXTINF:6.0,",
+            "/proxy/7?url=https%3A%2F%2Fexample.com%2Flive%2Fsegment1.ts",
+            "#EXTINF:6.0,",
+            "/proxy/7?url=https%3A%2F%2Fcdn.example.net%2Fa%2Fsegment2.ts%3Fx%3D1%26y%3D2",
+        ]);
+        assert_eq!(referenced, hosts(&["example.com", "init.example.org", "cdn.example.net"]));
+    }
+
+    #[test]
+    fn only_hosts_of_the_channel_and_its_playlists_are_fetched() {
+        let proxy = StreamProxy::new(reqwest::Client::new());
+        let channel = channel("https://example.com/live/index.m3u8");
+        assert!(proxy.is_allowed(&channel, "https://example.com/live/segment1.ts"));
+        assert!(!proxy.is_allowed(&channel, "https://cdn.example.net/segment2.ts"));
+        assert!(!proxy.is_allowed(&channel, "http://127.0.0.1:8183/config.txt"));
+        assert!(!proxy.is_allowed(&channel, "file:///etc/passwd"));
+        assert!(!proxy.is_allowed(&channel, "not a url"));
+
+        proxy.allow_hosts("7", "https://example.com/live/index.m3u8", hosts(&["example.com", "cdn.example.net"]));
+        assert!(proxy.is_allowed(&channel, "https://cdn.example.net/segment2.ts"));
+        assert!(!proxy.is_allowed(&channel, "ftp://cdn.example.net/segment2.ts"));
+        // Hosts belong to the channel whose playlist referenced them
+        let mut other = channel.clone();
+        other.id = "8".to_string();
+        assert!(!proxy.is_allowed(&other, "https://cdn.example.net/segment2.ts"));
+    }
+
+    #[test]
+    fn refreshed_playlists_replace_their_hosts() {
+        let proxy = StreamProxy::new(reqwest::Client::new());
+        let channel = channel("https://example.com/live/master.m3u8");
+        proxy.allow_hosts("7", "https://example.com/live/master.m3u8", hosts(&["example.com", "audio.example.org"]));
+        proxy.allow_hosts("7", "https://example.com/live/low.m3u8", hosts(&["example.com", "old.example.net"]));
+        proxy.allow_hosts("7", "https://example.com/live/low.m3u8", hosts(&["example.com", "new.example.net"]));
+        assert!(!proxy.is_allowed(&channel, "https://old.example.net/1.ts"));
+        assert!(proxy.is_allowed(&channel, "https://new.example.net/2.ts"));
+        assert!(proxy.is_allowed(&channel, "https://audio.example.org/en.m3u8"));
+
+        // Playlists with ever-changing URLs do not grow the list forever
+        for n in 0..MAX_PLAYLISTS_PER_CHANNEL {
+            proxy.allow_hosts("7", &format!("https://example.com/live/low.m3u8?token={}", n), hosts(&["example.com"]));
+        }
+        assert_eq!(proxy.allowed_hosts.read().unwrap()["7"].playlists.len(), MAX_PLAYLISTS_PER_CHANNEL);
+        assert!(!proxy.is_allowed(&channel, "https://audio.example.org/en.m3u8"));
+    }
+
+    #[test]
+    fn the_least_recently_played_channels_are_forgotten() {
+        let proxy = StreamProxy::new(reqwest::Client::new());
+        for n in 0..=MAX_CHANNELS {
+            proxy.allow_hosts(&n.to_string(), "https://example.com/index.m3u8", hosts(&["cdn.example.net"]));
+        }
+        let allowed_hosts = proxy.allowed_hosts.read().unwrap();
+        assert_eq!(allowed_hosts.len(), MAX_CHANNELS);
+        assert!(!allowed_hosts.contains_key("0"));
+        assert!(allowed_hosts.contains_key(&MAX_CHANNELS.to_string()));
+    }
+}
@@ -0,0 +1,21 @@
+/// `len` bytes from the operating system's random number generator, as lowercase hex. Used for
+/// secrets that must not be guessable: session tokens, passwords and private directory names.
+pub fn random_hex(len: usize) -> String {
+    let mut bytes = vec![0u8; len];
+    getrandom::getrandom(&mut bytes).expect("The operating system has no random number generator");
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn random_hex_has_two_digits_per_byte() {
+        let value = random_hex(16);
+        assert_eq!(value.len(), 32);
+        assert!(value.bytes().all(|b| b.is_ascii_hexdigit() && !b.is_ascii_uppercase()));
+        assert_ne!(value, random_hex(16));
+        assert_eq!(random_hex(0), "");
+    }
+}
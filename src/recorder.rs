@@ -0,0 +1,657 @@
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+use futures::StreamExt;
+use serde::Serialize;
+use tokio::fs::File;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::process::Command;
+use tokio::sync::watch;
+use tokio::time::Instant;
+use log::{info, warn};
+use crate::health::unix_now;
+use crate::hls;
+use crate::playlist::Channel;
+use crate::transcode;
+
+#[derive(Serialize, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum RecordingState {
+    Recording,
+    /// The duration elapsed or the stream ended.
+    Finished,
+    /// Stopped from the recordings page.
+    Stopped,
+    Failed,
+}
+
+impl RecordingState {
+    pub fn label(&self) -> &'static str {
+        match self {
+            RecordingState::Recording => "recording",
+            RecordingState::Finished => "finished",
+            RecordingState::Stopped => "stopped",
+            RecordingState::Failed => "failed",
+        }
+    }
+}
+
+struct Recording {
+    channel_id: String,
+    title: String,
+    file_name: String,
+    started_at: u64,
+    ends_at: Option<u64>,
+    state: RecordingState,
+    error: Option<String>,
+    stop: watch::Sender<bool>,
+}
+
+#[derive(Serialize)]
+pub struct RecordingStatus {
+    pub id: u64,
+    pub channel_id: String,
+    pub title: String,
+    pub file_name: String,
+    pub started_at: u64,
+    pub ends_at: Option<u64>,
+    pub state: RecordingState,
+    pub error: Option<String>,
+    pub size_bytes: u64,
+}
+
+/// Captures channels to MPEG-TS files, with ffmpeg when configured and by downloading
+/// the stream (or its HLS segments) directly otherwise.
+pub struct Recorder {
+    client: reqwest::Client,
+    ffmpeg_path: Option<String>,
+    dir: PathBuf,
+    next_id: AtomicU64,
+    recordings: Arc<Mutex<HashMap<u64, Recording>>>,
+}
+
+/// Everything a recording task needs, moved into the spawned task.
+struct Job {
+    id: u64,
+    client: reqwest::Client,
+    ffmpeg_path: Option<String>,
+    channel: Channel,
+    path: PathBuf,
+    duration: Option<Duration>,
+    stop: watch::Receiver<bool>,
+    recordings: Arc<Mutex<HashMap<u64, Recording>>>,
+}
+
+/// An `#EXT-X-MAP` init section: its URL and the byte range of it, if only part of the file.
+#[derive(Clone, PartialEq, Debug)]
+struct InitSection {
+    url: String,
+    range: Option<(u64, u64)>,
+}
+
+#[derive(PartialEq, Debug)]
+struct Segment {
+    url: String,
+    /// The init section that has to come before this segment in the file.
+    map: Option<InitSection>,
+}
+
+/// The segments of a media playlist, each with the `#EXT-X-MAP` in effect for it.
+fn media_segments(playlist_url: &str, text: &str) -> Vec<Segment> {
+    let mut segments = Vec::new();
+    let mut map = None;
+    for line in text.lines().map(str::trim) {
+        if let Some(attributes) = line.strip_prefix("#EXT-X-MAP:") {
+            let attributes = hls::parse_attributes(attributes);
+            map = attributes.get("URI").map(|uri| InitSection {
+                url: hls::resolve_url(playlist_url, uri),
+                // BYTERANGE is `length[@offset]`, the offset defaulting to the start of the file
+                range: attributes.get("BYTERANGE").and_then(|range| {
+                    let (length, offset) = range.split_once('@').unwrap_or((range, "0"));
+                    Some((offset.parse().ok()?, length.parse().ok()?))
+                }),
+            });
+        } else if !line.is_empty() && !line.starts_with('#') {
+            segments.push(Segment { url: hls::resolve_url(playlist_url, line), map: map.clone() });
+        }
+    }
+    segments
+}
+
+/// File extension for a recording made of these segments: fragmented MP4 when there is an
+/// init section, otherwise whatever the segments are named, MPEG-TS by default.
+fn recording_extension(segments: &[Segment]) -> &'static str {
+    let Some(first) = segments.first() else { return "ts" };
+    if first.map.is_some() {
+        return "mp4";
+    }
+    let path = first.url.to_ascii_lowercase();
+    let path = path.split(['?', '#']).next().unwrap_or("");
+    match path.rsplit_once('.').map(|(_, extension)| extension) {
+        Some("mp4" | "m4s" | "m4v" | "cmfv") => "mp4",
+        Some("aac") => "aac",
+        Some("mp3") => "mp3",
+        _ => "ts",
+    }
+}
+
+/// Content type to serve a recording file with, by its extension.
+pub fn content_type(file_name: &str) -> &'static str {
+    match file_name.rsplit_once('.').map(|(_, extension)| extension) {
+        Some("mp4") => "video/mp4",
+        Some("aac") => "audio/aac",
+        Some("mp3") => "audio/mpeg",
+        _ => "video/mp2t",
+    }
+}
+
+/// Turns a title into something safe to use in a file name.
+fn file_stem(title: &str) -> String {
+    let stem: String = title.chars()
+        .map(|c| if c.is_alphanumeric() || c == '-' { c } else { '_' })
+        .take(60)
+        .collect();
+    let stem = stem.trim_matches('_');
+    if stem.is_empty() { "recording".to_string() } else { stem.to_string() }
+}
+
+impl Recorder {
+    pub fn from_config(config: &HashMap<String, String>) -> Self {
+        Recorder {
+            client: reqwest::Client::builder()
+                .connect_timeout(Duration::from_secs(10))
+                .build()
+                .expect("Failed to build HTTP client"),
+            ffmpeg_path: config.get("ffmpeg_path").cloned(),
+            dir: PathBuf::from(config.get("recordings_dir").map(String::as_str).unwrap_or("recordings")),
+            next_id: AtomicU64::new(1),
+            recordings: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    pub fn uses_ffmpeg(&self) -> bool {
+        self.ffmpeg_path.is_some()
+    }
+
+    /// Starts recording `channel` for `duration`, or until stopped when `None`.
+    pub fn start(&self, channel: &Channel, duration: Option<Duration>, title: Option<&str>) -> Result<u64, String> {
+        let title = title.unwrap_or(&channel.name).to_string();
+        let started_at = unix_now();
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        // The id keeps recordings of the same title started in the same second apart
+        let file_name = format!("{}_{}_{}.ts", file_stem(&title), started_at, id);
+        let (stop, stop_receiver) = watch::channel(false);
+
+        let job = Job {
+            id,
+            client: self.client.clone(),
+            ffmpeg_path: self.ffmpeg_path.clone(),
+            channel: channel.clone(),
+            path: self.dir.join(&file_name),
+            duration,
+            stop: stop_receiver,
+            recordings: self.recordings.clone(),
+        };
+        info!("Recording {} to {}", title, job.path.display());
+
+        self.recordings.lock().unwrap().insert(id, Recording {
+            channel_id: channel.id.clone(),
+            title,
+            file_name,
+            started_at,
+            ends_at: duration.map(|d| started_at + d.as_secs()),
+            state: RecordingState::Recording,
+            error: None,
+            stop,
+        });
+
+        let recordings = self.recordings.clone();
+        tokio::spawn(async move {
+            let result = job.run().await;
+            let mut recordings = recordings.lock().unwrap();
+            let Some(recording) = recordings.get_mut(&id) else { return };
+            match result {
+                Ok(()) if *recording.stop.borrow() => recording.state = RecordingState::Stopped,
+                Ok(()) => recording.state = RecordingState::Finished,
+                Err(e) => {
+                    warn!("Recording of {} failed: {}", recording.title, e);
+                    recording.state = RecordingState::Failed;
+                    recording.error = Some(e);
+                }
+            }
+        });
+        Ok(id)
+    }
+
+    /// Asks a running recording to finish. Returns false for unknown or already finished ones.
+    pub fn stop(&self, id: u64) -> bool {
+        let recordings = self.recordings.lock().unwrap();
+        match recordings.get(&id) {
+            Some(recording) if recording.state == RecordingState::Recording => {
+                let _ = recording.stop.send(true);
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Forgets a recording that is no longer running and deletes its file.
+    pub fn delete(&self, id: u64) -> Result<bool, String> {
+        let mut recordings = self.recordings.lock().unwrap();
+        match recordings.get(&id) {
+            None => return Ok(false),
+            Some(recording) if recording.state == RecordingState::Recording => {
+                return Err("Stop the recording before deleting it".to_string());
+            }
+            Some(_) => {}
+        }
+        let recording = recordings.remove(&id).expect("checked above");
+        match std::fs::remove_file(self.dir.join(&recording.file_name)) {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e.to_string()),
+            _ => Ok(true),
+        }
+    }
+
+    pub fn file_path(&self, id: u64) -> Option<PathBuf> {
+        self.recordings.lock().unwrap().get(&id).map(|recording| self.dir.join(&recording.file_name))
+    }
+
+    /// Number of recordings currently capturing a stream.
+    pub fn active_count(&self) -> usize {
+        self.recordings.lock().unwrap().values().filter(|r| r.state == RecordingState::Recording).count()
+    }
+
+    pub fn status(&self, id: u64) -> Option<RecordingStatus> {
+        self.recordings.lock().unwrap().get(&id).map(|recording| self.recording_status(id, recording))
+    }
+
+    pub fn statuses(&self) -> Vec<RecordingStatus> {
+        let recordings = self.recordings.lock().unwrap();
+        let mut statuses: Vec<RecordingStatus> = recordings.iter()
+            .map(|(id, recording)| self.recording_status(*id, recording))
+            .collect();
+        statuses.sort_by_key(|s| std::cmp::Reverse(s.id));
+        statuses
+    }
+
+    fn recording_status(&self, id: u64, recording: &Recording) -> RecordingStatus {
+        RecordingStatus {
+            id,
+            channel_id: recording.channel_id.clone(),
+            title: recording.title.clone(),
+            file_name: recording.file_name.clone(),
+            started_at: recording.started_at,
+            ends_at: recording.ends_at,
+            state: recording.state,
+            error: recording.error.clone(),
+            size_bytes: std::fs::metadata(self.dir.join(&recording.file_name)).map(|m| m.len()).unwrap_or(0),
+        }
+    }
+}
+
+/// Resolves once the recording is stopped or its deadline passes.
+async fn interrupted(stop: &mut watch::Receiver<bool>, deadline: Option<Instant>) {
+    let deadline_passed = async {
+        match deadline {
+            Some(deadline) => tokio::time::sleep_until(deadline).await,
+            None => std::future::pending().await,
+        }
+    };
+    let stopped = async {
+        while !*stop.borrow_and_update() {
+            if stop.changed().await.is_err() {
+                std::future::pending::<()>().await;
+            }
+        }
+    };
+    tokio::select! {
+        _ = deadline_passed => {}
+        _ = stopped => {}
+    }
+}
+
+impl Job {
+    async fn run(mut self) -> Result<(), String> {
+        if let Some(dir) = self.path.parent() {
+            tokio::fs::create_dir_all(dir).await.map_err(|e| format!("Failed to create {}: {}", dir.display(), e))?;
+        }
+        match self.ffmpeg_path.take() {
+            Some(ffmpeg_path) => self.record_with_ffmpeg(&ffmpeg_path).await,
+            None => self.record_direct().await,
+        }
+    }
+
+    fn request(&self, url: &str) -> reqwest::RequestBuilder {
+        let mut request = self.client.get(url);
+        for (name, value) in &self.channel.headers {
+            request = request.header(name.as_str(), value.as_str());
+        }
+        request
+    }
+
+    async fn record_with_ffmpeg(&mut self, ffmpeg_path: &str) -> Result<(), String> {
+        let mut command = Command::new(ffmpeg_path);
+        command.args(["-hide_banner", "-loglevel", "error", "-y"]);
+        command.args(transcode::input_args(&self.channel));
+        if let Some(duration) = self.duration {
+            command.arg("-t").arg(duration.as_secs().to_string());
+        }
+        command
+            .args(["-map", "0", "-c", "copy", "-f", "mpegts"])
+            .arg(&self.path)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::null())
+            .stderr(Stdio::piped())
+            .kill_on_drop(true);
+
+        let mut child = command.spawn().map_err(|e| format!("Failed to execute ffmpeg: {}", e))?;
+        // Child::wait() closes stdin, which ffmpeg reads as a request to quit
+        let mut stdin = child.stdin.take();
+        let mut stderr = child.stderr.take();
+        let errors = tokio::spawn(async move {
+            let mut text = String::new();
+            if let Some(stderr) = stderr.as_mut() {
+                let _ = stderr.read_to_string(&mut text).await;
+            }
+            text
+        });
+
+        let status = tokio::select! {
+            status = child.wait() => Some(status),
+            _ = interrupted(&mut self.stop, None) => None,
+        };
+        let Some(status) = status else {
+            // "q" makes ffmpeg flush and close the file; kill it if it does not react
+            if let Some(mut stdin) = stdin.take() {
+                let _ = stdin.write_all(b"q\n").await;
+            }
+            if tokio::time::timeout(Duration::from_secs(10), child.wait()).await.is_err() {
+                let _ = child.kill().await;
+            }
+            return Ok(());
+        };
+
+        let status = status.map_err(|e| e.to_string())?;
+        if status.success() {
+            return Ok(());
+        }
+        let errors = errors.await.unwrap_or_default();
+        Err(match errors.lines().rfind(|line| !line.trim().is_empty()) {
+            Some(line) => format!("ffmpeg exited with {}: {}", status, line.trim()),
+            None => format!("ffmpeg exited with {}", status),
+        })
+    }
+
+    async fn record_direct(&mut self) -> Result<(), String> {
+        let deadline = self.duration.map(|d| Instant::now() + d);
+        let url = self.channel.url.clone();
+        let response = self.request(&url)
+            .send()
+            .await
+            .and_then(|r| r.error_for_status())
+            .map_err(|e| e.to_string())?;
+        let is_playlist = hls::is_hls_url(&url)
+            || response.headers().get(reqwest::header::CONTENT_TYPE)
+                .and_then(|v| v.to_str().ok())
+                .is_some_and(|v| v.to_ascii_lowercase().contains("mpegurl"));
+
+        if is_playlist {
+            let text = hls::read_playlist(response).await?;
+            return self.record_hls(url, text, deadline).await;
+        }
+
+        let mut file = self.create_file().await?;
+        let mut stream = response.bytes_stream();
+        loop {
+            tokio::select! {
+                _ = interrupted(&mut self.stop, deadline) => break,
+                chunk = stream.next() => match chunk {
+                    Some(Ok(bytes)) => file.write_all(&bytes).await.map_err(|e| e.to_string())?,
+                    Some(Err(e)) => return Err(e.to_string()),
+                    None => break,
+                },
+            }
+        }
+        file.flush().await.map_err(|e| e.to_string())
+    }
+
+    async fn create_file(&self) -> Result<File, String> {
+        File::create(&self.path).await.map_err(|e| format!("Failed to create {}: {}", self.path.display(), e))
+    }
+
+    /// Switches the file being recorded to another extension before it is created.
+    fn use_extension(&mut self, extension: &str) {
+        if self.path.extension().is_some_and(|current| current == extension) {
+            return;
+        }
+        self.path.set_extension(extension);
+        if let Some(recording) = self.recordings.lock().unwrap().get_mut(&self.id) {
+            if let Some(file_name) = self.path.file_name() {
+                recording.file_name = file_name.to_string_lossy().into_owned();
+            }
+        }
+    }
+
+    /// Polls the media playlist and appends each new segment to the recording, preceded by
+    /// its init section whenever that changes.
+    async fn record_hls(&mut self, url: String, text: String, deadline: Option<Instant>) -> Result<(), String> {
+        let (mut playlist_url, mut text) = (url, text);
+        // Record the best variant of a master playlist
+        if let Some(master) = hls::parse_master(&playlist_url, &text) {
+            let variant = master.variants.first().ok_or("Master playlist has no variants")?;
+            playlist_url = variant.url.clone();
+            text = self.fetch_text(&playlist_url).await?;
+        }
+
+        self.use_extension(recording_extension(&media_segments(&playlist_url, &text)));
+        let mut file = self.create_file().await?;
+        let result = self.append_segments(&mut file, playlist_url, text, deadline).await;
+        file.flush().await.map_err(|e| e.to_string())?;
+        result
+    }
+
+    async fn append_segments(&mut self, file: &mut File, playlist_url: String, text: String, deadline: Option<Instant>) -> Result<(), String> {
+        let mut text = text;
+        let mut seen = HashSet::new();
+        let mut written_map: Option<InitSection> = None;
+        let mut failures = 0;
+        loop {
+            if text.lines().any(|line| line.starts_with("#EXT-X-KEY") && !line.contains("METHOD=NONE")) {
+                return Err("Encrypted HLS streams can only be recorded with ffmpeg".to_string());
+            }
+
+            let segments = media_segments(&playlist_url, &text);
+            // Only segments still in the playlist can come up again, so forget the others
+            seen.retain(|url: &String| segments.iter().any(|segment| segment.url == *url));
+            for segment in segments {
+                if seen.contains(&segment.url) {
+                    continue;
+                }
+                if *self.stop.borrow() || deadline.is_some_and(|d| Instant::now() >= d) {
+                    return Ok(());
+                }
+                if let Some(map) = segment.map.filter(|map| written_map.as_ref() != Some(map)) {
+                    let bytes = self.fetch_bytes(&map.url, map.range).await?;
+                    file.write_all(&bytes).await.map_err(|e| e.to_string())?;
+                    written_map = Some(map);
+                }
+                let bytes = self.fetch_bytes(&segment.url, None).await?;
+                file.write_all(&bytes).await.map_err(|e| e.to_string())?;
+                seen.insert(segment.url);
+            }
+
+            if text.contains("#EXT-X-ENDLIST") {
+                return Ok(());
+            }
+
+            let target_duration = text.lines()
+                .find_map(|line| line.strip_prefix("#EXT-X-TARGETDURATION:"))
+                .and_then(|v| v.trim().parse::<u64>().ok())
+                .unwrap_or(4);
+            tokio::select! {
+                _ = interrupted(&mut self.stop, deadline) => return Ok(()),
+                _ = tokio::time::sleep(Duration::from_millis(target_duration * 500)) => {}
+            }
+
+            match self.fetch_text(&playlist_url).await {
+                Ok(updated) => {
+                    text = updated;
+                    failures = 0;
+                }
+                Err(e) if failures < 3 => {
+                    warn!("Failed to refresh {}: {}", playlist_url, e);
+                    failures += 1;
+                }
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    /// Downloads a segment, or `(offset, length)` bytes of it.
+    async fn fetch_bytes(&self, url: &str, range: Option<(u64, u64)>) -> Result<Vec<u8>, String> {
+        let mut request = self.request(url);
+        if let Some((offset, length)) = range {
+            request = request.header(reqwest::header::RANGE, format!("bytes={}-{}", offset, offset + length.max(1) - 1));
+        }
+        let bytes = request
+            .send()
+            .await
+            .and_then(|r| r.error_for_status())
+            .map_err(|e| e.to_string())?
+            .bytes()
+            .await
+            .map_err(|e| e.to_string())?;
+        Ok(bytes.to_vec())
+    }
+
+    async fn fetch_text(&self, url: &str) -> Result<String, String> {
+        let response = self.request(url)
+            .send()
+            .await
+            .and_then(|r| r.error_for_status())
+            .map_err(|e| e.to_string())?;
+        hls::read_playlist(response).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::playlist::{channel_id, ChannelKind};
+    use tokio::net::TcpListener;
+
+    const FMP4_PLAYLIST: &str = "#EXTM3U\n#EXT-X-TARGETDURATION:1\n#EXT-X-MAP:URI=\"init.mp4\"\n#EXTINF:1,\nseg1.m4s\n#EXTINF:1,\nseg2.m4s\n#EXT-X-ENDLIST\n";
+
+    #[test]
+    fn segments_carry_the_init_section_in_effect() {
+        let text = "#EXTM3U\nfirst.ts\n#EXT-X-MAP:URI=\"init.mp4\",BYTERANGE=\"720@16\"\nsecond.m4s\n#EXT-X-MAP:URI=\"/other/init.mp4\"\nthird.m4s\n";
+        let segments = media_segments("http://host/live/index.m3u8", text);
+        let map = |url: &str, range| Some(InitSection { url: url.to_string(), range });
+        assert_eq!(segments, [
+            Segment { url: "http://host/live/first.ts".to_string(), map: None },
+            Segment { url: "http://host/live/second.m4s".to_string(), map: map("http://host/live/init.mp4", Some((16, 720))) },
+            Segment { url: "http://host/live/third.m4s".to_string(), map: map("http://host/other/init.mp4", None) },
+        ]);
+    }
+
+    #[test]
+    fn extension_follows_the_segment_type() {
+        let extension = |text: &str| recording_extension(&media_segments("http://host/index.m3u8", text));
+        assert_eq!(extension(FMP4_PLAYLIST), "mp4");
+        assert_eq!(extension("#EXTM3U\n#EXTINF:4,\nchunk_1.ts?token=a.mp4x\n"), "ts");
+        assert_eq!(extension("#EXTM3U\n#EXTINF:4,\naudio/chunk_1.AAC\n"), "aac");
+        assert_eq!(extension("#EXTM3U\n#EXTINF:4,\nsegment-7\n"), "ts");
+        assert_eq!(content_type("News_1.mp4"), "video/mp4");
+        assert_eq!(content_type("News_1.ts"), "video/mp2t");
+    }
+
+    /// Serves `files` (path to body) on a local port and returns the base URL.
+    async fn serve(files: Vec<(&'static str, &'static [u8])>) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let base = format!("http://{}", listener.local_addr().unwrap());
+        tokio::spawn(async move {
+            while let Ok((mut socket, _)) = listener.accept().await {
+                let mut request = [0u8; 2048];
+                let read = socket.read(&mut request).await.unwrap_or(0);
+                let request = String::from_utf8_lossy(&request[..read]).into_owned();
+                let path = request.split(' ').nth(1).unwrap_or("");
+                let response = match files.iter().find(|(file, _)| *file == path) {
+                    Some((_, body)) => [format!("HTTP/1.1 200 OK\r\nContent-Length: {}\r\nConnection: close\r\n\r\n", body.len()).as_bytes(), body].concat(),
+                    None => b"HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\nConnection: close\r\n\r\n".to_vec(),
+                };
+                let _ = socket.write_all(&response).await;
+            }
+        });
+        base
+    }
+
+    #[tokio::test]
+    async fn fragmented_mp4_recording_starts_with_its_init_section() {
+        let base = serve(vec![
+            ("/live/index.m3u8", FMP4_PLAYLIST.as_bytes()),
+            ("/live/init.mp4", b"INIT"),
+            ("/live/seg1.m4s", b"ONE"),
+            ("/live/seg2.m4s", b"TWO"),
+        ]).await;
+        let dir = std::env::temp_dir().join(format!("m3u_browser_recordings_{}", std::process::id()));
+        let recorder = Recorder::from_config(&HashMap::from([("recordings_dir".to_string(), dir.to_string_lossy().into_owned())]));
+        let url = format!("{}/live/index.m3u8", base);
+        let channel = Channel {
+            id: channel_id(&url),
+            name: "News".to_string(),
+            url,
+            icon_url: String::new(),
+            kind: ChannelKind::Live,
+            tvg_id: None,
+            headers: Vec::new(),
+        };
+
+        let id = recorder.start(&channel, None, None).unwrap();
+        let started = Instant::now();
+        while recorder.status(id).unwrap().state == RecordingState::Recording && started.elapsed() < Duration::from_secs(10) {
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+        let status = recorder.status(id).unwrap();
+        assert_eq!(status.state, RecordingState::Finished, "{:?}", status.error);
+        assert!(status.file_name.ends_with(".mp4"));
+        assert_eq!(std::fs::read(recorder.file_path(id).unwrap()).unwrap(), b"INITONETWO");
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn recordings_started_together_get_their_own_files() {
+        let base = serve(vec![("/live/stream.ts", b"STREAM")]).await;
+        let dir = std::env::temp_dir().join(format!("m3u_browser_recordings_same_{}", std::process::id()));
+        let recorder = Recorder::from_config(&HashMap::from([("recordings_dir".to_string(), dir.to_string_lossy().into_owned())]));
+        let url = format!("{}/live/stream.ts", base);
+        let channel = Channel {
+            id: channel_id(&url),
+            name: "News".to_string(),
+            url,
+            icon_url: String::new(),
+            kind: ChannelKind::Live,
+            tvg_id: None,
+            headers: Vec::new(),
+        };
+
+        let ids = [recorder.start(&channel, None, Some("Evening")).unwrap(), recorder.start(&channel, None, Some("Evening")).unwrap()];
+        let started = Instant::now();
+        while ids.iter().any(|id| recorder.status(*id).unwrap().state == RecordingState::Recording) && started.elapsed() < Duration::from_secs(10) {
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+        let [first, second] = ids.map(|id| recorder.status(id).unwrap());
+        assert_ne!(first.file_name, second.file_name);
+        for id in ids {
+            assert_eq!(recorder.status(id).unwrap().state, RecordingState::Finished);
+            assert_eq!(std::fs::read(recorder.file_path(id).unwrap()).unwrap(), b"STREAM");
+        }
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,554 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::sync::RwLock;
+use std::time::{Duration, Instant};
+use futures::future::join_all;
+use serde::Serialize;
+use serde_json::json;
+use tokio::net::UdpSocket;
+use log::{info, warn};
+use quick_xml::escape::{escape, resolve_predefined_entity};
+use quick_xml::events::{BytesStart, Event};
+use quick_xml::Reader;
+use crate::hls;
+use crate::playlist::channel_id;
+use crate::profiles::PlayerVars;
+
+const SSDP_ADDRESS: &str = "239.255.255.250:1900";
+const AV_TRANSPORT: &str = "urn:schemas-upnp-org:service:AVTransport:1";
+
+#[derive(Clone, Debug)]
+enum Target {
+    /// Kodi's JSON-RPC endpoint, e.g. `http://192.168.1.20:8080/jsonrpc`.
+    Kodi { url: String, username: Option<String>, password: Option<String> },
+    /// A UPnP MediaRenderer's device description; the AVTransport control URL is looked up from it.
+    Dlna { description_url: String },
+}
+
+/// A TV or media box streams can be sent to, from `config.txt` or found by SSDP.
+#[derive(Clone, Debug)]
+pub struct Renderer {
+    pub id: String,
+    pub name: String,
+    target: Target,
+}
+
+#[derive(Serialize)]
+pub struct RendererInfo {
+    pub id: String,
+    pub name: String,
+    pub kind: &'static str,
+}
+
+impl Renderer {
+    fn info(&self) -> RendererInfo {
+        RendererInfo {
+            id: self.id.clone(),
+            name: self.name.clone(),
+            kind: match self.target {
+                Target::Kodi { .. } => "kodi",
+                Target::Dlna { .. } => "dlna",
+            },
+        }
+    }
+}
+
+/// The device name and AVTransport control URL from a UPnP device description.
+struct DeviceDescription {
+    friendly_name: String,
+    control_url: String,
+}
+
+/// Configured Kodi and DLNA targets, e.g.
+///
+/// ```text
+/// kodi.livingroom.url = http://192.168.1.20:8080/jsonrpc
+/// kodi.livingroom.username = kodi
+/// kodi.livingroom.password = secret
+/// dlna.bedroom.url = http://192.168.1.30:49152/description.xml
+/// dlna_discovery = true
+/// ```
+pub struct Renderers {
+    client: reqwest::Client,
+    configured: Vec<Renderer>,
+    discovery: bool,
+    discovery_timeout: Duration,
+    discovered: RwLock<Vec<Renderer>>,
+}
+
+fn is_valid_name(name: &str) -> bool {
+    !name.is_empty() && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+}
+
+/// Groups `<prefix>.<name>.<field>` keys by name.
+fn grouped<'a>(config: &'a HashMap<String, String>, prefix: &str) -> BTreeMap<&'a str, HashMap<&'a str, &'a String>> {
+    let mut groups: BTreeMap<&str, HashMap<&str, &String>> = BTreeMap::new();
+    for (key, value) in config {
+        if let Some((name, field)) = key.strip_prefix(prefix).and_then(|rest| rest.rsplit_once('.')) {
+            groups.entry(name).or_default().insert(field, value);
+        }
+    }
+    groups
+}
+
+impl Renderers {
+    pub fn from_config(config: &HashMap<String, String>) -> Self {
+        let mut configured = Vec::new();
+        for (prefix, kind) in [("kodi.", "kodi"), ("dlna.", "dlna")] {
+            for (name, fields) in grouped(config, prefix) {
+                if !is_valid_name(name) {
+                    warn!("Ignoring {} target with invalid name: {}", kind, name);
+                    continue;
+                }
+                let Some(url) = fields.get("url") else {
+                    warn!("{} target {} has no url", kind, name);
+                    continue;
+                };
+                let target = if kind == "kodi" {
+                    Target::Kodi {
+                        url: url.to_string(),
+                        username: fields.get("username").map(|v| v.to_string()),
+                        password: fields.get("password").map(|v| v.to_string()),
+                    }
+                } else {
+                    Target::Dlna { description_url: url.to_string() }
+                };
+                configured.push(Renderer {
+                    id: format!("{}-{}", kind, name),
+                    name: fields.get("label").map(|l| l.to_string()).unwrap_or_else(|| name.to_string()),
+                    target,
+                });
+            }
+        }
+
+        Renderers {
+            client: reqwest::Client::builder()
+                .timeout(Duration::from_secs(10))
+                .build()
+                .expect("Failed to build HTTP client"),
+            configured,
+            discovery: config.get("dlna_discovery").map(String::as_str) == Some("true"),
+            discovery_timeout: Duration::from_secs(
+                config.get("dlna_discovery_secs").and_then(|v| v.parse().ok()).unwrap_or(3),
+            ),
+            discovered: RwLock::new(Vec::new()),
+        }
+    }
+
+    /// True when there is anything to show a "Play on" menu for.
+    pub fn is_enabled(&self) -> bool {
+        self.discovery || !self.configured.is_empty()
+    }
+
+    pub fn discovery_enabled(&self) -> bool {
+        self.discovery
+    }
+
+    pub fn list(&self) -> Vec<RendererInfo> {
+        let discovered = self.discovered.read().unwrap();
+        self.configured.iter().chain(discovered.iter()).map(Renderer::info).collect()
+    }
+
+    fn get(&self, id: &str) -> Option<Renderer> {
+        self.configured.iter()
+            .find(|r| r.id == id)
+            .cloned()
+            .or_else(|| self.discovered.read().unwrap().iter().find(|r| r.id == id).cloned())
+    }
+
+    /// Searches the local network for MediaRenderers and replaces the discovered list.
+    pub async fn discover(&self) -> Result<usize, String> {
+        let locations = ssdp_search(self.discovery_timeout).await?;
+        let descriptions = join_all(locations.iter().map(|location| self.describe(location))).await;
+
+        let mut renderers = Vec::new();
+        for (location, description) in locations.iter().zip(descriptions) {
+            match description {
+                Ok(description) => renderers.push(Renderer {
+                    id: format!("upnp-{}", channel_id(location)),
+                    name: description.friendly_name,
+                    target: Target::Dlna { description_url: location.clone() },
+                }),
+                Err(e) => info!("Skipping UPnP device at {}: {}", location, e),
+            }
+        }
+        renderers.sort_by(|a, b| a.name.cmp(&b.name));
+
+        let count = renderers.len();
+        *self.discovered.write().unwrap() = renderers;
+        Ok(count)
+    }
+
+    async fn describe(&self, description_url: &str) -> Result<DeviceDescription, String> {
+        let xml = self.client.get(description_url)
+            .send()
+            .await
+            .and_then(|r| r.error_for_status())
+            .map_err(|e| e.to_string())?
+            .text()
+            .await
+            .map_err(|e| e.to_string())?;
+
+        let texts = text_elements(&xml).map_err(|e| format!("Invalid device description: {}", e))?;
+        let service = texts.iter()
+            .find(|text| text.is_in("service") && text.name() == "serviceType" && text.text == AV_TRANSPORT)
+            .ok_or("Device has no AVTransport service")?;
+        let control_url = texts.iter()
+            .find(|text| text.parent == service.parent && text.name() == "controlURL" && !text.text.is_empty())
+            .ok_or("AVTransport service has no controlURL")?;
+        let base_url = element_text(&texts, "URLBase").unwrap_or(description_url);
+
+        Ok(DeviceDescription {
+            friendly_name: element_text(&texts, "friendlyName").unwrap_or(description_url).to_string(),
+            control_url: hls::resolve_url(base_url, &control_url.text),
+        })
+    }
+
+    /// Starts playing `vars.url` on the renderer with the given id.
+    pub async fn play(&self, id: &str, vars: &PlayerVars<'_>) -> Result<(), String> {
+        let renderer = self.get(id).ok_or("Unknown renderer")?;
+        match &renderer.target {
+            Target::Kodi { url, username, password } => {
+                self.kodi_open(url, username.as_deref(), password.as_deref(), vars).await
+            }
+            Target::Dlna { description_url } => {
+                let description = self.describe(description_url).await?;
+                self.dlna_play(&description.control_url, vars).await
+            }
+        }
+    }
+
+    async fn kodi_open(&self, url: &str, username: Option<&str>, password: Option<&str>, vars: &PlayerVars<'_>) -> Result<(), String> {
+        // Kodi takes request headers appended to the URL as `url|Name=value&Name=value`
+        let mut headers = Vec::new();
+        if !vars.user_agent.is_empty() {
+            headers.push(format!("User-Agent={}", urlencoding::encode(vars.user_agent)));
+        }
+        if !vars.referrer.is_empty() {
+            headers.push(format!("Referer={}", urlencoding::encode(vars.referrer)));
+        }
+        let file = if headers.is_empty() {
+            vars.url.to_string()
+        } else {
+            format!("{}|{}", vars.url, headers.join("&"))
+        };
+
+        let mut request = self.client.post(url)
+            .header("Content-Type", "application/json")
+            .body(json!({
+                "jsonrpc": "2.0",
+                "id": 1,
+                "method": "Player.Open",
+                "params": { "item": { "file": file } },
+            }).to_string());
+        if let Some(username) = username {
+            request = request.basic_auth(username, password);
+        }
+        let body = request.send()
+            .await
+            .and_then(|r| r.error_for_status())
+            .map_err(|e| format!("Kodi is not reachable: {}", e))?
+            .text()
+            .await
+            .map_err(|e| e.to_string())?;
+
+        let response: serde_json::Value = serde_json::from_str(&body).map_err(|e| format!("Invalid Kodi response: {}", e))?;
+        match response.get("error") {
+            Some(error) => Err(format!("Kodi error: {}", error["message"].as_str().unwrap_or("unknown"))),
+            None => Ok(()),
+        }
+    }
+
+    async fn dlna_play(&self, control_url: &str, vars: &PlayerVars<'_>) -> Result<(), String> {
+        let mime = if hls::is_hls_url(vars.url) { "application/vnd.apple.mpegurl" } else { "video/mp2t" };
+        let title = if vars.title.is_empty() { vars.url } else { vars.title };
+        let metadata = format!(
+            concat!(
+                r#"<DIDL-Lite xmlns="urn:schemas-upnp-org:metadata-1-0/DIDL-Lite/" xmlns:dc="http://purl.org/dc/elements/1.1/" xmlns:upnp="urn:schemas-upnp-org:metadata-1-0/upnp/">"#,
+                r#"<item id="0" parentID="-1" restricted="1"><dc:title>{}</dc:title><upnp:class>object.item.videoItem</upnp:class>"#,
+                r#"<res protocolInfo="http-get:*:{}:*">{}</res></item></DIDL-Lite>"#,
+            ),
+            escape(title), mime, escape(vars.url)
+        );
+
+        self.soap_action(control_url, "SetAVTransportURI", &format!(
+            "<InstanceID>0</InstanceID><CurrentURI>{}</CurrentURI><CurrentURIMetaData>{}</CurrentURIMetaData>",
+            escape(vars.url), escape(&metadata)
+        )).await?;
+        self.soap_action(control_url, "Play", "<InstanceID>0</InstanceID><Speed>1</Speed>").await
+    }
+
+    async fn soap_action(&self, control_url: &str, action: &str, arguments: &str) -> Result<(), String> {
+        let envelope = format!(
+            concat!(
+                r#"<?xml version="1.0" encoding="utf-8"?>"#,
+                r#"<s:Envelope xmlns:s="http://schemas.xmlsoap.org/soap/envelope/" s:encodingStyle="http://schemas.xmlsoap.org/soap/encoding/">"#,
+                r#"<s:Body><u:{action} xmlns:u="{service}">{arguments}</u:{action}></s:Body></s:Envelope>"#,
+            ),
+            action = action, service = AV_TRANSPORT, arguments = arguments
+        );
+        let response = self.client.post(control_url)
+            .header("Content-Type", r#"text/xml; charset="utf-8""#)
+            .header("SOAPAction", format!(r#""{}#{}""#, AV_TRANSPORT, action))
+            .body(envelope)
+            .send()
+            .await
+            .map_err(|e| format!("Renderer is not reachable: {}", e))?;
+
+        if response.status().is_success() {
+            return Ok(());
+        }
+        let status = response.status();
+        let body = response.text().await.unwrap_or_default();
+        let description = text_elements(&body).ok()
+            .and_then(|texts| element_text(&texts, "errorDescription").map(str::to_string))
+            .unwrap_or_else(|| status.to_string());
+        Err(format!("{} failed: {}", action, description))
+    }
+}
+
+/// Sends an SSDP M-SEARCH for MediaRenderers and collects the `LOCATION` of every answer.
+async fn ssdp_search(timeout: Duration) -> Result<Vec<String>, String> {
+    let socket = UdpSocket::bind("0.0.0.0:0").await.map_err(|e| e.to_string())?;
+    let request = format!(
+        "M-SEARCH * HTTP/1.1\r\nHOST: {}\r\nMAN: \"ssdp:discover\"\r\nMX: {}\r\nST: urn:schemas-upnp-org:device:MediaRenderer:1\r\n\r\n",
+        SSDP_ADDRESS,
+        timeout.as_secs().max(1)
+    );
+    socket.send_to(request.as_bytes(), SSDP_ADDRESS).await.map_err(|e| e.to_string())?;
+
+    let mut locations = Vec::new();
+    let mut seen = HashSet::new();
+    let mut buffer = [0u8; 2048];
+    let started = Instant::now();
+    while let Some(remaining) = timeout.checked_sub(started.elapsed()) {
+        let Ok(Ok((len, _))) = tokio::time::timeout(remaining, socket.recv_from(&mut buffer)).await else { break };
+        let response = String::from_utf8_lossy(&buffer[..len]);
+        let location = response.lines()
+            .filter_map(|line| line.split_once(':'))
+            .find(|(name, _)| name.trim().eq_ignore_ascii_case("location"))
+            .map(|(_, value)| value.trim().to_string());
+        if let Some(location) = location {
+            if seen.insert(location.clone()) {
+                locations.push(location);
+            }
+        }
+    }
+    Ok(locations)
+}
+
+/// The text directly inside one element, with the local names of the element and its ancestors.
+struct XmlText {
+    path: Vec<String>,
+    /// Position of the parent element in the document, so siblings can be told apart from cousins.
+    parent: usize,
+    text: String,
+}
+
+impl XmlText {
+    fn name(&self) -> &str {
+        self.path.last().map(String::as_str).unwrap_or("")
+    }
+
+    fn is_in(&self, parent: &str) -> bool {
+        self.path.len() >= 2 && self.path[self.path.len() - 2] == parent
+    }
+}
+
+/// Reads the text of every element in document order. Namespace prefixes are dropped,
+/// CDATA and entities are decoded and comments are skipped.
+fn text_elements(xml: &str) -> Result<Vec<XmlText>, String> {
+    let mut reader = Reader::from_str(xml);
+    let mut texts = Vec::new();
+    // (local name, position, text so far) of every open element
+    let mut open: Vec<(String, usize, String)> = Vec::new();
+    let mut position = 0;
+
+    loop {
+        match reader.read_event() {
+            Ok(Event::Start(element)) => {
+                position += 1;
+                open.push((local_name(&element), position, String::new()));
+            }
+            Ok(Event::Empty(element)) => {
+                position += 1;
+                let mut path: Vec<String> = open.iter().map(|(name, _, _)| name.clone()).collect();
+                path.push(local_name(&element));
+                texts.push(XmlText { path, parent: open.last().map_or(0, |(_, p, _)| *p), text: String::new() });
+            }
+            Ok(Event::Text(content)) => {
+                if let Some((_, _, text)) = open.last_mut() {
+                    text.push_str(&content.decode().map_err(|e| e.to_string())?);
+                }
+            }
+            Ok(Event::CData(content)) => {
+                if let Some((_, _, text)) = open.last_mut() {
+                    text.push_str(&content.decode().map_err(|e| e.to_string())?);
+                }
+            }
+            Ok(Event::GeneralRef(reference)) => {
+                if let Some((_, _, text)) = open.last_mut() {
+                    if let Ok(Some(c)) = reference.resolve_char_ref() {
+                        text.push(c);
+                    } else if let Some(value) = reference.decode().ok().and_then(|name| resolve_predefined_entity(&name)) {
+                        text.push_str(value);
+                    }
+                }
+            }
+            Ok(Event::End(_)) => {
+                let path: Vec<String> = open.iter().map(|(name, _, _)| name.clone()).collect();
+                let Some((_, _, text)) = open.pop() else { break };
+                let parent = open.last().map_or(0, |(_, p, _)| *p);
+                texts.push(XmlText { path, parent, text: text.trim().to_string() });
+            }
+            Ok(Event::Eof) => break,
+            Err(e) => return Err(format!("at byte {}: {}", reader.buffer_position(), e)),
+            _ => {}
+        }
+    }
+    Ok(texts)
+}
+
+fn local_name(element: &BytesStart) -> String {
+    String::from_utf8_lossy(element.local_name().as_ref()).into_owned()
+}
+
+/// The text of the first non-empty `<tag>` element.
+fn element_text<'a>(texts: &'a [XmlText], tag: &str) -> Option<&'a str> {
+    texts.iter().find(|text| text.name() == tag && !text.text.is_empty()).map(|text| text.text.as_str())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{Arc, Mutex};
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+
+    const DESCRIPTION: &str = r#"<?xml version="1.0"?>
+<!-- <friendlyName>Commented out</friendlyName> -->
+<root xmlns="urn:schemas-upnp-org:device-1-0">
+  <device>
+    <friendlyName><![CDATA[Living Room <TV>]]></friendlyName>
+    <serviceList>
+      <service>
+        <serviceType>urn:schemas-upnp-org:service:RenderingControl:1</serviceType>
+        <controlURL>/rendering</controlURL>
+      </service>
+      <d:service xmlns:d="urn:schemas-upnp-org:device-1-0">
+        <d:serviceType>urn:schemas-upnp-org:service:AVTransport:1</d:serviceType>
+        <d:controlURL>/transport?a=1&amp;b=2</d:controlURL>
+      </d:service>
+    </serviceList>
+  </device>
+</root>"#;
+
+    const FAULT: &str = r#"<s:Envelope xmlns:s="http://schemas.xmlsoap.org/soap/envelope/"><s:Body><s:Fault>
+<detail><UPnPError xmlns="urn:schemas-upnp-org:control-1-0"><errorCode>714</errorCode>
+<errorDescription>Illegal MIME-type</errorDescription></UPnPError></detail></s:Fault></s:Body></s:Envelope>"#;
+
+    /// A request seen by the stand-in renderer: path, `SOAPAction` header and body.
+    type Request = (String, String, String);
+
+    /// Serves `routes` (path to status and body) on a local port and records every request.
+    async fn stand_in(routes: Vec<(&'static str, u16, &'static str)>) -> (String, Arc<Mutex<Vec<Request>>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let base = format!("http://{}", listener.local_addr().unwrap());
+        let requests = Arc::new(Mutex::new(Vec::new()));
+        let recorded = requests.clone();
+        tokio::spawn(async move {
+            while let Ok((mut socket, _)) = listener.accept().await {
+                let mut data = Vec::new();
+                let mut buffer = [0u8; 4096];
+                let (head, body) = loop {
+                    let read = socket.read(&mut buffer).await.unwrap();
+                    data.extend_from_slice(&buffer[..read]);
+                    let text = String::from_utf8_lossy(&data).into_owned();
+                    let Some((head, body)) = text.split_once("\r\n\r\n") else { continue };
+                    let length = head.lines()
+                        .filter_map(|line| line.split_once(':'))
+                        .find(|(name, _)| name.eq_ignore_ascii_case("content-length"))
+                        .and_then(|(_, value)| value.trim().parse().ok())
+                        .unwrap_or(0);
+                    if read == 0 || body.len() >= length {
+                        break (head.to_string(), body.to_string());
+                    }
+                };
+                let path = head.split(' ').nth(1).unwrap_or("").to_string();
+                let action = head.lines()
+                    .filter_map(|line| line.split_once(':'))
+                    .find(|(name, _)| name.eq_ignore_ascii_case("soapaction"))
+                    .map(|(_, value)| value.trim().to_string())
+                    .unwrap_or_default();
+                let (status, response) = routes.iter()
+                    .find(|(route, _, _)| *route == path)
+                    .map(|(_, status, body)| (*status, *body))
+                    .unwrap_or((404, ""));
+                recorded.lock().unwrap().push((path, action, body));
+                let reply = format!("HTTP/1.1 {} X\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}", status, response.len(), response);
+                let _ = socket.write_all(reply.as_bytes()).await;
+            }
+        });
+        (base, requests)
+    }
+
+    fn renderers(description_url: &str) -> Renderers {
+        Renderers::from_config(&HashMap::from([("dlna.tv.url".to_string(), description_url.to_string())]))
+    }
+
+    fn vars<'a>(url: &'a str) -> PlayerVars<'a> {
+        PlayerVars { url, title: "News & Weather", user_agent: "", referrer: "", headers: String::new() }
+    }
+
+    #[test]
+    fn text_elements_drop_prefixes_and_skip_comments() {
+        let texts = text_elements(DESCRIPTION).unwrap();
+        assert_eq!(element_text(&texts, "friendlyName"), Some("Living Room <TV>"));
+        assert_eq!(element_text(&texts, "controlURL"), Some("/rendering"));
+        let transport = texts.iter().find(|text| text.text == AV_TRANSPORT).unwrap();
+        assert!(transport.is_in("service"));
+        assert_eq!(element_text(&text_elements(FAULT).unwrap(), "errorDescription"), Some("Illegal MIME-type"));
+        assert!(text_elements("<root><a></b></root>").is_err());
+    }
+
+    #[tokio::test]
+    async fn description_is_read_from_the_device() {
+        let (base, _) = stand_in(vec![("/description.xml", 200, DESCRIPTION), ("/empty.xml", 200, "<root><device/></root>")]).await;
+        let description = renderers("").describe(&format!("{}/description.xml", base)).await.unwrap();
+        assert_eq!(description.friendly_name, "Living Room <TV>");
+        assert_eq!(description.control_url, format!("{}/transport?a=1&b=2", base));
+
+        let error = renderers("").describe(&format!("{}/empty.xml", base)).await.err();
+        assert_eq!(error.as_deref(), Some("Device has no AVTransport service"));
+    }
+
+    #[tokio::test]
+    async fn play_sets_the_uri_and_starts_playback() {
+        let (base, requests) = stand_in(vec![
+            ("/description.xml", 200, DESCRIPTION),
+            ("/transport?a=1&b=2", 200, ""),
+        ]).await;
+        let renderers = renderers(&format!("{}/description.xml", base));
+        renderers.play("dlna-tv", &vars("http://example.com/live.m3u8?x=1&y=2")).await.unwrap();
+
+        let requests = requests.lock().unwrap().clone();
+        let actions: Vec<&str> = requests.iter().map(|(_, action, _)| action.as_str()).collect();
+        assert_eq!(actions, ["", &format!("\"{}#SetAVTransportURI\"", AV_TRANSPORT), &format!("\"{}#Play\"", AV_TRANSPORT)]);
+
+        // The envelope has to parse, and the metadata nested in it has to parse once unescaped
+        let texts = text_elements(&requests[1].2).unwrap();
+        assert_eq!(element_text(&texts, "CurrentURI"), Some("http://example.com/live.m3u8?x=1&y=2"));
+        let metadata = text_elements(element_text(&texts, "CurrentURIMetaData").unwrap()).unwrap();
+        assert_eq!(element_text(&metadata, "title"), Some("News & Weather"));
+        assert_eq!(element_text(&metadata, "res"), Some("http://example.com/live.m3u8?x=1&y=2"));
+    }
+
+    #[tokio::test]
+    async fn soap_faults_are_reported() {
+        let (base, _) = stand_in(vec![
+            ("/description.xml", 200, DESCRIPTION),
+            ("/transport?a=1&b=2", 500, FAULT),
+        ]).await;
+        let error = renderers(&format!("{}/description.xml", base)).play("dlna-tv", &vars("http://example.com/a.ts")).await.err();
+        assert_eq!(error.as_deref(), Some("SetAVTransportURI failed: Illegal MIME-type"));
+    }
+}
@@ -0,0 +1,385 @@
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::Mutex;
+use std::time::Duration;
+use serde::{Deserialize, Serialize};
+use log::{info, warn};
+use crate::epg::{self, Programme};
+use crate::playlist::{Channel, Library};
+use crate::recorder::{Recorder, RecordingState, RecordingStatus};
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum ScheduleState {
+    Scheduled,
+    Recording,
+    Done,
+    /// The server was not running while the programme aired.
+    Missed,
+    Failed,
+}
+
+impl ScheduleState {
+    pub fn label(&self) -> &'static str {
+        match self {
+            ScheduleState::Scheduled => "scheduled",
+            ScheduleState::Recording => "recording",
+            ScheduleState::Done => "done",
+            ScheduleState::Missed => "missed",
+            ScheduleState::Failed => "failed",
+        }
+    }
+
+    fn is_pending(&self) -> bool {
+        matches!(self, ScheduleState::Scheduled | ScheduleState::Recording)
+    }
+}
+
+/// A programme to record, as stored in the schedule file.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct ScheduledRecording {
+    pub id: u64,
+    pub channel_id: String,
+    pub channel_name: String,
+    pub title: String,
+    /// Programme start and end from the guide, without padding.
+    pub start: i64,
+    pub stop: i64,
+    /// Seconds recorded before the start and after the end, fixed when the programme was scheduled.
+    pub padding_before: i64,
+    pub padding_after: i64,
+    pub state: ScheduleState,
+    /// The recorder's id while this is being recorded. Not meaningful after a restart.
+    #[serde(skip)]
+    pub recording_id: Option<u64>,
+    pub error: Option<String>,
+}
+
+impl ScheduledRecording {
+    /// The time range actually captured, including padding.
+    pub fn window(&self) -> (i64, i64) {
+        (self.start - self.padding_before, self.stop + self.padding_after)
+    }
+}
+
+/// Recordings scheduled from the guide, saved to `schedule_path` and started by `tick()`.
+pub struct Scheduler {
+    path: PathBuf,
+    padding_before: i64,
+    padding_after: i64,
+    max_concurrent: usize,
+    entries: Mutex<Vec<ScheduledRecording>>,
+    /// Held while writing the schedule file, so `entries` stays available meanwhile.
+    saving: tokio::sync::Mutex<()>,
+}
+
+impl Scheduler {
+    pub fn from_config(config: &HashMap<String, String>) -> Self {
+        let minutes = |key: &str, default: i64| config.get(key).and_then(|v| v.parse::<i64>().ok()).unwrap_or(default) * 60;
+        let path = PathBuf::from(config.get("schedule_path").map(String::as_str).unwrap_or("schedule.json"));
+
+        let mut entries: Vec<ScheduledRecording> = match std::fs::read_to_string(&path) {
+            Ok(json) => serde_json::from_str(&json).unwrap_or_else(|e| {
+                warn!("Ignoring invalid schedule file {}: {}", path.display(), e);
+                Vec::new()
+            }),
+            Err(_) => Vec::new(),
+        };
+        // Recordings that were running when the server stopped are picked up again if still airing
+        let now = epg::now();
+        for entry in entries.iter_mut().filter(|e| e.state == ScheduleState::Recording) {
+            if now < entry.window().1 {
+                entry.state = ScheduleState::Scheduled;
+            } else {
+                entry.state = ScheduleState::Failed;
+                entry.error = Some("Interrupted by a restart".to_string());
+            }
+        }
+
+        Scheduler {
+            path,
+            padding_before: minutes("recording_padding_before_minutes", 2),
+            padding_after: minutes("recording_padding_after_minutes", 5),
+            max_concurrent: config.get("max_concurrent_recordings").and_then(|v| v.parse().ok()).unwrap_or(2),
+            entries: Mutex::new(entries),
+            saving: tokio::sync::Mutex::new(()),
+        }
+    }
+
+    pub fn list(&self) -> Vec<ScheduledRecording> {
+        let mut entries = self.entries.lock().unwrap().clone();
+        entries.sort_by_key(|e| e.start);
+        entries
+    }
+
+    /// Whether this programme of the channel is already scheduled.
+    pub fn is_scheduled(&self, channel_id: &str, start: i64) -> bool {
+        self.entries.lock().unwrap().iter().any(|e| e.channel_id == channel_id && e.start == start && e.state.is_pending())
+    }
+
+    /// Adds a programme to the schedule, refusing it when that would need more than
+    /// `max_concurrent_recordings` captures at the same time, counting those started by hand.
+    pub async fn schedule(&self, channel: &Channel, programme: &Programme, recorder: &Recorder) -> Result<ScheduledRecording, String> {
+        let entry = self.add(channel, programme, recorder)?;
+        self.save().await;
+        Ok(entry)
+    }
+
+    fn add(&self, channel: &Channel, programme: &Programme, recorder: &Recorder) -> Result<ScheduledRecording, String> {
+        let mut entries = self.entries.lock().unwrap();
+        if programme.stop + self.padding_after <= epg::now() {
+            return Err("This programme has already ended".to_string());
+        }
+        if entries.iter().any(|e| e.channel_id == channel.id && e.start == programme.start && e.state.is_pending()) {
+            return Err("This programme is already scheduled".to_string());
+        }
+
+        let entry = ScheduledRecording {
+            id: entries.iter().map(|e| e.id).max().unwrap_or(0) + 1,
+            channel_id: channel.id.clone(),
+            channel_name: channel.name.clone(),
+            title: programme.title.clone(),
+            start: programme.start,
+            stop: programme.stop,
+            padding_before: self.padding_before,
+            padding_after: self.padding_after,
+            state: ScheduleState::Scheduled,
+            recording_id: None,
+            error: None,
+        };
+
+        let manual: Vec<RecordingStatus> = recorder.statuses().into_iter()
+            .filter(|r| r.state == RecordingState::Recording)
+            .filter(|r| !entries.iter().any(|e| e.state == ScheduleState::Recording && e.recording_id == Some(r.id)))
+            .collect();
+        let conflicts = conflicts(&entries, &manual, entry.window(), self.max_concurrent);
+        if !conflicts.is_empty() {
+            return Err(format!(
+                "At most {} recordings can run at once, and this overlaps with: {}",
+                self.max_concurrent,
+                conflicts.join("; ")
+            ));
+        }
+
+        info!("Scheduled {} on {}", entry.title, entry.channel_name);
+        entries.push(entry.clone());
+        Ok(entry)
+    }
+
+    /// Removes an entry, stopping its recording if one is running.
+    pub async fn cancel(&self, id: u64, recorder: &Recorder) -> bool {
+        let removed = self.remove(id, recorder);
+        if removed {
+            self.save().await;
+        }
+        removed
+    }
+
+    fn remove(&self, id: u64, recorder: &Recorder) -> bool {
+        let mut entries = self.entries.lock().unwrap();
+        let Some(index) = entries.iter().position(|e| e.id == id) else { return false };
+        let entry = entries.remove(index);
+        if entry.state == ScheduleState::Recording {
+            if let Some(recording_id) = entry.recording_id {
+                recorder.stop(recording_id);
+            }
+        }
+        true
+    }
+
+    /// Starts recordings whose padded start has come and records the outcome of finished ones.
+    pub async fn tick(&self, library: &Library, recorder: &Recorder) {
+        if self.update(library, recorder) {
+            self.save().await;
+        }
+    }
+
+    /// Moves entries on to their next state, returning whether any changed.
+    fn update(&self, library: &Library, recorder: &Recorder) -> bool {
+        let now = epg::now();
+        let mut entries = self.entries.lock().unwrap();
+        let mut changed = false;
+
+        for entry in entries.iter_mut() {
+            let (start, stop) = entry.window();
+            match entry.state {
+                ScheduleState::Scheduled if now >= stop => {
+                    entry.state = ScheduleState::Missed;
+                    changed = true;
+                }
+                ScheduleState::Scheduled if now >= start => {
+                    changed = true;
+                    if recorder.active_count() >= self.max_concurrent {
+                        entry.state = ScheduleState::Failed;
+                        entry.error = Some(format!("{} recordings were already running", self.max_concurrent));
+                        continue;
+                    }
+                    let Some(channel) = library.channel(&entry.channel_id) else {
+                        entry.state = ScheduleState::Failed;
+                        entry.error = Some("The channel is no longer in the playlist".to_string());
+                        continue;
+                    };
+                    let title = format!("{} - {}", entry.channel_name, entry.title);
+                    match recorder.start(channel, Some(Duration::from_secs((stop - now) as u64)), Some(&title)) {
+                        Ok(recording_id) => {
+                            entry.state = ScheduleState::Recording;
+                            entry.recording_id = Some(recording_id);
+                        }
+                        Err(e) => {
+                            entry.state = ScheduleState::Failed;
+                            entry.error = Some(e);
+                        }
+                    }
+                }
+                ScheduleState::Recording => {
+                    let status = entry.recording_id.and_then(|id| recorder.status(id));
+                    match status {
+                        Some(status) if status.state == RecordingState::Recording => {}
+                        Some(status) if status.state == RecordingState::Failed => {
+                            entry.state = ScheduleState::Failed;
+                            entry.error = status.error;
+                            changed = true;
+                        }
+                        _ => {
+                            entry.state = ScheduleState::Done;
+                            changed = true;
+                        }
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        changed
+    }
+
+    /// Writes the current entries. Whoever saves last takes its copy last, so the file never
+    /// ends up older than the latest change.
+    async fn save(&self) {
+        let _saving = self.saving.lock().await;
+        let json = {
+            let entries = self.entries.lock().unwrap();
+            serde_json::to_string_pretty(&*entries).expect("Schedule entries always serialize")
+        };
+        // Write to a temporary file first so a crash never leaves a truncated schedule
+        let temp_path = self.path.with_extension("json.tmp");
+        let written = match tokio::fs::write(&temp_path, json).await {
+            Ok(()) => tokio::fs::rename(&temp_path, &self.path).await,
+            Err(e) => Err(e),
+        };
+        if let Err(e) = written {
+            warn!("Failed to save schedule to {}: {}", self.path.display(), e);
+        }
+    }
+}
+
+/// Descriptions of the pending entries and `manual` recordings overlapping `window`, if at
+/// some point within it `max_concurrent` of them would already be recording.
+fn conflicts(entries: &[ScheduledRecording], manual: &[RecordingStatus], window: (i64, i64), max_concurrent: usize) -> Vec<String> {
+    let mut overlapping: Vec<((i64, i64), String)> = entries.iter()
+        .filter(|e| e.state.is_pending())
+        .map(|e| (e.window(), format!("{} on {} at {}", e.title, e.channel_name, epg::format_time(e.start))))
+        .collect();
+    // Recordings started by hand run until their end time, or until stopped when they have none
+    overlapping.extend(manual.iter().map(|r| (
+        (r.started_at as i64, r.ends_at.map_or(i64::MAX, |t| t as i64)),
+        format!("{} (started by hand)", r.title),
+    )));
+    overlapping.retain(|((start, stop), _)| *start < window.1 && window.0 < *stop);
+
+    // The number of concurrent recordings only increases where one of them starts
+    let busiest = std::iter::once(window.0)
+        .chain(overlapping.iter().map(|((start, _), _)| *start).filter(|&t| t > window.0))
+        .map(|t| overlapping.iter().filter(|((start, stop), _)| *start <= t && t < *stop).count())
+        .max()
+        .unwrap_or(0);
+
+    if busiest >= max_concurrent { overlapping.into_iter().map(|(_, description)| description).collect() } else { Vec::new() }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(title: &str, start: i64, stop: i64) -> ScheduledRecording {
+        ScheduledRecording {
+            id: 1,
+            channel_id: "abc".to_string(),
+            channel_name: "News".to_string(),
+            title: title.to_string(),
+            start,
+            stop,
+            padding_before: 0,
+            padding_after: 0,
+            state: ScheduleState::Scheduled,
+            recording_id: None,
+            error: None,
+        }
+    }
+
+    fn manual(title: &str, started_at: u64, ends_at: Option<u64>) -> RecordingStatus {
+        RecordingStatus {
+            id: 7,
+            channel_id: "def".to_string(),
+            title: title.to_string(),
+            file_name: "manual.ts".to_string(),
+            started_at,
+            ends_at,
+            state: RecordingState::Recording,
+            error: None,
+            size_bytes: 0,
+        }
+    }
+
+    #[test]
+    fn only_overlaps_at_the_limit_conflict() {
+        let entries = [entry("Early", 0, 100), entry("Late", 100, 200)];
+        assert!(conflicts(&entries, &[], (50, 150), 2).is_empty());
+        assert_eq!(conflicts(&entries, &[], (50, 150), 1).len(), 2);
+        assert!(conflicts(&entries, &[], (200, 300), 1).is_empty());
+    }
+
+    #[test]
+    fn recordings_started_by_hand_count_against_the_limit() {
+        let entries = [entry("Early", 0, 100)];
+        let open_ended = [manual("Sports", 10, None)];
+        assert_eq!(conflicts(&entries, &open_ended, (50, 150), 2).len(), 2);
+        assert_eq!(conflicts(&[], &open_ended, (5000, 6000), 1), ["Sports (started by hand)"]);
+
+        let finishing = [manual("Sports", 10, Some(60))];
+        assert!(conflicts(&entries, &finishing, (60, 150), 2).is_empty());
+    }
+
+    #[tokio::test]
+    async fn schedule_is_saved_and_loaded_again() {
+        let dir = std::env::temp_dir().join(format!("m3u_browser_schedule_test_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("schedule.json");
+        let config = HashMap::from([("schedule_path".to_string(), path.display().to_string())]);
+        let scheduler = Scheduler::from_config(&config);
+        let recorder = Recorder::from_config(&HashMap::new());
+        let channel = Channel {
+            id: "abc".to_string(),
+            name: "News".to_string(),
+            url: "https://example.com/news.m3u8".to_string(),
+            icon_url: String::new(),
+            kind: crate::playlist::ChannelKind::Live,
+            tvg_id: Some("news.uk".to_string()),
+            headers: Vec::new(),
+        };
+        let start = epg::now() + 3600;
+        let programme = Programme { start, stop: start + 1800, title: "Evening".to_string(), description: None };
+
+        let entry = scheduler.schedule(&channel, &programme, &recorder).await.unwrap();
+        assert!(scheduler.schedule(&channel, &programme, &recorder).await.is_err());
+        let loaded = Scheduler::from_config(&config);
+        assert!(loaded.is_scheduled("abc", start));
+        assert_eq!(loaded.list()[0].window(), entry.window());
+
+        assert!(scheduler.cancel(entry.id, &recorder).await);
+        assert!(!scheduler.cancel(entry.id, &recorder).await);
+        assert!(Scheduler::from_config(&config).list().is_empty());
+        assert!(!dir.join("schedule.json.tmp").exists());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
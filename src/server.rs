@@ -0,0 +1,113 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+use rustls::pki_types::pem::PemObject;
+use rustls::pki_types::{CertificateDer, PrivateKeyDer};
+
+/// Where the web server listens and whether it serves HTTPS.
+pub struct ServerSettings {
+    pub bind_address: String,
+    pub port: u16,
+    /// Certificate chain and private key paths when TLS is enabled.
+    pub tls: Option<(String, String)>,
+}
+
+impl ServerSettings {
+    pub fn from_config(config: &HashMap<String, String>) -> Result<Self, String> {
+        let port = match config.get("port") {
+            Some(port) => port.parse().map_err(|_| format!("Invalid port: {}", port))?,
+            None => 8080,
+        };
+        let tls = match (config.get("tls_cert_path"), config.get("tls_key_path")) {
+            (Some(cert), Some(key)) => Some((cert.clone(), key.clone())),
+            (None, None) => None,
+            _ => return Err("tls_cert_path and tls_key_path must be set together".to_string()),
+        };
+        Ok(ServerSettings {
+            bind_address: config.get("bind_address").cloned().unwrap_or_else(|| "127.0.0.1".to_string()),
+            port,
+            tls,
+        })
+    }
+
+    /// Whether only this machine can connect.
+    pub fn is_loopback(&self) -> bool {
+        self.bind_address == "localhost"
+            || self.bind_address.parse::<std::net::IpAddr>().is_ok_and(|address| address.is_loopback())
+    }
+
+    /// The address to open in a browser, for the startup log.
+    pub fn url(&self) -> String {
+        let host = match self.bind_address.as_str() {
+            "0.0.0.0" | "::" => "localhost",
+            address => address,
+        };
+        let host = if host.contains(':') { format!("[{}]", host) } else { host.to_string() };
+        let scheme = if self.tls.is_some() { "https" } else { "http" };
+        format!("{}://{}:{}", scheme, host, self.port)
+    }
+
+    /// Loads the PEM certificate chain and key into a rustls configuration.
+    pub fn load_tls_config(&self) -> Result<Option<rustls::ServerConfig>, String> {
+        let Some((cert_path, key_path)) = &self.tls else { return Ok(None) };
+
+        let certs = CertificateDer::pem_file_iter(cert_path)
+            .and_then(|certs| certs.collect::<Result<Vec<_>, _>>())
+            .map_err(|e| format!("Failed to read certificate {}: {}", cert_path, e))?;
+        if certs.is_empty() {
+            return Err(format!("No certificate found in {}", cert_path));
+        }
+        let key = PrivateKeyDer::from_pem_file(key_path)
+            .map_err(|e| format!("Failed to read private key {}: {}", key_path, e))?;
+
+        rustls::ServerConfig::builder_with_provider(Arc::new(rustls::crypto::ring::default_provider()))
+            .with_safe_default_protocol_versions()
+            .map_err(|e| e.to_string())?
+            .with_no_client_auth()
+            .with_single_cert(certs, key)
+            .map(Some)
+            .map_err(|e| format!("Invalid certificate or key: {}", e))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn settings(values: &[(&str, &str)]) -> Result<ServerSettings, String> {
+        ServerSettings::from_config(&values.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect())
+    }
+
+    #[test]
+    fn defaults_to_http_on_localhost() {
+        let defaults = settings(&[]).unwrap();
+        assert_eq!((defaults.bind_address.as_str(), defaults.port), ("127.0.0.1", 8080));
+        assert!(defaults.is_loopback());
+        assert_eq!(defaults.url(), "http://127.0.0.1:8080");
+        assert!(defaults.load_tls_config().unwrap().is_none());
+    }
+
+    #[test]
+    fn addresses_and_urls() {
+        let everywhere = settings(&[("bind_address", "0.0.0.0"), ("port", "9000")]).unwrap();
+        assert!(!everywhere.is_loopback());
+        assert_eq!(everywhere.url(), "http://localhost:9000");
+        assert!(settings(&[("bind_address", "::1")]).unwrap().is_loopback());
+        assert_eq!(settings(&[("bind_address", "::1")]).unwrap().url(), "http://[::1]:8080");
+        assert!(settings(&[("bind_address", "localhost")]).unwrap().is_loopback());
+        assert!(!settings(&[("bind_address", "192.168.1.10")]).unwrap().is_loopback());
+
+        let https = settings(&[("tls_cert_path", "cert.pem"), ("tls_key_path", "key.pem")]).unwrap();
+        assert_eq!(https.url(), "https://127.0.0.1:8080");
+    }
+
+    #[test]
+    fn invalid_settings_are_errors() {
+        assert_eq!(settings(&[("port", "http")]).err().unwrap(), "Invalid port: http");
+        assert_eq!(settings(&[("port", "70000")]).err().unwrap(), "Invalid port: 70000");
+        assert!(settings(&[("tls_cert_path", "cert.pem")]).is_err());
+        assert!(settings(&[("tls_key_path", "key.pem")]).is_err());
+
+        let missing = settings(&[("tls_cert_path", "/nonexistent/cert.pem"), ("tls_key_path", "/nonexistent/key.pem")]).unwrap();
+        assert!(missing.load_tls_config().err().unwrap().starts_with("Failed to read certificate /nonexistent/cert.pem"));
+    }
+}
@@ -0,0 +1,387 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use serde::Serialize;
+use tokio::process::{Child, Command};
+use log::{info, warn};
+use crate::playlist::Channel;
+
+#[derive(Serialize, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum TranscodeMode {
+    /// Copy the audio and video as-is into HLS segments.
+    Remux,
+    /// Re-encode to H.264/AAC for browsers that cannot decode the source codecs.
+    Transcode,
+}
+
+impl TranscodeMode {
+    pub fn from_query(mode: Option<&str>) -> Self {
+        match mode {
+            Some("transcode") => TranscodeMode::Transcode,
+            _ => TranscodeMode::Remux,
+        }
+    }
+}
+
+struct Session {
+    channel_name: String,
+    mode: TranscodeMode,
+    dir: PathBuf,
+    child: Child,
+    started_at: Instant,
+    last_access: Instant,
+    exit_status: Option<String>,
+}
+
+#[derive(Serialize)]
+pub struct SessionStatus {
+    pub channel_id: String,
+    pub channel_name: String,
+    pub mode: TranscodeMode,
+    pub running_secs: u64,
+    pub idle_secs: u64,
+    pub exit_status: Option<String>,
+}
+
+/// Runs ffmpeg processes that turn channels into HLS segments in a temporary directory.
+pub struct TranscodeManager {
+    ffmpeg_path: Option<String>,
+    base_dir: PathBuf,
+    max_sessions: usize,
+    idle_timeout: Duration,
+    sessions: Mutex<HashMap<String, Session>>,
+}
+
+/// Segment and playlist names ffmpeg writes; anything else is rejected before touching the disk.
+pub fn is_valid_file_name(name: &str) -> bool {
+    !name.is_empty()
+        && !name.starts_with('.')
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '.' || c == '_' || c == '-')
+        && (name.ends_with(".m3u8") || name.ends_with(".ts"))
+}
+
+/// ffmpeg options that open `channel` with the headers from the playlist, ending with `-i <url>`.
+pub fn input_args(channel: &Channel) -> Vec<String> {
+    let mut args = Vec::new();
+    let mut headers = String::new();
+    for (name, value) in &channel.headers {
+        if name.eq_ignore_ascii_case("User-Agent") {
+            args.push("-user_agent".to_string());
+            args.push(value.clone());
+        } else {
+            headers.push_str(&format!("{}: {}\r\n", name, value));
+        }
+    }
+    if !headers.is_empty() {
+        args.push("-headers".to_string());
+        args.push(headers);
+    }
+    args.push("-i".to_string());
+    args.push(channel.url.clone());
+    args
+}
+
+impl TranscodeManager {
+    pub fn from_config(config: &HashMap<String, String>) -> Self {
+        TranscodeManager {
+            ffmpeg_path: config.get("ffmpeg_path").cloned(),
+            base_dir: config.get("transcode_dir").map(PathBuf::from)
+                .unwrap_or_else(|| std::env::temp_dir().join("m3u_browser_transcode")),
+            max_sessions: config.get("transcode_max_sessions").and_then(|v| v.parse().ok()).unwrap_or(2),
+            idle_timeout: Duration::from_secs(config.get("transcode_idle_secs").and_then(|v| v.parse().ok()).unwrap_or(60)),
+            sessions: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.ffmpeg_path.is_some()
+    }
+
+    pub fn idle_timeout(&self) -> Duration {
+        self.idle_timeout
+    }
+
+    /// Starts a session for `channel` unless one is already running in the same mode, and marks
+    /// it as in use. A session in the other mode is stopped first. Returns the directory the HLS
+    /// output is written to.
+    pub async fn start(&self, channel: &Channel, mode: TranscodeMode) -> Result<PathBuf, String> {
+        let ffmpeg_path = self.ffmpeg_path.as_ref().ok_or("ffmpeg path not specified in config")?;
+        let previous = {
+            let mut sessions = self.sessions.lock().unwrap();
+            match sessions.get_mut(&channel.id) {
+                Some(session) if session.mode == mode => {
+                    session.last_access = Instant::now();
+                    return Ok(session.dir.clone());
+                }
+                Some(_) => sessions.remove(&channel.id),
+                None => None,
+            }
+        };
+        if let Some(previous) = previous {
+            info!("Restarting the session for {} as {:?}", channel.name, mode);
+            shutdown(previous).await;
+        }
+
+        let mut sessions = self.sessions.lock().unwrap();
+        if let Some(session) = sessions.get_mut(&channel.id) {
+            // Started by another request while the previous session was stopping
+            if session.mode != mode {
+                return Err(format!("The session for {} is already switching to {:?}", channel.name, session.mode));
+            }
+            session.last_access = Instant::now();
+            return Ok(session.dir.clone());
+        }
+        if sessions.len() >= self.max_sessions {
+            return Err(format!("Too many concurrent transcodes (limit {})", self.max_sessions));
+        }
+
+        let dir = self.base_dir.join(&channel.id);
+        // A playlist left over from an earlier session would look like fresh output
+        if dir.exists() {
+            std::fs::remove_dir_all(&dir).map_err(|e| format!("Failed to clear {}: {}", dir.display(), e))?;
+        }
+        std::fs::create_dir_all(&dir).map_err(|e| format!("Failed to create {}: {}", dir.display(), e))?;
+
+        let mut command = Command::new(ffmpeg_path);
+        command.args(["-hide_banner", "-loglevel", "error", "-nostdin"]);
+        command.args(input_args(channel));
+        match mode {
+            TranscodeMode::Remux => {
+                command.args(["-c", "copy"]);
+            }
+            TranscodeMode::Transcode => {
+                command.args([
+                    "-c:v", "libx264", "-preset", "veryfast", "-tune", "zerolatency",
+                    "-c:a", "aac", "-b:a", "128k", "-ac", "2",
+                ]);
+            }
+        }
+        command
+            .args([
+                "-f", "hls",
+                "-hls_time", "4",
+                "-hls_list_size", "10",
+                "-hls_flags", "delete_segments+omit_endlist",
+                "-hls_segment_filename",
+            ])
+            .arg(dir.join("segment_%05d.ts"))
+            .arg(dir.join("index.m3u8"))
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .kill_on_drop(true);
+
+        let child = command.spawn().map_err(|e| format!("Failed to execute ffmpeg: {}", e))?;
+        info!("Started {:?} session for {}", mode, channel.name);
+
+        sessions.insert(channel.id.clone(), Session {
+            channel_name: channel.name.clone(),
+            mode,
+            dir: dir.clone(),
+            child,
+            started_at: Instant::now(),
+            last_access: Instant::now(),
+            exit_status: None,
+        });
+        Ok(dir)
+    }
+
+    /// Marks a session as in use and returns its output directory.
+    pub fn touch(&self, id: &str) -> Option<PathBuf> {
+        let mut sessions = self.sessions.lock().unwrap();
+        let session = sessions.get_mut(id)?;
+        session.last_access = Instant::now();
+        Some(session.dir.clone())
+    }
+
+    pub async fn stop(&self, id: &str) -> bool {
+        let session = self.sessions.lock().unwrap().remove(id);
+        match session {
+            Some(session) => {
+                shutdown(session).await;
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn statuses(&self) -> Vec<SessionStatus> {
+        let mut sessions = self.sessions.lock().unwrap();
+        let mut statuses: Vec<SessionStatus> = sessions.iter_mut().map(|(id, session)| {
+            refresh_exit_status(session);
+            SessionStatus {
+                channel_id: id.clone(),
+                channel_name: session.channel_name.clone(),
+                mode: session.mode,
+                running_secs: session.started_at.elapsed().as_secs(),
+                idle_secs: session.last_access.elapsed().as_secs(),
+                exit_status: session.exit_status.clone(),
+            }
+        }).collect();
+        statuses.sort_by_key(|s| s.running_secs);
+        statuses
+    }
+
+    /// Stops sessions nobody has requested a file from within the idle timeout, and ones whose ffmpeg died.
+    pub async fn reap(&self) {
+        let expired: Vec<Session> = {
+            let mut sessions = self.sessions.lock().unwrap();
+            let ids: Vec<String> = sessions.iter_mut()
+                .filter_map(|(id, session)| {
+                    refresh_exit_status(session);
+                    let idle = session.last_access.elapsed() > self.idle_timeout;
+                    let dead = session.exit_status.is_some() && session.last_access.elapsed() > Duration::from_secs(10);
+                    (idle || dead).then(|| id.clone())
+                })
+                .collect();
+            ids.iter().filter_map(|id| sessions.remove(id)).collect()
+        };
+        for session in expired {
+            info!("Reaping idle transcode session for {}", session.channel_name);
+            shutdown(session).await;
+        }
+    }
+}
+
+fn refresh_exit_status(session: &mut Session) {
+    if session.exit_status.is_none() {
+        if let Ok(Some(status)) = session.child.try_wait() {
+            session.exit_status = Some(status.to_string());
+        }
+    }
+}
+
+async fn shutdown(mut session: Session) {
+    let _ = session.child.start_kill();
+    let _ = session.child.wait().await;
+    if let Err(e) = tokio::fs::remove_dir_all(&session.dir).await {
+        warn!("Failed to remove {}: {}", session.dir.display(), e);
+    }
+}
+
+/// Waits for ffmpeg to write `file`, which takes a few seconds for the first playlist.
+pub async fn wait_for_file(path: &Path, timeout: Duration) -> bool {
+    let started = Instant::now();
+    while started.elapsed() < timeout {
+        if tokio::fs::metadata(path).await.is_ok() {
+            return true;
+        }
+        tokio::time::sleep(Duration::from_millis(250)).await;
+    }
+    false
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::playlist::ChannelKind;
+
+    fn channel(id: &str, url: &str, headers: Vec<(String, String)>) -> Channel {
+        Channel {
+            id: id.to_string(),
+            name: id.to_string(),
+            url: url.to_string(),
+            icon_url: String::new(),
+            kind: ChannelKind::Live,
+            tvg_id: None,
+            headers,
+        }
+    }
+
+    #[test]
+    fn input_args_put_headers_before_the_input() {
+        let with_headers = channel("a", "udp://239.0.0.1:1234", vec![
+            ("User-Agent".to_string(), "Player/1.0".to_string()),
+            ("Referer".to_string(), "http://example.com/".to_string()),
+            ("Origin".to_string(), "http://example.com".to_string()),
+        ]);
+        assert_eq!(input_args(&with_headers), [
+            "-user_agent", "Player/1.0",
+            "-headers", "Referer: http://example.com/\r\nOrigin: http://example.com\r\n",
+            "-i", "udp://239.0.0.1:1234",
+        ]);
+        let plain = channel("b", "rtmp://example.com/live", Vec::new());
+        assert_eq!(input_args(&plain), ["-i", "rtmp://example.com/live"]);
+    }
+
+    #[test]
+    fn only_playlist_and_segment_names_are_valid() {
+        assert!(is_valid_file_name("index.m3u8"));
+        assert!(is_valid_file_name("segment_00001.ts"));
+        assert!(!is_valid_file_name("../x.ts"));
+        assert!(!is_valid_file_name(".hidden.ts"));
+        assert!(!is_valid_file_name("x/y.ts"));
+        assert!(!is_valid_file_name("index.html"));
+        assert!(!is_valid_file_name(""));
+    }
+
+    /// Stands in for ffmpeg: waits a moment, then copies the `-i` file into one segment and
+    /// writes a playlist naming the mode.
+    #[cfg(unix)]
+    const FAKE_FFMPEG: &str = r#"#!/bin/sh
+while [ $# -gt 0 ]; do
+    case "$1" in
+        -i) input="$2"; shift ;;
+        -c) mode=remux ;;
+        -c:v) mode=transcode ;;
+    esac
+    playlist="$1"
+    shift
+done
+dir=$(dirname "$playlist")
+sleep 0.3
+cp "$input" "$dir/segment_00000.ts"
+printf '#EXTM3U\n#EXT-X-TARGETDURATION:4\n#EXTINF:4,\nsegment_00000.ts\n# %s\n' "$mode" > "$playlist"
+sleep 30
+"#;
+
+    #[cfg(unix)]
+    fn manager(base: &Path) -> TranscodeManager {
+        use std::os::unix::fs::PermissionsExt;
+        let ffmpeg = base.join("ffmpeg");
+        std::fs::write(&ffmpeg, FAKE_FFMPEG).unwrap();
+        std::fs::set_permissions(&ffmpeg, std::fs::Permissions::from_mode(0o755)).unwrap();
+        TranscodeManager::from_config(&HashMap::from([
+            ("ffmpeg_path".to_string(), ffmpeg.display().to_string()),
+            ("transcode_dir".to_string(), base.join("sessions").display().to_string()),
+        ]))
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn session_turns_a_local_file_into_hls_and_restarts_on_mode_change() {
+        let base = std::env::temp_dir().join(format!("m3u_browser_transcode_test_{}", std::process::id()));
+        std::fs::create_dir_all(&base).unwrap();
+        let input = base.join("input.ts");
+        std::fs::write(&input, b"test stream").unwrap();
+        let manager = manager(&base);
+        let channel = channel("local", &input.display().to_string(), Vec::new());
+
+        // A playlist from an earlier run must not be taken for the new session's output
+        let stale = base.join("sessions").join("local").join("index.m3u8");
+        std::fs::create_dir_all(stale.parent().unwrap()).unwrap();
+        std::fs::write(&stale, "#EXTM3U\n# stale\n").unwrap();
+
+        let dir = manager.start(&channel, TranscodeMode::Remux).await.unwrap();
+        assert!(!dir.join("index.m3u8").exists());
+        assert!(wait_for_file(&dir.join("index.m3u8"), Duration::from_secs(5)).await);
+        assert!(std::fs::read_to_string(dir.join("index.m3u8")).unwrap().contains("# remux"));
+        assert_eq!(std::fs::read(dir.join("segment_00000.ts")).unwrap(), b"test stream");
+        assert_eq!(manager.start(&channel, TranscodeMode::Remux).await.unwrap(), dir);
+        assert_eq!(manager.statuses().len(), 1);
+
+        let dir = manager.start(&channel, TranscodeMode::Transcode).await.unwrap();
+        assert!(wait_for_file(&dir.join("index.m3u8"), Duration::from_secs(5)).await);
+        assert!(std::fs::read_to_string(dir.join("index.m3u8")).unwrap().contains("# transcode"));
+        let statuses = manager.statuses();
+        assert_eq!(statuses.len(), 1);
+        assert_eq!(statuses[0].mode, TranscodeMode::Transcode);
+
+        assert!(manager.stop("local").await);
+        assert!(!dir.exists());
+        std::fs::remove_dir_all(&base).unwrap();
+    }
+}
@@ -0,0 +1,321 @@
+use std::collections::HashMap;
+use std::process::{Command, Stdio};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use serde_json::Value;
+use log::info;
+use crate::mpv_ipc::MpvStatus;
+use crate::players::PlayerRegistry;
+use crate::profiles::PlayerVars;
+
+/// VLC reports volume on a 0-512 scale where 256 is 100%.
+const VLC_FULL_VOLUME: f64 = 256.0;
+
+/// Drives a VLC instance through its HTTP interface (`--extraintf=http`), either one
+/// started by us or one already running on another machine.
+pub struct VlcRemote {
+    vlc_path: Option<String>,
+    enabled: bool,
+    host: String,
+    port: u16,
+    /// From `vlc_http_password`, or generated each time we start VLC when that is not set.
+    password: Mutex<Option<String>>,
+    password_configured: bool,
+    client: reqwest::Client,
+    current_channel: Mutex<Option<String>>,
+}
+
+/// A password for a VLC we start ourselves, so nobody on the network can guess it. VLC only
+/// takes it on the command line, where other users of this machine can still read it.
+fn random_password() -> String {
+    crate::random::random_hex(16)
+}
+
+impl VlcRemote {
+    pub fn from_config(config: &HashMap<String, String>) -> Self {
+        VlcRemote {
+            vlc_path: config.get("vlc_path").cloned(),
+            enabled: config.get("vlc_remote").map(String::as_str) == Some("true"),
+            host: config.get("vlc_http_host").cloned().unwrap_or_else(|| "127.0.0.1".to_string()),
+            port: config.get("vlc_http_port").and_then(|v| v.parse().ok()).unwrap_or(8090),
+            password: Mutex::new(config.get("vlc_http_password").cloned()),
+            password_configured: config.contains_key("vlc_http_password"),
+            client: reqwest::Client::builder()
+                .timeout(Duration::from_secs(5))
+                .build()
+                .expect("Failed to build HTTP client"),
+            current_channel: Mutex::new(None),
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.enabled
+    }
+
+    pub fn current_channel(&self) -> Option<String> {
+        self.current_channel.lock().unwrap().clone()
+    }
+
+    fn is_local(&self) -> bool {
+        matches!(self.host.as_str(), "127.0.0.1" | "localhost" | "::1")
+    }
+
+    /// Calls `status.json`, optionally with a command, and returns the player state.
+    pub async fn request(&self, params: &[(&str, &str)]) -> Result<Value, String> {
+        let password = self.password.lock().unwrap().clone().unwrap_or_default();
+        let response = self.client
+            .get(format!("http://{}:{}/requests/status.json", self.host, self.port))
+            .basic_auth("", Some(password))
+            .query(params)
+            .send()
+            .await
+            .map_err(|e| format!("VLC is not reachable: {}", e))?;
+        if response.status() == reqwest::StatusCode::UNAUTHORIZED {
+            return Err("VLC rejected the HTTP password".to_string());
+        }
+        let body = response.error_for_status()
+            .map_err(|e| e.to_string())?
+            .text()
+            .await
+            .map_err(|e| e.to_string())?;
+        serde_json::from_str(&body).map_err(|e| format!("Invalid VLC response: {}", e))
+    }
+
+    /// Starts a local VLC with the HTTP interface unless one already answers.
+    async fn ensure_running(&self, players: &PlayerRegistry) -> Result<(), String> {
+        if self.request(&[]).await.is_ok() {
+            return Ok(());
+        }
+        if !self.is_local() {
+            return Err(format!("VLC is not reachable at {}:{}", self.host, self.port));
+        }
+
+        let vlc_path = self.vlc_path.as_ref().ok_or("vlc path not specified in config")?;
+        let password = if self.password_configured {
+            self.password.lock().unwrap().clone().unwrap_or_default()
+        } else {
+            let password = random_password();
+            *self.password.lock().unwrap() = Some(password.clone());
+            password
+        };
+        let child = Command::new(vlc_path)
+            .arg("--extraintf=http")
+            .arg(format!("--http-host={}", self.host))
+            .arg(format!("--http-port={}", self.port))
+            .arg(format!("--http-password={}", password))
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn()
+            .map_err(|e| format!("Failed to execute vlc: {}", e))?;
+        info!("Started VLC with HTTP interface on port {}", self.port);
+        players.register("vlc (remote)", &format!("http://{}:{}/", self.host, self.port), child);
+
+        let started = Instant::now();
+        while started.elapsed() < Duration::from_secs(10) {
+            tokio::time::sleep(Duration::from_millis(300)).await;
+            if self.request(&[]).await.is_ok() {
+                return Ok(());
+            }
+        }
+        Err("VLC did not start its HTTP interface".to_string())
+    }
+
+    /// Replaces the current stream, or appends it to VLC's playlist when `enqueue` is set.
+    pub async fn play(
+        &self,
+        channel_id: Option<&str>,
+        vars: &PlayerVars<'_>,
+        enqueue: bool,
+        players: &PlayerRegistry,
+    ) -> Result<(), String> {
+        self.ensure_running(players).await?;
+
+        let mut options = Vec::new();
+        if !vars.title.is_empty() {
+            options.push(format!(":meta-title={}", vars.title));
+        }
+        if !vars.user_agent.is_empty() {
+            options.push(format!(":http-user-agent={}", vars.user_agent));
+        }
+        if !vars.referrer.is_empty() {
+            options.push(format!(":http-referrer={}", vars.referrer));
+        }
+
+        let command = if enqueue { "in_enqueue" } else { "in_play" };
+        let mut params = vec![("command", command), ("input", vars.url)];
+        params.extend(options.iter().map(|option| ("option", option.as_str())));
+        self.request(&params).await?;
+
+        if !enqueue {
+            *self.current_channel.lock().unwrap() = channel_id.map(str::to_string);
+        }
+        Ok(())
+    }
+
+    pub async fn command(&self, command: &str) -> Result<(), String> {
+        self.request(&[("command", command)]).await.map(|_| ())
+    }
+
+    /// Changes the volume by `percent` points.
+    pub async fn change_volume(&self, percent: i32) -> Result<(), String> {
+        let value = format!("{:+}", (percent as f64 * VLC_FULL_VOLUME / 100.0).round() as i32);
+        self.request(&[("command", "volume"), ("val", &value)]).await.map(|_| ())
+    }
+
+    pub async fn stop(&self) -> Result<(), String> {
+        self.command("pl_stop").await?;
+        *self.current_channel.lock().unwrap() = None;
+        Ok(())
+    }
+
+    /// Reports VLC's state in the same shape as the mpv remote, so both pages share their script.
+    pub async fn status(&self) -> MpvStatus {
+        match self.request(&[]).await {
+            Ok(status) => {
+                let meta = &status["information"]["category"]["meta"];
+                let title = meta["title"].as_str().or_else(|| meta["filename"].as_str()).map(str::to_string);
+                MpvStatus {
+                    running: true,
+                    title: if status["state"].as_str() == Some("stopped") { None } else { title },
+                    paused: status["state"].as_str().map(|state| state == "paused"),
+                    volume: status["volume"].as_f64().map(|v| v * 100.0 / VLC_FULL_VOLUME),
+                    channel_id: self.current_channel(),
+                }
+            }
+            Err(_) => MpvStatus {
+                running: false,
+                title: None,
+                paused: None,
+                volume: None,
+                channel_id: self.current_channel(),
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+
+    const PLAYING: &str = r#"{"state": "playing", "volume": 320, "information": {"category": {"meta": {"filename": "index.m3u8", "title": "News"}}}}"#;
+
+    /// Stands in for VLC's HTTP interface with the password `pw`, answering every request with
+    /// `status`. Returns the remote and the query pairs of the requests it got.
+    async fn stand_in(status: &'static str) -> (VlcRemote, Arc<Mutex<Vec<Vec<(String, String)>>>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let requests = Arc::new(Mutex::new(Vec::new()));
+        let recorded = requests.clone();
+        tokio::spawn(async move {
+            while let Ok((mut socket, _)) = listener.accept().await {
+                let mut request = [0u8; 4096];
+                let read = socket.read(&mut request).await.unwrap_or(0);
+                let request = String::from_utf8_lossy(&request[..read]).into_owned();
+                let target = request.split(' ').nth(1).unwrap_or("");
+                let url = reqwest::Url::parse(&format!("http://vlc{}", target)).unwrap();
+                recorded.lock().unwrap().push(url.query_pairs().map(|(k, v)| (k.into_owned(), v.into_owned())).collect());
+                // ":pw" in base64
+                let authorized = request.lines().any(|line| line.split_once(':').is_some_and(|(name, value)| {
+                    name.eq_ignore_ascii_case("authorization") && value.trim() == "Basic OnB3"
+                }));
+                let response = if authorized {
+                    format!("HTTP/1.1 200 OK\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}", status.len(), status)
+                } else {
+                    "HTTP/1.1 401 Unauthorized\r\nContent-Length: 0\r\nConnection: close\r\n\r\n".to_string()
+                };
+                let _ = socket.write_all(response.as_bytes()).await;
+            }
+        });
+        let remote = VlcRemote::from_config(&HashMap::from([
+            ("vlc_remote".to_string(), "true".to_string()),
+            ("vlc_http_port".to_string(), port.to_string()),
+            ("vlc_http_password".to_string(), "pw".to_string()),
+        ]));
+        (remote, requests)
+    }
+
+    fn pairs(values: &[(&str, &str)]) -> Vec<(String, String)> {
+        values.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect()
+    }
+
+    #[tokio::test]
+    async fn status_is_reported_like_mpv() {
+        let (remote, _) = stand_in(PLAYING).await;
+        let status = remote.status().await;
+        assert!(status.running);
+        assert_eq!(status.title.as_deref(), Some("News"));
+        assert_eq!(status.paused, Some(false));
+        assert_eq!(status.volume, Some(125.0));
+
+        let (remote, _) = stand_in(r#"{"state": "paused", "volume": 128, "information": {"category": {"meta": {"filename": "clip.ts"}}}}"#).await;
+        let status = remote.status().await;
+        assert_eq!(status.title.as_deref(), Some("clip.ts"));
+        assert_eq!(status.paused, Some(true));
+        assert_eq!(status.volume, Some(50.0));
+
+        let (remote, _) = stand_in(r#"{"state": "stopped", "volume": 0, "information": {"category": {"meta": {"title": "Old"}}}}"#).await;
+        assert_eq!(remote.status().await.title, None);
+
+        let unreachable = VlcRemote::from_config(&HashMap::from([("vlc_http_port".to_string(), "1".to_string())]));
+        let status = unreachable.status().await;
+        assert!(!status.running);
+        assert_eq!(status.volume, None);
+    }
+
+    #[tokio::test]
+    async fn play_sends_the_stream_with_its_options() {
+        let (remote, requests) = stand_in(PLAYING).await;
+        let players = PlayerRegistry::default();
+        let vars = PlayerVars {
+            url: "https://example.com/live.m3u8?a=1&b=2",
+            title: "News & Weather",
+            user_agent: "Player/1.0",
+            referrer: "",
+            headers: String::new(),
+        };
+
+        remote.play(Some("7"), &vars, false, &players).await.unwrap();
+        assert_eq!(remote.current_channel().as_deref(), Some("7"));
+        let last = requests.lock().unwrap().last().cloned().unwrap();
+        assert_eq!(last, pairs(&[
+            ("command", "in_play"),
+            ("input", "https://example.com/live.m3u8?a=1&b=2"),
+            ("option", ":meta-title=News & Weather"),
+            ("option", ":http-user-agent=Player/1.0"),
+        ]));
+
+        // Queued streams do not change what is playing
+        let bare = PlayerVars { url: "udp://239.0.0.1:1234", title: "", user_agent: "", referrer: "", headers: String::new() };
+        remote.play(Some("8"), &bare, true, &players).await.unwrap();
+        assert_eq!(remote.current_channel().as_deref(), Some("7"));
+        let last = requests.lock().unwrap().last().cloned().unwrap();
+        assert_eq!(last, pairs(&[("command", "in_enqueue"), ("input", "udp://239.0.0.1:1234")]));
+
+        remote.stop().await.unwrap();
+        assert_eq!(remote.current_channel(), None);
+        assert_eq!(requests.lock().unwrap().last().cloned().unwrap(), pairs(&[("command", "pl_stop")]));
+    }
+
+    #[tokio::test]
+    async fn volume_changes_are_scaled_to_vlc() {
+        let (remote, requests) = stand_in(PLAYING).await;
+        remote.change_volume(10).await.unwrap();
+        remote.change_volume(-5).await.unwrap();
+        let requests = requests.lock().unwrap();
+        assert_eq!(requests[0], pairs(&[("command", "volume"), ("val", "+26")]));
+        assert_eq!(requests[1], pairs(&[("command", "volume"), ("val", "-13")]));
+    }
+
+    #[tokio::test]
+    async fn wrong_passwords_are_reported() {
+        let (mut remote, _) = stand_in(PLAYING).await;
+        remote.password = Mutex::new(Some("guess".to_string()));
+        assert_eq!(remote.command("pl_pause").await.unwrap_err(), "VLC rejected the HTTP password");
+        assert_ne!(random_password(), random_password());
+        assert_eq!(random_password().len(), 32);
+    }
+}
@@ -0,0 +1,66 @@
+function showPlayerError(message) {
+    document.getElementById('playerError').textContent = message;
+}
+
+function playSource(video, src, type) {
+    if (type === 'hls') {
+        if (video.canPlayType('application/vnd.apple.mpegurl')) {
+            video.src = src;
+        } else if (window.Hls && Hls.isSupported()) {
+            const hls = new Hls();
+            hls.on(Hls.Events.ERROR, function(event, data) {
+                if (data.fatal) {
+                    showPlayerError('Playback failed: ' + data.details);
+                }
+            });
+            hls.loadSource(src);
+            hls.attachMedia(video);
+        } else {
+            showPlayerError('This browser cannot play HLS streams.');
+        }
+    } else if (type === 'mpegts') {
+        if (window.mpegts && mpegts.getFeatureList().mseLivePlayback) {
+            const player = mpegts.createPlayer({ type: 'mpegts', isLive: true, url: src });
+            player.on(mpegts.Events.ERROR, function(errorType, detail) {
+                showPlayerError('Playback failed: ' + errorType + ' ' + detail);
+            });
+            player.attachMediaElement(video);
+            player.load();
+            player.play();
+        } else {
+            video.src = src;
+        }
+    } else {
+        video.src = src;
+    }
+}
+
+function startPlayer() {
+    const video = document.getElementById('player');
+    const src = video.dataset.src;
+    const type = video.dataset.type;
+
+    video.addEventListener('error', function() {
+        showPlayerError('The browser cannot play this stream. Try an external player instead.');
+    });
+
+    // Server-side conversions are started by a POST first, so other sites cannot start ffmpeg
+    const start = video.dataset.start;
+    if (!start) {
+        playSource(video, src, type);
+        return;
+    }
+    fetch(start, { method: 'POST' })
+        .then(response => {
+            if (response.ok) {
+                playSource(video, src, type);
+            } else {
+                response.text().then(message => showPlayerError('Conversion failed: ' + message));
+            }
+        })
+        .catch(error => {
+            showPlayerError('Conversion failed: ' + error);
+        });
+}
+
+document.addEventListener("DOMContentLoaded", startPlayer);
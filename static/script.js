@@ -1,31 +1,8 @@
-function lazyLoadImages() {
-    var lazyloadImages = document.querySelectorAll("img.lazyload");    
-    var imageObserver = new IntersectionObserver(function(entries, observer) {
-        entries.forEach(function(entry) {
-            if (entry.isIntersecting) {
-                var image = entry.target;
-                fetch(image.dataset.src)
-                    .then(response => response.text())
-                    .then(imageUrl => {
-                        image.src = imageUrl;
-                        image.classList.remove("lazyload");
-                    })
-                    .catch(error => {
-                        console.error('Error:', error);
-                        image.src = '/static/placeholder.png';
-                    });
-                imageObserver.unobserve(image);
-            }
-        });
-    });
-
-    lazyloadImages.forEach(function(image) {
-        imageObserver.observe(image);
-    });
-}
-
 function setView(view) {
     const channelList = document.getElementById('channelList') || document.getElementById('searchResults');
+    if (!channelList) {
+        return;
+    }
     localStorage.setItem('viewPreference', view);
     if (view === 'grid') {
         channelList.classList.add('grid-view');
@@ -41,8 +18,16 @@ function loadViewPreference() {
     setView(view);
 }
 
-function playChannel(url, player) {
-    fetch('/play/' + player + '/' + encodeURIComponent(url))
+function playChannel(id, player, variant, enqueue) {
+    const params = new URLSearchParams();
+    if (variant != null) {
+        params.set('variant', variant);
+    }
+    if (enqueue) {
+        params.set('enqueue', 'true');
+    }
+    const query = params.toString() ? '?' + params.toString() : '';
+    fetch('/play/' + player + '/' + id + query, { method: 'POST' })
         .then(response => {
             if (!response.ok) {
                 console.error('Failed to play channel');
@@ -53,7 +38,233 @@ function playChannel(url, player) {
         });
 }
 
+function checkChannel(id) {
+    const badge = document.getElementById('health-' + id);
+    if (badge) {
+        badge.textContent = 'checking...';
+    }
+    fetch('/health/' + id, { method: 'POST' })
+        .then(response => response.json())
+        .then(record => {
+            if (badge) {
+                badge.textContent = record.status;
+                badge.className = 'badge ' + record.status;
+                badge.title = (record.error || ('HTTP ' + record.http_status)) + ', ' + record.latency_ms + ' ms';
+            }
+        })
+        .catch(error => {
+            console.error('Error:', error);
+        });
+}
+
+function probeChannel(id) {
+    const info = document.getElementById('media-' + id);
+    if (info) {
+        info.textContent = 'probing...';
+    }
+    fetch('/probe/' + id, { method: 'POST' })
+        .then(response => response.json())
+        .then(result => {
+            if (!info) {
+                return;
+            }
+            if (result.result === 'ok') {
+                const parts = [];
+                if (result.height) {
+                    parts.push(result.height + 'p');
+                }
+                if (result.video_codec) {
+                    parts.push(result.video_codec.toUpperCase());
+                }
+                parts.push(result.audio_codecs.join('/').toUpperCase());
+                parts.push(result.audio_languages.join(', '));
+                info.textContent = parts.filter(part => part).join(' ');
+                info.className = 'media-info';
+            } else {
+                info.textContent = result.error;
+                info.className = 'media-info failed';
+            }
+        })
+        .catch(error => {
+            console.error('Error:', error);
+        });
+}
+
+function stopTranscode(id) {
+    fetch('/transcodes/' + id + '/stop', { method: 'POST' })
+        .then(() => location.reload())
+        .catch(error => {
+            console.error('Error:', error);
+        });
+}
+
+function clearImageCache() {
+    if (!confirm('Remove all cached logos?')) {
+        return;
+    }
+    fetch('/images/clear', { method: 'POST' })
+        .then(() => location.reload())
+        .catch(error => {
+            console.error('Error:', error);
+        });
+}
+
+function recordChannel(button, id) {
+    const minutes = prompt('Record for how many minutes? Leave empty to record until stopped.', '60');
+    if (minutes === null) {
+        return;
+    }
+    const query = minutes.trim() === '' ? '' : '?minutes=' + encodeURIComponent(minutes.trim());
+    fetch('/record/' + id + query, { method: 'POST' })
+        .then(response => {
+            if (response.ok) {
+                button.textContent = 'Recording';
+                button.disabled = true;
+            } else {
+                response.text().then(text => alert('Failed to record: ' + text));
+            }
+        })
+        .catch(error => {
+            console.error('Error:', error);
+        });
+}
+
+function stopRecording(id) {
+    fetch('/recordings/' + id + '/stop', { method: 'POST' })
+        .then(() => setTimeout(() => location.reload(), 1000))
+        .catch(error => {
+            console.error('Error:', error);
+        });
+}
+
+function deleteRecording(id) {
+    if (!confirm('Delete this recording?')) {
+        return;
+    }
+    fetch('/recordings/' + id + '/delete', { method: 'POST' })
+        .then(() => location.reload())
+        .catch(error => {
+            console.error('Error:', error);
+        });
+}
+
+function scheduleProgramme(button, channelId, start) {
+    fetch('/schedule/' + channelId + '/' + start, { method: 'POST' })
+        .then(response => {
+            if (response.ok) {
+                button.textContent = 'Scheduled';
+                button.disabled = true;
+            } else {
+                response.text().then(text => alert('Failed to schedule: ' + text));
+            }
+        })
+        .catch(error => {
+            console.error('Error:', error);
+        });
+}
+
+function cancelScheduled(id) {
+    fetch('/schedule/' + id + '/cancel', { method: 'POST' })
+        .then(() => location.reload())
+        .catch(error => {
+            console.error('Error:', error);
+        });
+}
+
+function stopPlayer(id) {
+    fetch('/players/' + id + '/stop', { method: 'POST' })
+        .then(() => location.reload())
+        .catch(error => {
+            console.error('Error:', error);
+        });
+}
+
+function showRemoteStatus(status) {
+    const element = document.getElementById('remoteStatus');
+    if (!element) {
+        return;
+    }
+    if (!status.running) {
+        element.textContent = 'Not running';
+        return;
+    }
+    const parts = [status.title || 'Idle'];
+    if (status.paused) {
+        parts.push('paused');
+    }
+    if (status.volume !== null && status.volume !== undefined) {
+        parts.push('volume ' + Math.round(status.volume) + '%');
+    }
+    element.textContent = parts.join(' - ');
+}
+
+function refreshRemote(target) {
+    fetch('/' + target + '/status')
+        .then(response => response.json())
+        .then(showRemoteStatus)
+        .catch(error => {
+            console.error('Error:', error);
+        });
+}
+
+function remoteAction(target, action) {
+    fetch('/' + target + '/' + action, { method: 'POST' })
+        .then(response => {
+            if (!response.ok) {
+                response.text().then(text => console.error('Remote action failed:', text));
+            }
+            refreshRemote(target);
+        })
+        .catch(error => {
+            console.error('Error:', error);
+        });
+}
+
+function loadRenderers() {
+    const selects = document.querySelectorAll('.renderer-select');
+    if (selects.length === 0) {
+        return;
+    }
+    fetch('/renderers')
+        .then(response => response.json())
+        .then(renderers => {
+            selects.forEach(select => {
+                select.innerHTML = '';
+                renderers.forEach(renderer => {
+                    const option = document.createElement('option');
+                    option.value = renderer.id;
+                    option.textContent = renderer.name + ' (' + renderer.kind + ')';
+                    select.appendChild(option);
+                });
+            });
+        })
+        .catch(error => console.error('Error:', error));
+}
+
+function castChannel(button, id, variant) {
+    const renderer = button.previousElementSibling.value;
+    if (!renderer) {
+        alert('No renderer found');
+        return;
+    }
+    const query = variant == null ? '' : '?variant=' + variant;
+    fetch('/cast/' + encodeURIComponent(renderer) + '/' + id + query, { method: 'POST' })
+        .then(response => {
+            if (!response.ok) {
+                response.text().then(text => alert('Failed to play: ' + text));
+            }
+        })
+        .catch(error => {
+            console.error('Error:', error);
+        });
+}
+
 document.addEventListener("DOMContentLoaded", function() {
-    lazyLoadImages();
     loadViewPreference();
+    loadRenderers();
+    if (document.getElementById('remoteStatus')) {
+        const target = location.pathname.split('/')[1];
+        refreshRemote(target);
+        setInterval(() => refreshRemote(target), 5000);
+    }
 });